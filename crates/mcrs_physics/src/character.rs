@@ -1,7 +1,7 @@
 use std::time::Duration;
 
 use bevy::prelude::*;
-use mcrs_universe::universe::Universe;
+use mcrs_universe::{block::BlockMaterials, universe::Universe, Blueprints};
 
 use crate::{raycast::*, test_print, MARGIN_EPSILON};
 
@@ -86,6 +86,11 @@ impl Default for CameraController {
 
 /// Checks if a rigidbody is very close to a block below
 pub fn is_grounded(rigidbody: &Rigidbody, tr: &Transform, universe: &Universe) -> bool {
+    get_ground(rigidbody, tr, universe).is_some()
+}
+
+/// Position of the block the `Rigidbody` is standing on.
+pub fn get_ground(rigidbody: &Rigidbody, tr: &Transform, universe: &Universe) -> Option<IVec3> {
     cast_cuboid(
         RayFinite {
             position: tr.translation,
@@ -95,7 +100,8 @@ pub fn is_grounded(rigidbody: &Rigidbody, tr: &Transform, universe: &Universe) -
         rigidbody.size,
         &universe,
     )
-    .is_some_and(|hit| hit.distance <= MARGIN_EPSILON * 2.0)
+    .filter(|hit| hit.distance <= MARGIN_EPSILON * 2.0)
+    .map(|hit| hit.grid_pos)
 }
 
 // Todo: add a system that moves `Rigidbody`s that are not `Character`s
@@ -112,6 +118,7 @@ pub fn character_controller_movement(
         &Friction,
     )>,
     universe: Res<Universe>,
+    bp: Res<Blueprints>,
     time: Res<Time<Fixed>>,
 ) {
     for (mut controller, mut tr, mut vel, character, rigidbody, friction) in
@@ -131,6 +138,7 @@ pub fn character_controller_movement(
             &rigidbody,
             &friction,
             &universe,
+            &bp.materials,
             time.delta(),
         );
     }
@@ -145,6 +153,7 @@ pub fn character_controller_step(
     rigidbody: &Rigidbody,
     friction: &Friction,
     universe: &Universe,
+    materials: &BlockMaterials,
     dt: Duration,
 ) {
     if !controller.is_active {
@@ -155,14 +164,18 @@ pub fn character_controller_step(
     controller.jump_timer.tick(dt);
 
     let acc = controller.acceleration.x * tr.forward() + controller.acceleration.z * tr.left();
-    if is_grounded(rigidbody, &tr, &universe) {
+    if let Some(ground) = get_ground(rigidbody, &tr, &universe) {
         if controller.jumping && controller.jump_timer.finished() {
             vel.vel.y = character.jump_strenght;
             controller.jump_timer.set_duration(character.jump_cooldown);
             controller.jump_timer.reset();
         }
         vel.vel += acc * Vec3::new(1.0, 0.0, 1.0) * character.ground_speed;
-        vel.vel *= friction.ground;
+        // slippery blocks have a friction below 1, scaling the speed lost per tick
+        let block_friction = universe
+            .read_chunk_block(&ground)
+            .map_or(1.0, |block| materials.get(block.id).friction);
+        vel.vel *= Vec3::ONE - (Vec3::ONE - friction.ground) * block_friction;
     } else {
         vel.vel += acc * Vec3::new(1.0, 0.0, 1.0) * character.air_speed;
         vel.vel *= friction.air;
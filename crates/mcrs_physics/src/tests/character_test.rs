@@ -8,18 +8,19 @@ use crate::{
     MARGIN_EPSILON,
 };
 use bevy::prelude::*;
-use mcrs_universe::universe::Universe;
+use mcrs_universe::{block::BlockMaterials, universe::Universe};
 use std::{
     f32::consts::{FRAC_PI_4, PI},
     time::Duration,
 };
 
-#[derive(Clone, Debug, Default)]
+#[derive(Debug, Default)]
 struct Context {
     character: Character,
     rigidbody: Rigidbody,
     friction: Friction,
     universe: Universe,
+    materials: BlockMaterials,
     dt: Duration,
 }
 
@@ -42,6 +43,7 @@ impl Context {
                 ground: Vec3::splat(0.78),
             },
             universe: universe_single_block(),
+            materials: BlockMaterials::default(),
             dt: Duration::from_millis(20),
         }
     }
@@ -60,9 +62,7 @@ impl ToString for CharacterState {
     }
 }
 
-fn step_cube_character(state: &CharacterState, opt: Option<Context>) -> CharacterState {
-    let context = opt.unwrap_or(Context::new());
-
+fn step_cube_character(state: &CharacterState, context: &Context) -> CharacterState {
     // Return a mutated copy
     let mut state = state.clone();
 
@@ -74,6 +74,7 @@ fn step_cube_character(state: &CharacterState, opt: Option<Context>) -> Characte
         &context.rigidbody,
         &context.friction,
         &context.universe,
+        &context.materials,
         context.dt,
     );
 
@@ -91,7 +92,7 @@ fn gravity() {
         "grounded"
     );
 
-    let stepped = step_cube_character(&state, Some(context.clone()));
+    let stepped = step_cube_character(&state, &context);
 
     assert!(
         !is_grounded(&context.rigidbody, &state.tr, &context.universe),
@@ -120,7 +121,7 @@ fn grounded() {
         "not grounded"
     );
 
-    let stepped = step_cube_character(&state, Some(context.clone()));
+    let stepped = step_cube_character(&state, &context);
 
     dbg!(&state, &stepped);
     assert!(
@@ -142,7 +143,7 @@ fn jump_while_falling() {
     let mut state = CharacterState::default();
     state.tr.translation = Vec3::splat(0.5) + Vec3::Y * 2.0;
     state.controller.jumping = true;
-    let stepped = step_cube_character(&state, None);
+    let stepped = step_cube_character(&state, &Context::new());
     dbg!(&state, &stepped);
     assert!(
         state.vel.vel.y > stepped.vel.vel.y,
@@ -171,7 +172,7 @@ fn jump_once() {
             state.controller.jumping = false;
         }
 
-        let stepped = step_cube_character(&iterated, Some(context.clone()));
+        let stepped = step_cube_character(&iterated, &context);
         iterated = stepped;
         iter += 1;
     }
@@ -210,7 +211,7 @@ fn grounded_then_fall() {
             panic!("not grounded while on the ground");
         }
 
-        iterated = step_cube_character(&iterated, Some(context.clone()));
+        iterated = step_cube_character(&iterated, &context);
         iter += 1;
     }
 
@@ -257,7 +258,7 @@ fn bonk_into_wall() {
                 panic!("no longer grounded");
             }
 
-            let stepped = step_cube_character(&iterated, Some(context.clone()));
+            let stepped = step_cube_character(&iterated, &context);
 
             let traveled = (stepped.tr.translation - state.tr.translation).length();
             if iter > 1 && stepped.vel.vel.length_squared() < EPS {
@@ -336,7 +337,7 @@ fn bonk_into_wall_and_jump() {
                 panic!("no longer grounded");
             }
 
-            let mut stepped = step_cube_character(&iterated, Some(context.clone()));
+            let mut stepped = step_cube_character(&iterated, &context);
 
             if iter > 1 && stepped.vel.vel.length_squared() < EPS {
                 bonked = true;
@@ -443,7 +444,7 @@ fn bonk_into_corner(start_jumping_after_bonk: bool) {
                 panic!("falling to -Y");
             }
 
-            let mut stepped = step_cube_character(&iterated, Some(context.clone()));
+            let mut stepped = step_cube_character(&iterated, &context);
 
             if iter > 1 && stepped.vel.vel.length_squared() < EPS {
                 println!("BONK");
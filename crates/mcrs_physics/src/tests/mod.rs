@@ -13,8 +13,9 @@ use mcrs_universe::{
 };
 
 pub fn universe_single_block() -> Universe {
-    let mut universe = Universe {
+    let universe = Universe {
         chunks: [(IVec3::ZERO, Chunk::empty())].into_iter().collect(),
+        ..Default::default()
     };
     universe.set_chunk_block(&IVec3::ZERO, stone());
     universe
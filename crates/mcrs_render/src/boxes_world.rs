@@ -83,8 +83,14 @@ impl Plugin for BoxesWorldPlugin {
         app.insert_resource(LoadedVoxTextures::default())
             .insert_resource(VoxTextureLoadQueue::default())
             .add_plugins(ExtractResourcePlugin::<LoadedVoxTextures>::default())
-            .add_systems(Startup, read_info_textures)
-            .add_systems(Update, load_vox_textures);
+            .add_systems(
+                Update,
+                (
+                    read_info_textures.run_if(resource_changed::<Blueprints>),
+                    load_vox_textures,
+                )
+                    .chain(),
+            );
 
         app.sub_app_mut(RenderApp)
             .insert_resource(ExtractedTexturedBoxes::default())
@@ -107,6 +113,10 @@ fn read_info_textures(
     mut loaded: ResMut<LoadedVoxTextures>,
     info: Res<Blueprints>,
 ) {
+    // start over, the blueprints may have been reloaded
+    *loaded = LoadedVoxTextures::default();
+    queue.to_load.clear();
+
     let mut max_id = 0;
     for block_info in info.blocks.iter() {
         let vox_texture_index = VoxTextureIndex(*block_info.id as u32);
@@ -288,17 +298,11 @@ fn write_boxes(
     render_queue.write_buffer(&boxes_data.boxes_buffer, 0, &bytes);
 }
 
-const MAX_VOX_TEXTURE_STORAGE: u32 = 1024;
-
 fn write_vox_textures(
     render_queue: Res<RenderQueue>,
     boxes_data: Res<BoxesData>,
     loaded: ResMut<LoadedVoxTextures>,
 ) {
-    let mut bytes: Vec<u8> = vec![];
-    let offsets = [0; (MAX_VOX_TEXTURE_STORAGE * 4) as usize];
-    bytes.extend(offsets);
-
     let mut textures: Vec<(VoxTextureIndex, BlockTexturePtr)> = loaded
         .textures
         .iter()
@@ -306,8 +310,14 @@ fn write_vox_textures(
         .collect();
     textures.sort_by(|a, b| a.0.cmp(&b.0));
 
-    let mut texture_offset: u32 = 0;
-    for (i, (_id, vox_texture)) in textures.iter().enumerate() {
+    // the offset table has a slot up to the highest index, the textures follow it
+    let table_len = textures.last().map_or(0, |(index, _)| index.0 + 1);
+    let mut bytes: Vec<u8> = vec![0; table_len as usize * 4];
+
+    let mut texture_offset: u32 = table_len;
+    for (index, vox_texture) in textures.iter() {
+        // the shader looks up the offset using the block id
+        let i = index.0 as usize;
         let vox = vox_texture.0.read().unwrap().to_bytes_vec();
         let len = vox.len();
         bytes.extend(vox);
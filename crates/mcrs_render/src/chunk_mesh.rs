@@ -11,8 +11,9 @@ use block_mesh::{
 };
 use mcrs_physics::intersect::get_chunks_in_sphere;
 use mcrs_universe::{
-    block::{BlockFace, BlockFlag},
+    block::{BlockFace, BlockFlag, LightType},
     chunk::{Chunk, ChunkVersion},
+    journal::{ChangeKind, JournalRead, JournalReader},
     universe::Universe,
     Blueprints, CHUNK_SIDE, MAX_LIGHT,
 };
@@ -29,10 +30,16 @@ pub struct ChunkEntities {
 pub struct ChunkEntity {
     pub entity: Entity,
     pub version: ChunkVersion,
+    /// What changed since the mesh was built, as told by the journal.
+    pub changes: Option<ChangeKind>,
 }
 impl ChunkEntity {
     fn new(entity: Entity, version: ChunkVersion) -> Self {
-        Self { entity, version }
+        Self {
+            entity,
+            version,
+            changes: None,
+        }
     }
 }
 
@@ -57,6 +64,7 @@ fn adjacent() -> impl Iterator<Item = IVec3> {
 
 /// Creates a mesh for every chunk that is in universe
 /// When a chunk is modified, a new mesh is created
+/// When only its light changed, the mesh is rebuilt in place
 pub fn sync_chunk_meshes(
     mut commands: Commands,
     mut chunk_entities: ResMut<ChunkEntities>,
@@ -67,7 +75,29 @@ pub fn sync_chunk_meshes(
     handles: Res<TextureHandles>,
     camera_query: Query<(&Camera, &GlobalTransform)>,
     render_settings: Res<RenderSettings>,
+    mesh_query: Query<&Mesh3d>,
+    mut journal_reader: Local<JournalReader>,
 ) {
+    match universe.journal.read(&mut journal_reader) {
+        JournalRead::Changes(changes) => {
+            for (chunk_pos, chunk_changes) in changes.iter() {
+                if let Some(chunk_entity) = chunk_entities.map.get_mut(chunk_pos) {
+                    let kind = if chunk_changes.is_light_only() {
+                        ChangeKind::Light
+                    } else {
+                        ChangeKind::Block
+                    };
+                    chunk_entity.changes = chunk_entity.changes.max(Some(kind));
+                }
+            }
+        }
+        JournalRead::Lost => {
+            for chunk_entity in chunk_entities.map.values_mut() {
+                chunk_entity.changes = Some(ChangeKind::Block);
+            }
+        }
+    }
+
     let Some((_, camera_tr)) = camera_query.iter().next() else {
         return;
     };
@@ -82,16 +112,23 @@ pub fn sync_chunk_meshes(
     // For each chunk that is in universe, check that it is instanced
     let mut to_remove = vec![];
     let mut to_add = vec![];
+    let mut to_relight = vec![];
     for chunk_pos in chunks_in_view.iter() {
         let Some(chunk) = universe.chunks.get(chunk_pos) else {
             continue;
         };
 
         if let Some(chunk_entity) = chunk_entities.map.get(chunk_pos) {
-            if chunk_entity.version != chunk.version {
+            if chunk_entity.version != chunk.version() {
+                if chunk_entity.changes == Some(ChangeKind::Light) {
+                    to_relight.push(*chunk_pos);
+                    continue;
+                }
                 info!(
                     "despawned chunk mesh at {}, obsolete (mesh: {:?}, chunk: {:?})",
-                    chunk_pos, chunk_entity.version, chunk.version
+                    chunk_pos,
+                    chunk_entity.version,
+                    chunk.version()
                 );
                 commands.entity(chunk_entity.entity).despawn_recursive();
                 to_remove.push(chunk_pos.clone());
@@ -119,7 +156,7 @@ pub fn sync_chunk_meshes(
             &handles,
         );
 
-        to_add.push((chunk_pos, entity, chunk.version.clone()));
+        to_add.push((chunk_pos, entity, chunk.version()));
         remeshed_chunks += 1;
         if remeshed_chunks >= MAX_CHUNK_REMESH_PER_FRAME {
             break;
@@ -128,6 +165,27 @@ pub fn sync_chunk_meshes(
     for key in &to_remove {
         chunk_entities.map.remove(key);
     }
+    // only the light changed, the whole mesh is generated again but in the mesh asset of
+    // the chunk entity, which is kept instead of being despawned and spawned again
+    for chunk_pos in to_relight {
+        if remeshed_chunks >= MAX_CHUNK_REMESH_PER_FRAME {
+            break;
+        }
+        let (Some(chunk), Some(chunk_entity)) = (
+            universe.chunks.get(&chunk_pos),
+            chunk_entities.map.get_mut(&chunk_pos),
+        ) else {
+            continue;
+        };
+        if let Ok(mesh) = mesh_query.get(chunk_entity.entity) {
+            if let Some(raw_mesh) = generate_chunk_mesh(&chunk_pos, &bp, &universe) {
+                meshes.insert(&mesh.0, build_render_mesh(raw_mesh));
+            }
+        }
+        chunk_entity.version = chunk.version();
+        chunk_entity.changes = None;
+        remeshed_chunks += 1;
+    }
     for (chunk_pos, entity, version) in to_add {
         chunk_entities
             .map
@@ -196,32 +254,10 @@ pub fn create_chunk_entity(
     handles: &TextureHandles,
 ) -> Entity {
     let mut entity_commands = commands.spawn((Transform::from_translation(chunk_pos.as_vec3()),));
-    if let Some(mut raw_mesh) = generate_chunk_mesh(chunk_pos, &bp, &universe) {
+    if let Some(raw_mesh) = generate_chunk_mesh(chunk_pos, &bp, &universe) {
         info!("spawned chunk mesh at {}", chunk_pos);
-        let mut indices = Vec::with_capacity(raw_mesh.indices.len() / 3);
-        for i in 0..raw_mesh.indices.len() / 3 {
-            indices.push([
-                raw_mesh.indices[i * 3],
-                raw_mesh.indices[i * 3 + 1],
-                raw_mesh.indices[i * 3 + 2],
-            ])
-        }
-        let mut render_mesh = Mesh::new(
-            PrimitiveTopology::TriangleList,
-            RenderAssetUsages::RENDER_WORLD,
-        );
-        for uv in raw_mesh.uvs.iter_mut() {
-            for c in uv.iter_mut() {
-                *c *= UV_SCALE;
-            }
-        }
-        render_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, raw_mesh.vertices);
-        render_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, raw_mesh.normals);
-        render_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, raw_mesh.uvs);
-        render_mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, raw_mesh.colors);
-        render_mesh.insert_indices(Indices::U32(raw_mesh.indices));
         entity_commands.insert((
-            Mesh3d(meshes.add(render_mesh)),
+            Mesh3d(meshes.add(build_render_mesh(raw_mesh))),
             MeshMaterial3d(materials.add(StandardMaterial {
                 unlit: true,
                 base_color_texture: Some(handles.blocks.clone_weak()),
@@ -234,6 +270,24 @@ pub fn create_chunk_entity(
     entity_commands.id()
 }
 
+pub fn build_render_mesh(mut raw_mesh: ChunkMesh) -> Mesh {
+    let mut render_mesh = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    for uv in raw_mesh.uvs.iter_mut() {
+        for c in uv.iter_mut() {
+            *c *= UV_SCALE;
+        }
+    }
+    render_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, raw_mesh.vertices);
+    render_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, raw_mesh.normals);
+    render_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, raw_mesh.uvs);
+    render_mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, raw_mesh.colors);
+    render_mesh.insert_indices(Indices::U32(raw_mesh.indices));
+    render_mesh
+}
+
 pub fn generate_chunk_mesh(
     chunk_pos: &IVec3,
     bp: &Blueprints,
@@ -309,7 +363,7 @@ pub fn generate_chunk_mesh(
                 quad.minimum[2] as i32 - 1,
             ]);
             let block = chunk_ref[Chunk::xyz2idx(block_xyz)];
-            let block_bp = bp.blocks.get(&block.id);
+            let block_bp = bp.blocks.get(&block.id).unwrap_or_else(|_| bp.air());
 
             let mut face_color = Color::WHITE;
 
@@ -324,11 +378,17 @@ pub fn generate_chunk_mesh(
                         forward,
                         backward,
                     } => {
-                        let n = IVec3::from_array(face.signed_normal().to_array());
+                        let mut n = IVec3::from_array(face.signed_normal().to_array());
+                        // blocks lying on their side show the top texture along their axis
+                        match block_bp.get_state_value(block.state, "axis") {
+                            Some("x") => n = IVec3::new(n.y, n.x, n.z),
+                            Some("z") => n = IVec3::new(n.x, n.z, n.y),
+                            _ => {}
+                        }
                         if n == IVec3::Y {
                             // Hack: biomes aren't implemented yet.
                             // Set the grass color to green instead of white
-                            if block_bp.name == "Grass" {
+                            if bp.blocks.id_named_checked("Grass") == Some(&block_bp.id) {
                                 face_color = Color::srgb(0.7, 1.0, 0.4);
                             }
                             [top.0 as f32, top.1 as f32]
@@ -363,7 +423,10 @@ pub fn generate_chunk_mesh(
             let block_normal = IVec3::from_array(face.signed_normal().to_array());
             let looking_at = block_xyz + block_normal;
             let light = if let Some(block) = universe.read_chunk_block(&(looking_at + chunk_pos)) {
-                block.light0.max(block.light1) as f32 / MAX_LIGHT as f32
+                block
+                    .get_light(LightType::Torch)
+                    .max(block.get_light(LightType::Sun)) as f32
+                    / MAX_LIGHT as f32
             } else {
                 0.0
             };
@@ -46,9 +46,9 @@ struct Box {
     _padding3: u32,
 }
 
+// Starts with the offset of each texture, by index, followed by the textures
 struct VoxTextureStorage {
-    offsets: array<u32, 1024>,
-    textures: array<u32>,
+    data: array<u32>,
 }
 
 fn get_at_f(pos: vec3<f32>) -> u32 {
@@ -72,17 +72,22 @@ fn get_at(grid: vec3<i32>) -> u32 {
     }
 }
 
+// block layout: id (16 bits), flags (8 bits), torch light (4 bits), sun light (4 bits)
+fn block_id(data: u32) -> u32 {
+    return data & 0xFFFFu;
+}
+
 fn is_solid(data: u32) -> bool {
-    return (data & 0xFFu) != 0u;
+    return block_id(data) != 0u;
 }
 
 fn is_opaque(data: u32) -> bool {
-    return ((data >> 8u) & 1u) > 0u;
+    return ((data >> 16u) & 1u) > 0u;
 }
 
 fn light(data: u32) -> u32 {
-    let torch_light = (data >> 16u) & 0xFFu;
-    let sun_light = (data >> 24u) & 0xFFu;
+    let torch_light = (data >> 24u) & 0xFu;
+    let sun_light = (data >> 28u) & 0xFu;
     // sun_light to be later modulated by time_of_day
     return max(torch_light, sun_light);
 }
@@ -179,7 +184,7 @@ fn shoot_ray(inray: Ray, flags: u32) -> HitInfo {
                 let norm_box = -ray_step * mask;
                 ray_box.pos = ray_box.pos + norm_box * 0.00001;
                 ray_box.dir = ray.dir;
-                voxhit = shoot_ray_vox(ray_box, voxel.data & 0xFFu);
+                voxhit = shoot_ray_vox(ray_box, block_id(voxel.data));
                 if voxhit.hit {
                     break;
                 }
@@ -230,11 +235,11 @@ struct HitInfoVox {
 }
 
 fn shoot_ray_vox(inray: Ray, vox_index: u32) -> HitInfoVox {
-    let vox_offset = vox_textures.offsets[vox_index];
+    let vox_offset = vox_textures.data[vox_index];
     let vox_size = vec3u(
-        vox_textures.textures[vox_offset],
-        vox_textures.textures[vox_offset + 1u],
-        vox_textures.textures[vox_offset + 2u]
+        vox_textures.data[vox_offset],
+        vox_textures.data[vox_offset + 1u],
+        vox_textures.data[vox_offset + 2u]
     );
     let vox_size_f = vec3f(vox_size);
     let vox_offset_voxels = vox_offset + 4u + 256u;
@@ -262,14 +267,14 @@ fn shoot_ray_vox(inray: Ray, vox_index: u32) -> HitInfoVox {
             return HitInfoVox(false, 0u, 50u, 999999.0);
         }
         let voxel_i = u32(map_pos.x) * (vox_size.y * vox_size.z) + u32(map_pos.y) * vox_size.z + u32(map_pos.z);
-        let data = vox_textures.textures[vox_offset_voxels + voxel_i];
+        let data = vox_textures.data[vox_offset_voxels + voxel_i];
         if (data & 0xFFu) != 0u {
             hit = true;
             color_index = data & 0xFFu;
             break;
         }
     }
-    let color = vox_textures.textures[vox_offset + 4u + color_index];
+    let color = vox_textures.data[vox_offset + 4u + color_index];
     let end_ray_pos = ray.dir / dot(mask * ray.dir, vec3f(1.0)) * dot(mask * (map_pos + step(ray.dir, vec3f(0.0)) - ray.pos), vec3f(1.0)) + ray.pos;
 
     var hit_info: HitInfoVox;
@@ -23,6 +23,7 @@ use bevy::{
         UiPassNode,
     },
 };
+use mcrs_universe::journal::advance_change_journal;
 
 pub struct McrsVoxelRenderPlugin;
 
@@ -89,7 +90,8 @@ impl Plugin for McrsVoxelRenderPlugin {
                 brightness: 4000.0,
                 ..default()
             });
-            app.add_systems(FixedUpdate, sync_chunk_meshes);
+            // after the journal is advanced, so the changes of this tick can be read
+            app.add_systems(FixedLast, sync_chunk_meshes.after(advance_change_journal));
         }
 
         let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
@@ -12,9 +12,10 @@ use bevy::{
         Render, RenderApp, RenderSet,
     },
 };
-use mcrs_universe::chunk::Chunk;
+use mcrs_universe::chunk::{Chunk, ChunkVersion};
 use mcrs_universe::universe::Universe;
 use mcrs_universe::{CHUNK_SIDE, CHUNK_VOLUME};
+use std::sync::Arc;
 
 pub struct VoxelWorldPlugin;
 
@@ -149,11 +150,13 @@ pub struct ChunkTracking {
     added: Vec<IVec3>,
     modified: Vec<IVec3>,
     removed: Vec<IVec3>,
+    /// Versions of the extracted chunks, the render world shares the chunks of the main world.
+    versions: HashMap<IVec3, ChunkVersion>,
 }
 
 pub fn extract_universe(
     main_world: Res<MainWorld>,
-    mut render_universe: ResMut<Universe>,
+    render_universe: Res<Universe>,
     mut chunk_tracking: ResMut<ChunkTracking>,
 ) {
     let Some(universe) = main_world.get_resource::<Universe>() else {
@@ -165,22 +168,22 @@ pub fn extract_universe(
     chunk_tracking.removed.clear();
 
     for (chunk_pos, main_chunk) in universe.chunks.iter() {
-        if let Some(render_chunk) = render_universe.chunks.get(chunk_pos) {
-            if render_chunk.version == main_chunk.version {
+        let version = main_chunk.version();
+        if let Some(render_version) = chunk_tracking.versions.get(&chunk_pos) {
+            if *render_version == version {
                 continue;
             } else {
-                chunk_tracking.modified.push(*chunk_pos);
+                chunk_tracking.modified.push(chunk_pos);
             }
         } else {
-            chunk_tracking.added.push(*chunk_pos);
+            chunk_tracking.added.push(chunk_pos);
         }
-        render_universe
-            .chunks
-            .insert(*chunk_pos, main_chunk.clone());
+        chunk_tracking.versions.insert(chunk_pos, version);
+        render_universe.chunks.insert(chunk_pos, main_chunk);
     }
 
-    for (chunk_pos, _) in render_universe.chunks.iter() {
-        chunk_tracking.removed.push(*chunk_pos);
+    for chunk_pos in render_universe.chunks.positions() {
+        chunk_tracking.removed.push(chunk_pos);
     }
 }
 
@@ -203,7 +206,7 @@ pub struct VoxelData {
 
 #[derive(Resource, Clone, Default)]
 pub struct RenderChunkMap {
-    pub to_be_written: Vec<(u32, Chunk)>,
+    pub to_be_written: Vec<(u32, Arc<Chunk>)>,
     pub buffer_alloc: ChunkAllocator,
 }
 
@@ -279,12 +282,12 @@ fn prepare_chunks(
     let chunk_center = Vec3::splat(CHUNK_SIDE as f32 / 2.);
     let visible_chunks: HashSet<IVec3> = universe
         .chunks
-        .iter()
-        .filter_map(|(pos, _chunk)| {
+        .positions()
+        .filter_map(|pos| {
             if (cam_pos - (pos.as_vec3() + chunk_center)).length_squared()
                 < settings.view_distance_blocks.pow(2) as f32
             {
-                Some(*pos)
+                Some(pos)
             } else {
                 None
             }
@@ -303,14 +306,15 @@ fn prepare_chunks(
 
     let to_be_rendered: HashSet<IVec3> = universe
         .chunks
-        .iter()
-        .filter_map(|(pos, _)| {
-            if visible_chunks.contains(pos) {
-                if !render_chunk_map.buffer_alloc.is_allocated(pos) {
-                    Some(*pos)
+        .positions()
+        .filter_map(|pos| {
+            if visible_chunks.contains(&pos) {
+                if !render_chunk_map.buffer_alloc.is_allocated(&pos) {
+                    Some(pos)
                 } else {
-                    if chunk_tracking.added.contains(pos) || chunk_tracking.modified.contains(pos) {
-                        Some(*pos)
+                    if chunk_tracking.added.contains(&pos) || chunk_tracking.modified.contains(&pos)
+                    {
+                        Some(pos)
                     } else {
                         None
                     }
@@ -322,8 +326,7 @@ fn prepare_chunks(
         .collect();
 
     for &pos in to_be_rendered.iter() {
-        let chunk = universe.chunks.get(&pos).unwrap();
-        let grid = chunk.clone();
+        let grid = universe.chunks.get(&pos).unwrap();
         if let Some(BufferOffset(offset)) = render_chunk_map.buffer_alloc.get(&pos) {
             render_chunk_map.to_be_written.push((offset, grid));
         } else {
@@ -384,7 +387,9 @@ fn write_chunks(
         let mut linear_chunks = Vec::<u8>::new();
         for (offset, grid_ptr) in render_chunk_map.to_be_written.iter() {
             let offset = *offset as u32 * chunk_volume as u32;
-            linear_chunks.extend(bytemuck::cast_slice(grid_ptr.get_ref().as_ref()));
+            for block in grid_ptr.get_ref().iter() {
+                linear_chunks.extend(block.to_gpu().to_le_bytes());
+            }
             linear_chunks_offsets.extend(offset.to_le_bytes());
         }
         render_queue.write_buffer(&voxel_data.chunks_loading, 0, &linear_chunks);
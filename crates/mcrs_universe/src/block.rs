@@ -9,38 +9,45 @@ use crate::{is_default, HasNameId, MAX_LIGHT};
 
 /// 1 cubic meter ingame
 #[repr(C)]
-#[derive(Debug, Clone, Pod, Zeroable, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(
+    Debug, Clone, Pod, Zeroable, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize,
+)]
 pub struct Block {
     pub id: BlockId,
+    pub state: BlockState,
     pub properties: FlagBank,
-    // for now i'm using light0 as torchlight and light1 as sunlight
-    // in the future they could be a u16 divided into 4 u4
-    // that encode red, green and blue torchlight and sunlight.
-    pub light0: u8,
-    pub light1: u8,
+    // torchlight in the low nibble and sunlight in the high nibble,
+    // use `get_light` and `set_light` to access them.
+    pub light: u8,
 }
 impl Block {
     // Generation and flag checking/setting utilities
     pub fn new(block_info: &BlockBlueprint) -> Self {
         Self {
             id: block_info.id,
-            light0: block_info.light_level,
-            light1: 0,
-            properties: block_info.flags,
+            state: BlockState::default(),
+            light: block_info.light_level.min(MAX_LIGHT),
+            properties: block_info.block_flags(),
         }
     }
 
+    /// Packs the block as read by the voxel raytracer:
+    /// the id in the low 16 bits, then the flags and the light. The state is left out.
+    pub fn to_gpu(&self) -> u32 {
+        self.id.0 as u32 | (self.properties._flags as u32) << 16 | (self.light as u32) << 24
+    }
+
     pub fn get_light(&self, light_type: LightType) -> u8 {
         match light_type {
-            LightType::Torch => self.light0,
-            LightType::Sun => self.light1,
+            LightType::Torch => self.light & 0x0F,
+            LightType::Sun => self.light >> 4,
         }
     }
     pub fn set_light(&mut self, light_type: LightType, v: u8) {
         assert!((0..=MAX_LIGHT).contains(&v), "brightness: {}", v);
         match light_type {
-            LightType::Torch => self.light0 = v,
-            LightType::Sun => self.light1 = v,
+            LightType::Torch => self.light = (self.light & 0xF0) | v,
+            LightType::Sun => self.light = (self.light & 0x0F) | (v << 4),
         }
     }
 }
@@ -64,8 +71,13 @@ pub enum BlockFace {
 pub struct BlockBlueprint {
     pub name: String,
     pub id: BlockId,
+
+    /// Only `Collidable` is read, the other flags come from `material`.
     pub flags: FlagBank,
 
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub material: BlockMaterial,
+
     #[serde(default, skip_serializing_if = "is_default")]
     pub light_level: u8,
 
@@ -79,6 +91,10 @@ pub struct BlockBlueprint {
 
     #[serde(default, skip_serializing_if = "is_default")]
     pub drop_item_id: BlockId,
+
+    /// Properties that each block can set independently, like the axis of a log.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub states: Vec<BlockStateProperty>,
 }
 impl HasNameId<BlockId> for BlockBlueprint {
     fn id(&self) -> BlockId {
@@ -87,21 +103,215 @@ impl HasNameId<BlockId> for BlockBlueprint {
     fn name(&self) -> String {
         self.name.clone()
     }
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
 }
 impl BlockBlueprint {
+    /// Flags copied in every `Block` of this kind, so that the hot paths
+    /// (physics, meshing and lighting) can check them without a lookup.
+    pub fn block_flags(&self) -> FlagBank {
+        let mut flags = FlagBank::default();
+        let material = &self.material;
+        for (flag, value) in [
+            (
+                BlockFlag::Collidable,
+                self.flags.check(BlockFlag::Collidable),
+            ),
+            (
+                BlockFlag::Opaque,
+                material.transparency == Transparency::Opaque,
+            ),
+            (
+                BlockFlag::Translucent,
+                material.transparency == Transparency::Translucent,
+            ),
+            (BlockFlag::Fluid, material.fluid),
+            (BlockFlag::Replaceable, material.replaceable),
+            (BlockFlag::Gravity, material.gravity),
+        ] {
+            if value {
+                flags.set(flag);
+            }
+        }
+        flags
+    }
+
     /// True if the block emits light
     pub fn is_light_source(&self) -> bool {
         self.light_level > 0
     }
+
+    /// Number of different states, every combination of the property values.
+    pub fn state_count(&self) -> usize {
+        self.states.iter().map(|p| p.values.len().max(1)).product()
+    }
+
+    pub fn is_valid_state(&self, state: BlockState) -> bool {
+        (state.0 as usize) < self.state_count()
+    }
+
+    /// Value of the property `name` in `state`.
+    pub fn get_state_value(&self, state: BlockState, name: &str) -> Option<&str> {
+        let (stride, property) = self.state_stride(name)?;
+        let index = (state.0 as usize / stride) % property.values.len();
+        property.values.get(index).map(|v| v.as_str())
+    }
+
+    /// `state` with the property `name` set to `value`.
+    pub fn with_state_value(
+        &self,
+        state: BlockState,
+        name: &str,
+        value: &str,
+    ) -> Option<BlockState> {
+        let (stride, property) = self.state_stride(name)?;
+        let new_index = property.values.iter().position(|v| v == value)?;
+        let old_index = (state.0 as usize / stride) % property.values.len();
+        let packed = state.0 as usize - old_index * stride + new_index * stride;
+        Some(BlockState(packed as u16))
+    }
+
+    // The states are packed in mixed radix, in the order the properties are declared.
+    fn state_stride(&self, name: &str) -> Option<(usize, &BlockStateProperty)> {
+        let mut stride = 1;
+        for property in self.states.iter() {
+            if property.name == name {
+                return (!property.values.is_empty()).then_some((stride, property));
+            }
+            stride *= property.values.len().max(1);
+        }
+        None
+    }
+}
+
+/// How the block interacts with the world.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BlockMaterial {
+    /// Time in seconds to mine the block by hand, 0 is instant.
+    pub hardness: f32,
+    pub blast_resistance: f32,
+    /// Scales the ground friction of the bodies standing on the block, 1 leaves it unchanged.
+    pub friction: f32,
+    pub transparency: Transparency,
+    pub fluid: bool,
+    /// Placing a block on it replaces it, like tall grass.
+    pub replaceable: bool,
+    /// Falls when there is nothing below, like sand.
+    pub gravity: bool,
+}
+impl Default for BlockMaterial {
+    fn default() -> Self {
+        Self {
+            hardness: 1.0,
+            blast_resistance: 1.0,
+            friction: 1.0,
+            transparency: Transparency::Opaque,
+            fluid: false,
+            replaceable: false,
+            gravity: false,
+        }
+    }
+}
+
+/// How light and the faces of the neighbours are affected by the block.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Transparency {
+    /// Blocks light and hides the faces behind it.
+    #[default]
+    Opaque,
+    /// Either fully see-through or fully solid, like leaves.
+    Cutout,
+    /// Partially see-through, like water or stained glass.
+    Translucent,
+}
+
+/// Material of every block id, stored densely so it can be read per block.
+#[derive(Debug, Default, Clone)]
+pub struct BlockMaterials {
+    materials: Vec<BlockMaterial>,
+    fallback: BlockMaterial,
+}
+impl BlockMaterials {
+    pub fn new<'a>(blueprints: impl Iterator<Item = &'a BlockBlueprint>) -> Self {
+        let mut materials = Self::default();
+        for bp in blueprints {
+            let index = bp.id.0 as usize;
+            if materials.materials.len() <= index {
+                materials
+                    .materials
+                    .resize(index + 1, BlockMaterial::default());
+            }
+            materials.materials[index] = bp.material.clone();
+        }
+        materials
+    }
+
+    pub fn get(&self, id: BlockId) -> &BlockMaterial {
+        self.materials.get(id.0 as usize).unwrap_or(&self.fallback)
+    }
+}
+
+/// A named block property and the values it can take, the first one is the default.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct BlockStateProperty {
+    pub name: String,
+    pub values: Vec<String>,
 }
 
-/// Logical block Id.
-/// For now, it's also the offset in the 3d texture buffer.
+/// Combination of the values of a block's state properties, see `BlockBlueprint::states`.
 #[repr(C)]
 #[derive(Debug, Default, PartialEq, Eq, Clone, Hash, Copy, Deref, DerefMut, Pod, Zeroable)]
-pub struct BlockId(u8);
-impl From<u8> for BlockId {
-    fn from(v: u8) -> Self {
+pub struct BlockState(u16);
+impl BlockState {
+    /// Number of distinct states a block can have.
+    pub const MAX_STATES: usize = u16::MAX as usize + 1;
+}
+impl From<u16> for BlockState {
+    fn from(v: u16) -> Self {
+        BlockState(v)
+    }
+}
+
+impl Serialize for BlockState {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0.serialize(serializer)
+    }
+}
+impl<'de> Deserialize<'de> for BlockState {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Deserialize::deserialize(deserializer).map(BlockState)
+    }
+}
+
+/// Logical block Id, as numbered by the loaded blueprints.
+/// Saved levels keep their own numbering, see `palette::LevelPalette`.
+#[repr(C)]
+#[derive(
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Clone,
+    Hash,
+    Copy,
+    Deref,
+    DerefMut,
+    Pod,
+    Zeroable,
+)]
+pub struct BlockId(u16);
+impl From<u16> for BlockId {
+    fn from(v: u16) -> Self {
         BlockId(v)
     }
 }
@@ -129,12 +339,10 @@ impl<'de> Deserialize<'de> for BlockId {
 pub enum BlockFlag {
     Collidable,
     Opaque,
-    Flag3,
-    Flag4,
-    Flag5,
-    Flag6,
-    Flag7,
-    Flag8,
+    Translucent,
+    Fluid,
+    Replaceable,
+    Gravity,
 }
 
 impl From<BlockFlag> for u8 {
@@ -155,7 +363,7 @@ impl FromStr for BlockFlag {
 }
 
 #[repr(C)]
-#[derive(Default, Debug, Clone, Copy, Pod, Zeroable, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, Copy, Pod, Zeroable, PartialEq, Eq, Hash)]
 pub struct FlagBank {
     _flags: u8,
 }
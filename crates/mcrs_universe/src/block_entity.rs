@@ -0,0 +1,40 @@
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use bevy::{prelude::*, utils::HashMap};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Data attached to a single block that doesn't fit in `Block`,
+/// like the content of a chest or the text of a sign.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockEntity {
+    /// Tells how to read `data`, by convention the name of the owning block.
+    pub kind: String,
+    /// Serialized by the system that owns the kind, so its type can be anything.
+    pub data: Vec<u8>,
+}
+
+impl BlockEntity {
+    pub fn new<T: Serialize>(kind: &str, value: &T) -> Result<Self, bincode::Error> {
+        Ok(Self {
+            kind: kind.to_string(),
+            data: bincode::serialize(value)?,
+        })
+    }
+
+    pub fn read<T: DeserializeOwned>(&self) -> Result<T, bincode::Error> {
+        bincode::deserialize(&self.data)
+    }
+}
+
+/// Block entities of a chunk by inner position, locked apart from the blocks
+/// the same way `ChunkPointer` locks them.
+#[derive(Debug, Clone, Default)]
+pub struct BlockEntities(Arc<RwLock<HashMap<IVec3, BlockEntity>>>);
+impl BlockEntities {
+    pub fn get_ref(&self) -> RwLockReadGuard<'_, HashMap<IVec3, BlockEntity>> {
+        self.0.read().unwrap()
+    }
+    pub fn get_mut(&self) -> RwLockWriteGuard<'_, HashMap<IVec3, BlockEntity>> {
+        self.0.write().unwrap()
+    }
+}
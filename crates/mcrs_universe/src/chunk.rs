@@ -1,16 +1,23 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use crate::{
     block::{Block, LightType},
+    block_entity::{BlockEntities, BlockEntity},
     CHUNK_AREA, CHUNK_SIDE, CHUNK_VOLUME,
 };
 
 /// Cube of Blocks with side length of `CHUNK_SIDE`
-#[derive(Debug, Clone)]
+///
+/// Every method takes `&self`, the blocks, the block entities and the version
+/// are locked or atomic, so systems can write to different chunks in parallel.
+#[derive(Debug)]
 pub struct Chunk {
     pointer: ChunkPointer,
-    pub version: ChunkVersion,
+    entities: BlockEntities,
+    version: AtomicU64,
 }
 
 impl Chunk {
@@ -18,34 +25,143 @@ impl Chunk {
         (0..CHUNK_VOLUME).map(Self::idx2xyz)
     }
 
-    pub fn get_ref(&self) -> RwLockReadGuard<[Block; CHUNK_VOLUME]> {
+    /// Uniform and paletted chunks are expanded in a temporary copy,
+    /// prefer `read_block` to look at a few blocks.
+    pub fn get_ref(&self) -> ChunkReadGuard<'_> {
         self.pointer.get_ref()
     }
 
-    pub fn get_mut(&self) -> RwLockWriteGuard<[Block; CHUNK_VOLUME]> {
+    /// Makes the chunk dense, call `compact` after a batch of changes to shrink it again.
+    pub fn get_mut(&self) -> ChunkWriteGuard<'_> {
         // todo: maybe update version here too
         self.pointer.get_mut()
     }
 
+    /// Stores the blocks in the smallest representation, uniform or paletted if possible.
+    /// The blocks don't change so the version isn't updated. Cheap if already compact.
+    pub fn compact(&self) {
+        self.pointer.0.write().unwrap().compact();
+    }
+
+    /// Bytes used by the blocks of the chunk.
+    pub fn memory_usage(&self) -> usize {
+        self.pointer.0.read().unwrap().memory_usage()
+    }
+
+    pub fn is_dense(&self) -> bool {
+        matches!(*self.pointer.0.read().unwrap(), ChunkStorage::Dense(_))
+    }
+
     pub fn empty() -> Self {
+        Self::filled(Block::default())
+    }
+
+    /// A chunk made of a single kind of block, it doesn't allocate the blocks.
+    pub fn filled(block: Block) -> Self {
         Self {
-            pointer: ChunkPointer(Arc::new(RwLock::new([Block::default(); CHUNK_VOLUME]))),
-            version: ChunkVersion::new(),
+            pointer: ChunkPointer(Arc::new(RwLock::new(ChunkStorage::Uniform(block)))),
+            entities: BlockEntities::default(),
+            version: AtomicU64::new(0),
+        }
+    }
+
+    pub fn version(&self) -> ChunkVersion {
+        ChunkVersion(self.version.load(Ordering::Acquire))
+    }
+
+    /// Tells the consumers of the chunk that it changed, see `ChunkVersion`.
+    pub fn update_version(&self) {
+        self.version.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Replacing a block with one of a different kind removes its block entity.
+    /// The chunk is left dense, see `get_mut`.
+    pub fn set_block(&self, xyz: IVec3, block: Block) {
+        let old = std::mem::replace(&mut self.pointer.get_mut()[Self::xyz2idx(xyz)], block);
+        if old.id != block.id {
+            self.entities.get_mut().remove(&xyz);
         }
+        self.update_version();
     }
 
-    pub fn set_block(&mut self, xyz: IVec3, block: Block) {
-        self.pointer.get_mut()[Self::xyz2idx(xyz)] = block;
-        self.version.update();
+    /// Calls `edit` on the blocks from `min` to `max` inclusive, in chunk coordinates,
+    /// under a single lock. `edit` returns whether it changed the block.
+    /// The version is bumped once if anything changed and the chunk is compacted again.
+    /// Returns the number of changed blocks.
+    pub fn edit(
+        &self,
+        min: IVec3,
+        max: IVec3,
+        mut edit: impl FnMut(IVec3, &mut Block) -> bool,
+    ) -> usize {
+        let mut changed = 0;
+        let mut replaced = vec![];
+        {
+            let mut blocks = self.pointer.get_mut();
+            for x in min.x..=max.x {
+                for y in min.y..=max.y {
+                    for z in min.z..=max.z {
+                        let xyz = IVec3::new(x, y, z);
+                        let block = &mut blocks[Self::xyz2idx(xyz)];
+                        let old_id = block.id;
+                        if edit(xyz, block) {
+                            changed += 1;
+                            if old_id != block.id {
+                                replaced.push(xyz);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if changed > 0 {
+            let mut entities = self.entities.get_mut();
+            for xyz in replaced {
+                entities.remove(&xyz);
+            }
+            self.update_version();
+        }
+        self.compact();
+        changed
     }
 
-    pub fn set_block_light(&mut self, xyz: IVec3, light_type: LightType, v: u8) {
+    pub fn set_block_light(&self, xyz: IVec3, light_type: LightType, v: u8) {
         self.pointer.get_mut()[Self::xyz2idx(xyz)].set_light(light_type, v);
-        self.version.update();
+        self.update_version();
     }
 
     pub fn read_block(&self, xyz: IVec3) -> Block {
-        self.pointer.get_ref()[Self::xyz2idx(xyz)]
+        self.pointer.read_block(Self::xyz2idx(xyz))
+    }
+
+    /// Whether `f` is true for any block, only the palette of compact chunks is checked.
+    pub fn any_block(&self, f: impl Fn(&Block) -> bool) -> bool {
+        match &*self.pointer.0.read().unwrap() {
+            ChunkStorage::Uniform(block) => f(block),
+            ChunkStorage::Paletted { palette, .. } => palette.iter().any(f),
+            ChunkStorage::Dense(blocks) => blocks.iter().any(f),
+        }
+    }
+
+    pub fn get_entities(&self) -> &BlockEntities {
+        &self.entities
+    }
+
+    pub fn read_block_entity(&self, xyz: IVec3) -> Option<BlockEntity> {
+        self.entities.get_ref().get(&xyz).cloned()
+    }
+
+    pub fn set_block_entity(&self, xyz: IVec3, entity: BlockEntity) {
+        self.entities.get_mut().insert(xyz, entity);
+        self.update_version();
+    }
+
+    pub fn remove_block_entity(&self, xyz: IVec3) -> Option<BlockEntity> {
+        let removed = self.entities.get_mut().remove(&xyz);
+        if removed.is_some() {
+            self.update_version();
+        }
+        removed
     }
 
     pub fn xyz2idx(xyz: IVec3) -> usize {
@@ -68,15 +184,165 @@ impl Chunk {
     }
 }
 
-/// Points to an array of Blocks in a thread-safe way
-#[derive(Debug, Clone)]
-pub struct ChunkPointer(Arc<RwLock<[Block; CHUNK_VOLUME]>>);
+/// Points to the blocks of a chunk in a thread-safe way
+#[derive(Debug)]
+pub struct ChunkPointer(Arc<RwLock<ChunkStorage>>);
 impl ChunkPointer {
-    fn get_ref(&self) -> RwLockReadGuard<[Block; CHUNK_VOLUME]> {
-        self.0.read().unwrap()
+    fn get_ref(&self) -> ChunkReadGuard<'_> {
+        let storage = self.0.read().unwrap();
+        match *storage {
+            ChunkStorage::Dense(_) => ChunkReadGuard::Dense(storage),
+            _ => ChunkReadGuard::Expanded(storage.expand()),
+        }
+    }
+    fn get_mut(&self) -> ChunkWriteGuard<'_> {
+        let mut storage = self.0.write().unwrap();
+        storage.make_dense();
+        ChunkWriteGuard(storage)
     }
-    fn get_mut(&self) -> RwLockWriteGuard<[Block; CHUNK_VOLUME]> {
-        self.0.write().unwrap()
+    fn read_block(&self, index: usize) -> Block {
+        self.0.read().unwrap().get(index)
+    }
+}
+
+/// How the blocks of a chunk are kept in memory.
+///
+/// Sky and deep underground chunks are often a single block, most others hold few kinds of
+/// blocks, so both are stored compactly and only made dense when written to.
+/// `Chunk::compact` goes the other way once the chunk is done changing.
+#[derive(Debug, Clone)]
+pub enum ChunkStorage {
+    Uniform(Block),
+    Paletted {
+        palette: Vec<Block>,
+        indices: Box<[u8]>,
+    },
+    Dense(Box<[Block; CHUNK_VOLUME]>),
+}
+
+impl ChunkStorage {
+    fn get(&self, index: usize) -> Block {
+        match self {
+            Self::Uniform(block) => *block,
+            Self::Paletted { palette, indices } => palette[indices[index] as usize],
+            Self::Dense(blocks) => blocks[index],
+        }
+    }
+
+    fn expand(&self) -> Box<[Block; CHUNK_VOLUME]> {
+        match self {
+            Self::Uniform(block) => filled(*block),
+            Self::Paletted { palette, indices } => {
+                let mut blocks = filled(Block::default());
+                for (block, index) in blocks.iter_mut().zip(indices.iter()) {
+                    *block = palette[*index as usize];
+                }
+                blocks
+            }
+            Self::Dense(blocks) => blocks.clone(),
+        }
+    }
+
+    fn make_dense(&mut self) {
+        if !matches!(self, Self::Dense(_)) {
+            *self = Self::Dense(self.expand());
+        }
+    }
+
+    /// Picks the smallest representation of the same blocks.
+    fn compact(&mut self) {
+        // only written through dense storage, so the others are already the smallest
+        if !matches!(self, Self::Dense(_)) {
+            return;
+        }
+        let mut palette: Vec<Block> = vec![];
+        let mut lookup = HashMap::<Block, u8>::new();
+        let mut indices = Vec::with_capacity(CHUNK_VOLUME);
+        for i in 0..CHUNK_VOLUME {
+            let block = self.get(i);
+            let index = match lookup.get(&block) {
+                Some(index) => *index,
+                None if palette.len() <= u8::MAX as usize => {
+                    palette.push(block);
+                    let index = (palette.len() - 1) as u8;
+                    lookup.insert(block, index);
+                    index
+                }
+                None => {
+                    self.make_dense();
+                    return;
+                }
+            };
+            indices.push(index);
+        }
+        *self = if palette.len() == 1 {
+            Self::Uniform(palette[0])
+        } else {
+            Self::Paletted {
+                palette,
+                indices: indices.into_boxed_slice(),
+            }
+        };
+    }
+
+    fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + match self {
+                Self::Uniform(_) => 0,
+                Self::Paletted { palette, indices } => {
+                    palette.capacity() * std::mem::size_of::<Block>() + indices.len()
+                }
+                Self::Dense(_) => std::mem::size_of::<[Block; CHUNK_VOLUME]>(),
+            }
+    }
+}
+
+/// Allocates the array directly on the heap, it's too big for the stack in debug builds.
+fn filled(block: Block) -> Box<[Block; CHUNK_VOLUME]> {
+    vec![block; CHUNK_VOLUME]
+        .into_boxed_slice()
+        .try_into()
+        .expect("the vec has the length of a chunk")
+}
+
+/// Blocks of a chunk readable as an array, see `Chunk::get_ref`.
+pub enum ChunkReadGuard<'a> {
+    Dense(RwLockReadGuard<'a, ChunkStorage>),
+    Expanded(Box<[Block; CHUNK_VOLUME]>),
+}
+
+impl Deref for ChunkReadGuard<'_> {
+    type Target = [Block; CHUNK_VOLUME];
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Dense(storage) => match &**storage {
+                ChunkStorage::Dense(blocks) => blocks,
+                _ => unreachable!("only dense storage is read in place"),
+            },
+            Self::Expanded(blocks) => blocks,
+        }
+    }
+}
+
+/// Blocks of a chunk writable as an array, see `Chunk::get_mut`.
+pub struct ChunkWriteGuard<'a>(RwLockWriteGuard<'a, ChunkStorage>);
+
+impl Deref for ChunkWriteGuard<'_> {
+    type Target = [Block; CHUNK_VOLUME];
+    fn deref(&self) -> &Self::Target {
+        match &*self.0 {
+            ChunkStorage::Dense(blocks) => blocks,
+            _ => unreachable!("the storage is made dense before writing"),
+        }
+    }
+}
+
+impl DerefMut for ChunkWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match &mut *self.0 {
+            ChunkStorage::Dense(blocks) => blocks,
+            _ => unreachable!("the storage is made dense before writing"),
+        }
     }
 }
 
@@ -85,12 +351,11 @@ impl ChunkPointer {
 /// version changes (renderer sends triangles/data to the gpu, replication sends data to clients)
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct ChunkVersion(u64);
+
 impl ChunkVersion {
-    pub fn new() -> Self {
-        Self(0)
-    }
-    pub fn update(&mut self) {
-        self.0 += 1;
+    /// How many times the chunk was bumped since `older`.
+    pub fn bumps_since(&self, older: &ChunkVersion) -> u64 {
+        self.0.saturating_sub(older.0)
     }
 }
 
@@ -99,7 +364,11 @@ impl ChunkVersion {
 mod test {
     use bevy::math::IVec3;
 
-    use crate::chunk::Chunk;
+    use crate::{
+        block::{Block, BlockId},
+        chunk::Chunk,
+        CHUNK_VOLUME,
+    };
     #[test]
     fn xyz_to_index_to_xyz() {
         for x in 0..32 {
@@ -113,4 +382,31 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn storage_upgrades_and_compacts() {
+        let stone = Block {
+            id: BlockId::from(1),
+            ..Default::default()
+        };
+        let chunk = Chunk::filled(stone);
+        assert!(!chunk.is_dense());
+        assert_eq!(chunk.read_block(IVec3::new(3, 4, 5)), stone);
+        assert_eq!(chunk.get_ref()[0], stone);
+
+        let xyz = IVec3::new(1, 2, 3);
+        chunk.set_block(xyz, Block::default());
+        assert!(chunk.is_dense());
+        let dense = chunk.get_ref().to_vec();
+
+        chunk.compact();
+        assert!(!chunk.is_dense());
+        assert!(chunk.memory_usage() < CHUNK_VOLUME * std::mem::size_of::<Block>() / 4);
+        assert_eq!(chunk.read_block(xyz), Block::default());
+        assert_eq!(chunk.get_ref().as_slice(), dense.as_slice());
+
+        chunk.set_block(xyz, stone);
+        chunk.compact();
+        assert!(chunk.memory_usage() < 64);
+    }
 }
@@ -0,0 +1,224 @@
+use std::fmt::{self, Display, Formatter};
+
+use bevy::prelude::*;
+
+use crate::{
+    block::{Block, BlockId},
+    chunk::Chunk,
+    journal::ChangeKind,
+    universe::Universe,
+    CHUNK_SIDE,
+};
+
+/// The most blocks a `BlockVolume` holds, as many as a cube 256 blocks wide.
+pub const MAX_VOLUME_BLOCKS: usize = 1 << 24;
+
+/// A `BlockVolume` with these sides would hold more than `MAX_VOLUME_BLOCKS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VolumeTooLarge(pub [usize; 3]);
+
+impl Display for VolumeTooLarge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "a volume of {}x{}x{} blocks is too large, at most {} blocks are supported",
+            self.0[0], self.0[1], self.0[2], MAX_VOLUME_BLOCKS
+        )
+    }
+}
+
+impl std::error::Error for VolumeTooLarge {}
+
+/// Product of the sides, an error past `MAX_VOLUME_BLOCKS`.
+fn checked_block_count(sides: [usize; 3]) -> Result<usize, VolumeTooLarge> {
+    let [x, y, z] = sides;
+    x.checked_mul(y)
+        .and_then(|count| count.checked_mul(z))
+        .filter(|count| *count <= MAX_VOLUME_BLOCKS)
+        .ok_or(VolumeTooLarge(sides))
+}
+
+/// A box of blocks copied out of the `Universe`, see `Universe::copy_box`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockVolume {
+    pub size: IVec3,
+    blocks: Vec<Block>,
+}
+
+impl BlockVolume {
+    /// A volume of `size` filled with `block`, negative sides are empty.
+    pub fn filled(size: IVec3, block: Block) -> Result<Self, VolumeTooLarge> {
+        let size = size.max(IVec3::ZERO);
+        Ok(Self {
+            size,
+            blocks: vec![block; Self::block_count(size)?],
+        })
+    }
+
+    /// Number of blocks in a volume of `size`, negative sides are empty.
+    pub fn block_count(size: IVec3) -> Result<usize, VolumeTooLarge> {
+        let size = size.max(IVec3::ZERO);
+        checked_block_count([size.x as usize, size.y as usize, size.z as usize])
+    }
+
+    pub fn contains(&self, xyz: IVec3) -> bool {
+        xyz.cmpge(IVec3::ZERO).all() && xyz.cmplt(self.size).all()
+    }
+
+    /// Same ordering as `Chunk::xyz2idx`.
+    fn index(&self, xyz: IVec3) -> usize {
+        (xyz.x * self.size.y * self.size.z + xyz.y * self.size.z + xyz.z) as usize
+    }
+
+    pub fn get(&self, xyz: IVec3) -> Option<Block> {
+        self.contains(xyz).then(|| self.blocks[self.index(xyz)])
+    }
+
+    pub fn set(&mut self, xyz: IVec3, block: Block) {
+        if self.contains(xyz) {
+            let index = self.index(xyz);
+            self.blocks[index] = block;
+        }
+    }
+
+    /// Positions in the volume, in the order the blocks are stored.
+    pub fn iter(&self) -> impl Iterator<Item = IVec3> {
+        let size = self.size;
+        (0..size.x).flat_map(move |x| {
+            (0..size.y).flat_map(move |y| (0..size.z).map(move |z| IVec3::new(x, y, z)))
+        })
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+}
+
+/// Bulk edits of the blocks in a box.
+///
+/// Every touched chunk is locked once and its version bumped once, however many blocks
+/// change in it. Chunks that aren't loaded are skipped and lighting isn't recomputed,
+/// so these are meant for tools and generation rather than gameplay edits.
+/// Boxes are given by two opposite corners, both included.
+impl Universe {
+    /// Calls `edit` with the position of every loaded block in the box,
+    /// `edit` returns whether it changed the block. Returns the number of changed blocks.
+    pub fn edit_box(
+        &self,
+        corner0: IVec3,
+        corner1: IVec3,
+        mut edit: impl FnMut(IVec3, &mut Block) -> bool,
+    ) -> usize {
+        let (min, max) = (corner0.min(corner1), corner0.max(corner1));
+        let (min_chunk, _) = self.pos_to_chunk_and_inner(&min);
+        let (max_chunk, _) = self.pos_to_chunk_and_inner(&max);
+        let mut changed = 0;
+        for x in (min_chunk.x..=max_chunk.x).step_by(CHUNK_SIDE) {
+            for y in (min_chunk.y..=max_chunk.y).step_by(CHUNK_SIDE) {
+                for z in (min_chunk.z..=max_chunk.z).step_by(CHUNK_SIDE) {
+                    let chunk_pos = IVec3::new(x, y, z);
+                    let Some(chunk) = self.chunks.get(&chunk_pos) else {
+                        continue;
+                    };
+                    let mut changes = vec![];
+                    let inner_min = (min - chunk_pos).max(IVec3::ZERO);
+                    let inner_max = (max - chunk_pos).min(IVec3::splat(CHUNK_SIDE as i32 - 1));
+                    changed += chunk.edit(inner_min, inner_max, |xyz, block| {
+                        let old = *block;
+                        let changed = edit(chunk_pos + xyz, block);
+                        if let Some(kind) = ChangeKind::between(&old, block) {
+                            changes.push((xyz, kind));
+                        }
+                        changed
+                    });
+                    self.journal.record_all(chunk_pos, changes);
+                }
+            }
+        }
+        changed
+    }
+
+    pub fn fill_box(&self, corner0: IVec3, corner1: IVec3, block: Block) -> usize {
+        self.edit_box(corner0, corner1, |_, old| replace(old, block))
+    }
+
+    /// Replaces the blocks of kind `from` with `to`, whatever their state.
+    pub fn replace_in_box(
+        &self,
+        corner0: IVec3,
+        corner1: IVec3,
+        from: BlockId,
+        to: Block,
+    ) -> usize {
+        self.edit_box(corner0, corner1, |_, old| {
+            old.id == from && replace(old, to)
+        })
+    }
+
+    /// Fills the blocks whose center is within `radius` of the center of `center`.
+    pub fn fill_sphere(&self, center: IVec3, radius: f32, block: Block) -> usize {
+        let extent = IVec3::splat(radius.max(0.0) as i32);
+        let radius_squared = radius * radius;
+        self.edit_box(center - extent, center + extent, |pos, old| {
+            (pos - center).as_vec3().length_squared() <= radius_squared && replace(old, block)
+        })
+    }
+
+    /// Blocks of chunks that aren't loaded are copied as the default block.
+    pub fn copy_box(&self, corner0: IVec3, corner1: IVec3) -> Result<BlockVolume, VolumeTooLarge> {
+        let (min, max) = (corner0.min(corner1), corner0.max(corner1));
+        // the sides of far apart corners don't fit in an i32
+        let sides = max
+            .wrapping_sub(min)
+            .to_array()
+            .map(|side| side as u32 as usize + 1);
+        checked_block_count(sides)?;
+        let mut volume = BlockVolume::filled(max - min + IVec3::ONE, Block::default())?;
+        let (min_chunk, _) = self.pos_to_chunk_and_inner(&min);
+        let (max_chunk, _) = self.pos_to_chunk_and_inner(&max);
+        for x in (min_chunk.x..=max_chunk.x).step_by(CHUNK_SIDE) {
+            for y in (min_chunk.y..=max_chunk.y).step_by(CHUNK_SIDE) {
+                for z in (min_chunk.z..=max_chunk.z).step_by(CHUNK_SIDE) {
+                    let chunk_pos = IVec3::new(x, y, z);
+                    let Some(chunk) = self.chunks.get(&chunk_pos) else {
+                        continue;
+                    };
+                    let blocks = chunk.get_ref();
+                    let inner_min = (min - chunk_pos).max(IVec3::ZERO);
+                    let inner_max = (max - chunk_pos).min(IVec3::splat(CHUNK_SIDE as i32 - 1));
+                    for x in inner_min.x..=inner_max.x {
+                        for y in inner_min.y..=inner_max.y {
+                            for z in inner_min.z..=inner_max.z {
+                                let xyz = IVec3::new(x, y, z);
+                                let block = blocks[Chunk::xyz2idx(xyz)];
+                                volume.set(chunk_pos + xyz - min, block);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(volume)
+    }
+
+    /// Pastes `volume` with its lowest corner at `origin`.
+    pub fn paste(&self, origin: IVec3, volume: &BlockVolume) -> usize {
+        if volume.size.cmple(IVec3::ZERO).any() {
+            return 0;
+        }
+        self.edit_box(origin, origin + volume.size - IVec3::ONE, |pos, old| {
+            volume
+                .get(pos - origin)
+                .is_some_and(|block| replace(old, block))
+        })
+    }
+}
+
+/// Returns whether `old` was different from `new`.
+fn replace(old: &mut Block, new: Block) -> bool {
+    if *old == new {
+        return false;
+    }
+    *old = new;
+    true
+}
@@ -20,6 +20,9 @@ impl HasNameId<GhostId> for GhostBlueprint {
     fn name(&self) -> String {
         self.name.clone()
     }
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
 }
 
 #[repr(C)]
@@ -0,0 +1,183 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{block::Block, universe::Universe};
+
+/// Ticks of changes kept for readers that fall behind.
+pub const JOURNAL_KEPT_TICKS: usize = 64;
+
+/// What changed in a block, a block change includes a light change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChangeKind {
+    /// Only the light.
+    Light,
+    /// The kind, the state or the flags, and maybe the light.
+    Block,
+}
+
+impl ChangeKind {
+    pub fn between(old: &Block, new: &Block) -> Option<Self> {
+        if old == new {
+            None
+        } else if (Block {
+            light: new.light,
+            ..*old
+        }) == *new
+        {
+            Some(Self::Light)
+        } else {
+            Some(Self::Block)
+        }
+    }
+}
+
+/// Changes of a chunk, positions are relative to the chunk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChunkChanges {
+    pub blocks: HashSet<IVec3>,
+    /// Blocks whose light changed but not their kind.
+    pub light: HashSet<IVec3>,
+    /// Set when some change wasn't recorded block by block and may concern any block.
+    pub untracked: Option<ChangeKind>,
+    /// Version bumps of the chunk that came with the recorded changes. When the chunk
+    /// version advanced more than this, some change wasn't recorded.
+    pub versions: u64,
+}
+
+impl ChunkChanges {
+    pub fn is_light_only(&self) -> bool {
+        self.blocks.is_empty() && self.untracked != Some(ChangeKind::Block)
+    }
+
+    fn record(&mut self, xyz: IVec3, kind: ChangeKind) {
+        match kind {
+            ChangeKind::Light => self.light.insert(xyz),
+            ChangeKind::Block => self.blocks.insert(xyz),
+        };
+    }
+
+    fn record_untracked(&mut self, kind: ChangeKind) {
+        self.untracked = self.untracked.max(Some(kind));
+    }
+
+    fn merge(&mut self, other: &ChunkChanges) {
+        self.blocks.extend(other.blocks.iter());
+        self.light.extend(other.light.iter());
+        if let Some(kind) = other.untracked {
+            self.record_untracked(kind);
+        }
+        self.versions += other.versions;
+    }
+}
+
+/// Positions of the blocks changed in the last ticks, by chunk.
+///
+/// `ChunkVersion` only says that a chunk changed, the journal says where, so consumers
+/// like replication can send only the changed blocks. Each consumer reads it at its own
+/// pace with a `JournalReader`. The `Universe` methods record their changes, code writing
+/// to the blocks of a chunk directly has to record them with `record` or `record_chunk`.
+/// Each call of these accounts for one bump of the chunk version, see `ChunkChanges::versions`.
+/// Recording only needs a shared reference, so systems writing in parallel can record.
+#[derive(Debug, Default)]
+pub struct ChangeJournal {
+    tick: u64,
+    current: Mutex<HashMap<IVec3, ChunkChanges>>,
+    ticks: VecDeque<(u64, HashMap<IVec3, ChunkChanges>)>,
+}
+
+/// Where a consumer is in the `ChangeJournal`.
+#[derive(Debug, Clone, Default)]
+pub struct JournalReader {
+    next_tick: u64,
+}
+
+pub enum JournalRead {
+    /// Changes since the last read, by chunk position.
+    Changes(HashMap<IVec3, ChunkChanges>),
+    /// The reader fell behind the kept ticks, any loaded chunk may have changed.
+    Lost,
+}
+
+impl ChangeJournal {
+    pub fn record(&self, chunk_pos: IVec3, xyz: IVec3, kind: ChangeKind) {
+        let mut current = self.current.lock().unwrap();
+        let chunk_changes = current.entry(chunk_pos).or_default();
+        chunk_changes.record(xyz, kind);
+        chunk_changes.versions += 1;
+    }
+
+    /// Records the changes of many blocks of a chunk under a single lock,
+    /// made with a single version bump. Nothing is recorded if there are none.
+    pub fn record_all(
+        &self,
+        chunk_pos: IVec3,
+        changes: impl IntoIterator<Item = (IVec3, ChangeKind)>,
+    ) {
+        let mut changes = changes.into_iter().peekable();
+        if changes.peek().is_none() {
+            return;
+        }
+        let mut current = self.current.lock().unwrap();
+        let chunk_changes = current.entry(chunk_pos).or_default();
+        for (xyz, kind) in changes {
+            chunk_changes.record(xyz, kind);
+        }
+        chunk_changes.versions += 1;
+    }
+
+    /// Records a change of unknown blocks of the chunk.
+    pub fn record_chunk(&self, chunk_pos: IVec3, kind: ChangeKind) {
+        let mut current = self.current.lock().unwrap();
+        let chunk_changes = current.entry(chunk_pos).or_default();
+        chunk_changes.record_untracked(kind);
+        chunk_changes.versions += 1;
+    }
+
+    /// Changes recorded in the current tick, not readable yet.
+    pub fn pending(&self, chunk_pos: &IVec3) -> Option<ChunkChanges> {
+        self.current.lock().unwrap().get(chunk_pos).cloned()
+    }
+
+    /// Closes the current tick, its changes become readable.
+    pub fn next_tick(&mut self) {
+        let changes = std::mem::take(self.current.get_mut().unwrap());
+        self.ticks.push_back((self.tick, changes));
+        while self.ticks.len() > JOURNAL_KEPT_TICKS {
+            self.ticks.pop_front();
+        }
+        self.tick += 1;
+    }
+
+    /// Changes of the ticks closed since the last read of `reader`.
+    pub fn read(&self, reader: &mut JournalReader) -> JournalRead {
+        let oldest = self.ticks.front().map_or(self.tick, |(tick, _)| *tick);
+        if reader.next_tick < oldest {
+            reader.next_tick = self.tick;
+            return JournalRead::Lost;
+        }
+        let mut read = HashMap::<IVec3, ChunkChanges>::new();
+        for (_, changes) in self.ticks.iter().filter(|(t, _)| *t >= reader.next_tick) {
+            for (chunk_pos, chunk_changes) in changes.iter() {
+                read.entry(*chunk_pos).or_default().merge(chunk_changes);
+            }
+        }
+        reader.next_tick = self.tick;
+        JournalRead::Changes(read)
+    }
+}
+
+pub fn advance_change_journal(mut universe: ResMut<Universe>) {
+    // Single block writes leave their chunk dense, the chunks changed in the tick
+    // are compacted once here instead of after every write.
+    for chunk_pos in universe.journal.current.lock().unwrap().keys() {
+        if let Some(chunk) = universe.chunks.get(chunk_pos) {
+            chunk.compact();
+        }
+    }
+    universe.journal.next_tick();
+}
@@ -1,42 +1,63 @@
-use std::fs::read_to_string;
+use std::fmt::{self, Debug, Display, Formatter};
 use std::hash::Hash;
+use std::path::Path;
 
 use bevy::{
-    app::{App, Plugin},
+    app::{App, FixedLast, Plugin},
     ecs::system::Resource,
     utils::HashMap,
 };
-use block::{BlockBlueprint, BlockId};
+use block::{BlockBlueprint, BlockId, BlockMaterials, BlockState};
 use ghost::{GhostBlueprint, GhostId};
-use ron::from_str;
+use journal::advance_change_journal;
+use pack::{qualified_name, read_packs, PackSource, BASE_NAMESPACE};
+use reload::BlueprintsReloadPlugin;
 use serde::Deserialize;
 use universe::Universe;
 
 pub mod block;
+pub mod block_entity;
 pub mod chunk;
+pub mod edit;
 pub mod ghost;
-pub mod plugin;
+pub mod journal;
+pub mod pack;
+pub mod palette;
+pub mod reload;
+pub mod structure;
 pub mod universe;
 
 #[cfg(test)]
 mod test;
 
-// TODO: To be passed from above
-pub const BLOCK_BLUEPRINTS_PATH: &str = "assets/block_blueprints.ron";
-pub const GHOST_BLUEPRINTS_PATH: &str = "assets/ghost_blueprints.ron";
+/// Default directory of the content packs, relative to the working directory.
+pub const PACKS_PATH: &str = "assets/packs";
 pub const CHUNK_SIDE: usize = 32;
 pub const CHUNK_AREA: usize = CHUNK_SIDE * CHUNK_SIDE;
 pub const CHUNK_VOLUME: usize = CHUNK_AREA * CHUNK_SIDE;
 pub const MAX_LIGHT: u8 = 15;
 
-pub struct McrsUniversePlugin;
+/// Adds the `Universe` and reloads the blueprints when the content packs in `packs_path` change.
+/// The app loads the first `Blueprints` with `Blueprints::load` and inserts them itself,
+/// so that it decides what to do when they are invalid.
+pub struct McrsUniversePlugin {
+    pub packs_path: String,
+}
+
+impl Default for McrsUniversePlugin {
+    fn default() -> Self {
+        Self {
+            packs_path: PACKS_PATH.to_string(),
+        }
+    }
+}
 
 impl Plugin for McrsUniversePlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(Universe::default());
-        app.insert_resource(Blueprints {
-            blocks: BlueprintList::from_file(BLOCK_BLUEPRINTS_PATH),
-            ghosts: BlueprintList::from_file(GHOST_BLUEPRINTS_PATH),
+        app.add_systems(FixedLast, advance_change_journal);
+        app.add_plugins(BlueprintsReloadPlugin {
+            packs_path: self.packs_path.clone(),
         });
     }
 }
@@ -45,8 +66,197 @@ impl Plugin for McrsUniversePlugin {
 pub struct Blueprints {
     pub blocks: BlueprintList<BlockId, BlockBlueprint>,
     pub ghosts: BlueprintList<GhostId, GhostBlueprint>,
+    /// The material of each block in `blocks`, indexed by id.
+    pub materials: BlockMaterials,
+    /// Namespaces of the loaded content packs, sorted.
+    pub packs: Vec<String>,
 }
 
+impl Blueprints {
+    pub fn new(
+        blocks: BlueprintList<BlockId, BlockBlueprint>,
+        ghosts: BlueprintList<GhostId, GhostBlueprint>,
+    ) -> Self {
+        Self {
+            materials: BlockMaterials::new(blocks.iter()),
+            blocks,
+            ghosts,
+            packs: vec![],
+        }
+    }
+
+    /// Reads the content packs in `packs_path` and merges them, see `Blueprints::from_packs`.
+    pub fn load(packs_path: &str) -> Result<Self, BlueprintError> {
+        Self::from_packs(read_packs(packs_path)?)
+    }
+
+    /// Merges the packs in the order of their namespaces, so the result doesn't depend
+    /// on how they were found. Two packs can't share a namespace or a block id.
+    pub fn from_packs(mut packs: Vec<PackSource>) -> Result<Self, BlueprintError> {
+        packs.sort_by(|a, b| a.pack.namespace.cmp(&b.pack.namespace));
+        let mut blocks = BlueprintList::default();
+        let mut ghosts = BlueprintList::default();
+        let mut namespaces: Vec<String> = vec![];
+        for PackSource { path, source, pack } in packs {
+            if namespaces.contains(&pack.namespace) {
+                return Err(BlueprintError::DuplicateNamespace {
+                    path,
+                    namespace: pack.namespace,
+                });
+            }
+            blocks.extend_from_pack(&path, &source, &pack.namespace, pack.blocks)?;
+            ghosts.extend_from_pack(&path, &source, &pack.namespace, pack.ghosts)?;
+            namespaces.push(pack.namespace);
+        }
+        let blueprints = Self {
+            packs: namespaces,
+            ..Self::new(blocks, ghosts)
+        };
+        blueprints.validate()?;
+        Ok(blueprints)
+    }
+
+    /// Checks that `base:Air` has id 0, that every block state fits in a `BlockState`
+    /// and that every voxel texture exists.
+    pub fn validate(&self) -> Result<(), BlueprintError> {
+        match self.blocks.id_named_checked("Air") {
+            Some(id) if *id == BlockId::from(0) => {}
+            _ => return Err(BlueprintError::MissingAir),
+        }
+        for block in self.blocks.iter() {
+            if block.state_count() > BlockState::MAX_STATES {
+                return Err(BlueprintError::TooManyStates {
+                    name: block.name.clone(),
+                    count: block.state_count(),
+                });
+            }
+        }
+        let texture_paths = self
+            .blocks
+            .iter()
+            .map(|block| (&block.name, &block.voxel_texture_path))
+            .chain(
+                self.ghosts
+                    .iter()
+                    .map(|ghost| (&ghost.name, &ghost.voxel_texture_path)),
+            );
+        for (name, texture_path) in texture_paths {
+            if !texture_path.is_empty() && !Path::new(texture_path).exists() {
+                return Err(BlueprintError::MissingTexture {
+                    name: name.clone(),
+                    path: texture_path.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The air blueprint, which `Blueprints::validate` guarantees to exist.
+    pub fn air(&self) -> &BlockBlueprint {
+        self.blocks
+            .get_checked(&BlockId::from(0))
+            .expect("validated blueprints contain air")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlueprintError {
+    Io {
+        path: String,
+        message: String,
+    },
+    Parse {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    DuplicateId {
+        path: String,
+        line: usize,
+        id: String,
+    },
+    DuplicateName {
+        path: String,
+        line: usize,
+        name: String,
+    },
+    InvalidName {
+        path: String,
+        line: usize,
+        name: String,
+    },
+    InvalidNamespace {
+        path: String,
+        namespace: String,
+    },
+    DuplicateNamespace {
+        path: String,
+        namespace: String,
+    },
+    MissingAir,
+    TooManyStates {
+        name: String,
+        count: usize,
+    },
+    MissingTexture {
+        name: String,
+        path: String,
+    },
+    UnknownId(String),
+    UnknownName(String),
+}
+
+impl Display for BlueprintError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, message } => write!(f, "{}: {}", path, message),
+            Self::Parse {
+                path,
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}:{}: {}", path, line, column, message),
+            Self::DuplicateId { path, line, id } => {
+                write!(f, "{}:{}: duplicate id {}", path, line, id)
+            }
+            Self::DuplicateName { path, line, name } => {
+                write!(f, "{}:{}: duplicate name {}", path, line, name)
+            }
+            Self::InvalidName { path, line, name } => {
+                write!(f, "{}:{}: {} can't contain ':'", path, line, name)
+            }
+            Self::InvalidNamespace { path, namespace } => write!(
+                f,
+                "{}: the namespace \"{}\" must be non empty and can't contain ':'",
+                path, namespace
+            ),
+            Self::DuplicateNamespace { path, namespace } => {
+                write!(f, "{}: another pack has the namespace {}", path, namespace)
+            }
+            Self::MissingAir => write!(
+                f,
+                "the {} pack must have a block named Air with id 0",
+                BASE_NAMESPACE
+            ),
+            Self::TooManyStates { name, count } => write!(
+                f,
+                "{} has {} states, at most {} are supported",
+                name,
+                count,
+                BlockState::MAX_STATES
+            ),
+            Self::MissingTexture { name, path } => {
+                write!(f, "the voxel texture of {} is missing: {}", name, path)
+            }
+            Self::UnknownId(id) => write!(f, "no blueprint has id {}", id),
+            Self::UnknownName(name) => write!(f, "no blueprint is named {}", name),
+        }
+    }
+}
+
+impl std::error::Error for BlueprintError {}
+
 #[derive(Debug, Default)]
 pub struct BlueprintList<ID, BL> {
     list: HashMap<ID, BL>,
@@ -56,10 +266,11 @@ pub struct BlueprintList<ID, BL> {
 pub trait HasNameId<ID> {
     fn id(&self) -> ID;
     fn name(&self) -> String;
+    fn set_name(&mut self, name: String);
 }
 
 impl<
-        ID: Clone + Copy + Default + Eq + PartialEq + Hash,
+        ID: Clone + Copy + Default + Eq + PartialEq + Hash + Debug,
         BL: HasNameId<ID> + Clone + Default + for<'de> Deserialize<'de>,
     > BlueprintList<ID, BL>
 {
@@ -72,38 +283,93 @@ impl<
         blueprints
     }
 
-    pub fn from_file(path: &str) -> Self {
-        let string = read_to_string(path).unwrap();
-        let blueprints_vec: Vec<BL> = from_str(&string).unwrap();
-        Self::from_list(blueprints_vec)
+    /// Adds the blueprints of the pack `namespace`, read from `source`, qualifying their names.
+    /// Fails if a name has a namespace of its own or if an id or a name is already taken.
+    /// `path` and `source` are only used to report errors.
+    pub fn extend_from_pack(
+        &mut self,
+        path: &str,
+        source: &str,
+        namespace: &str,
+        list: Vec<BL>,
+    ) -> Result<(), BlueprintError> {
+        let mut names = HashMap::<String, usize>::new();
+        for mut blueprint in list {
+            let name = blueprint.name();
+            let seen = names.entry(name.clone()).or_default();
+            *seen += 1;
+            let line = line_of_nth(source, &format!("\"{}\"", name), *seen);
+            if name.contains(':') {
+                return Err(BlueprintError::InvalidName {
+                    path: path.to_string(),
+                    line,
+                    name,
+                });
+            }
+            let name = format!("{}:{}", namespace, name);
+            if self.name2id.contains_key(&name) {
+                return Err(BlueprintError::DuplicateName {
+                    path: path.to_string(),
+                    line,
+                    name,
+                });
+            }
+            if self.list.contains_key(&blueprint.id()) {
+                return Err(BlueprintError::DuplicateId {
+                    path: path.to_string(),
+                    line,
+                    id: format!("{:?}", blueprint.id()),
+                });
+            }
+            blueprint.set_name(name);
+            self.name2id.insert(blueprint.name(), blueprint.id());
+            self.list.insert(blueprint.id(), blueprint);
+        }
+        Ok(())
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &BL> {
         self.list.iter().map(|(_, b)| b)
     }
 
-    pub fn get(&self, id: &ID) -> &BL {
-        self.list.get(id).unwrap()
+    pub fn get(&self, id: &ID) -> Result<&BL, BlueprintError> {
+        self.list
+            .get(id)
+            .ok_or_else(|| BlueprintError::UnknownId(format!("{:?}", id)))
     }
     pub fn get_checked(&self, id: &ID) -> Option<&BL> {
         self.list.get(id)
     }
 
-    pub fn get_named(&self, name: &str) -> &BL {
-        self.list.get(&self.id_named(name)).unwrap()
+    pub fn get_named(&self, name: &str) -> Result<&BL, BlueprintError> {
+        self.get(&self.id_named(name)?)
     }
     pub fn get_named_checked(&self, name: &str) -> Option<&BL> {
         self.list.get(self.id_named_checked(name)?)
     }
 
-    pub fn id_named(&self, name: &str) -> ID {
-        *self.name2id.get(name).unwrap()
+    /// Names without a namespace refer to the base pack.
+    pub fn id_named(&self, name: &str) -> Result<ID, BlueprintError> {
+        let name = qualified_name(name);
+        self.name2id
+            .get(name.as_ref())
+            .copied()
+            .ok_or_else(|| BlueprintError::UnknownName(name.into_owned()))
     }
     pub fn id_named_checked(&self, name: &str) -> Option<&ID> {
-        self.name2id.get(name)
+        self.name2id.get(qualified_name(name).as_ref())
     }
 }
 
+/// 1-based line of the `nth` occurrence of `pattern` in `source`, 0 if there is none.
+fn line_of_nth(source: &str, pattern: &str, nth: usize) -> usize {
+    source
+        .match_indices(pattern)
+        .nth(nth.saturating_sub(1))
+        .map(|(index, _)| source[..index].lines().count().max(1))
+        .unwrap_or(0)
+}
+
 /// Used to tell serde to not serialize default fields.
 /// In combination with marking fields as default results in serde not serializing default fields
 /// and setting as the default value fields if during deserialization the field is not present.
@@ -0,0 +1,85 @@
+use std::borrow::Cow;
+use std::fs::{read_dir, read_to_string};
+use std::path::PathBuf;
+
+use ron::from_str;
+use serde::Deserialize;
+
+use crate::{block::BlockBlueprint, ghost::GhostBlueprint, BlueprintError};
+
+/// Namespace of the pack that holds the blocks the game can't do without, like `Air`.
+/// Names without a namespace refer to it.
+pub const BASE_NAMESPACE: &str = "base";
+
+/// A RON file of blocks and ghosts whose names live under `namespace`,
+/// so `Stone` in the `base` pack is known as `base:Stone`.
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct ContentPack {
+    pub namespace: String,
+    #[serde(default)]
+    pub blocks: Vec<BlockBlueprint>,
+    #[serde(default)]
+    pub ghosts: Vec<GhostBlueprint>,
+}
+
+/// A pack with the file it was read from, kept to point errors at their line.
+#[derive(Debug, Clone)]
+pub struct PackSource {
+    pub path: String,
+    pub source: String,
+    pub pack: ContentPack,
+}
+
+impl PackSource {
+    pub fn from_ron(path: &str, source: &str) -> Result<Self, BlueprintError> {
+        let pack: ContentPack = from_str(source).map_err(|err| BlueprintError::Parse {
+            path: path.to_string(),
+            line: err.position.line,
+            column: err.position.col,
+            message: err.code.to_string(),
+        })?;
+        if pack.namespace.is_empty() || pack.namespace.contains(':') {
+            return Err(BlueprintError::InvalidNamespace {
+                path: path.to_string(),
+                namespace: pack.namespace,
+            });
+        }
+        Ok(Self {
+            path: path.to_string(),
+            source: source.to_string(),
+            pack,
+        })
+    }
+}
+
+/// Reads every `.ron` file in `dir`, sorted by namespace.
+pub fn read_packs(dir: &str) -> Result<Vec<PackSource>, BlueprintError> {
+    let io_error = |path: &str, err: std::io::Error| BlueprintError::Io {
+        path: path.to_string(),
+        message: err.to_string(),
+    };
+    let mut paths: Vec<PathBuf> = read_dir(dir)
+        .map_err(|err| io_error(dir, err))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ron"))
+        .collect();
+    paths.sort();
+
+    let mut packs = vec![];
+    for path in paths {
+        let path = path.to_string_lossy().to_string();
+        let source = read_to_string(&path).map_err(|err| io_error(&path, err))?;
+        packs.push(PackSource::from_ron(&path, &source)?);
+    }
+    packs.sort_by(|a, b| a.pack.namespace.cmp(&b.pack.namespace));
+    Ok(packs)
+}
+
+/// `name` prefixed by the base namespace if it has none.
+pub fn qualified_name(name: &str) -> Cow<'_, str> {
+    if name.contains(':') {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("{}:{}", BASE_NAMESPACE, name))
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block::{Block, BlockBlueprint, BlockId, BlockState},
+    pack::qualified_name,
+    BlueprintList, CHUNK_VOLUME,
+};
+
+/// The blocks of a chunk encoded against a local palette.
+///
+/// Every distinct block id and state is stored once and the blocks refer to it by index,
+/// which takes a single byte as long as the chunk holds at most 256 kinds of blocks.
+/// Flags are not stored, they are rebuilt from the blueprints when decoding.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PalettedChunk {
+    pub palette: Vec<(BlockId, BlockState)>,
+    pub indices: PaletteIndices,
+    pub light: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PaletteIndices {
+    Byte(Vec<u8>),
+    Short(Vec<u16>),
+}
+
+impl PalettedChunk {
+    /// `map` translates the id of each block before it's put in the palette.
+    pub fn encode(blocks: &[Block; CHUNK_VOLUME], map: impl Fn(BlockId) -> BlockId) -> Self {
+        let mut palette = vec![];
+        let mut lookup = HashMap::<(BlockId, BlockState), u16>::new();
+        let mut indices = Vec::with_capacity(CHUNK_VOLUME);
+        let mut light = Vec::with_capacity(CHUNK_VOLUME);
+        for block in blocks.iter() {
+            let index = *lookup.entry((block.id, block.state)).or_insert_with(|| {
+                palette.push((map(block.id), block.state));
+                (palette.len() - 1) as u16
+            });
+            indices.push(index);
+            light.push(block.light);
+        }
+        let indices = if palette.len() <= 256 {
+            PaletteIndices::Byte(indices.into_iter().map(|i| i as u8).collect())
+        } else {
+            PaletteIndices::Short(indices)
+        };
+        Self {
+            palette,
+            indices,
+            light,
+        }
+    }
+
+    /// `map` builds the block of each palette entry.
+    /// Returns `None` if the indices or the light don't describe a full chunk.
+    pub fn decode(
+        &self,
+        blocks: &mut [Block; CHUNK_VOLUME],
+        map: impl Fn(BlockId) -> Block,
+    ) -> Option<()> {
+        let palette: Vec<Block> = self
+            .palette
+            .iter()
+            .map(|(id, state)| Block {
+                state: *state,
+                ..map(*id)
+            })
+            .collect();
+        let len = match &self.indices {
+            PaletteIndices::Byte(indices) => indices.len(),
+            PaletteIndices::Short(indices) => indices.len(),
+        };
+        if len != CHUNK_VOLUME || self.light.len() != CHUNK_VOLUME {
+            return None;
+        }
+        for (i, block) in blocks.iter_mut().enumerate() {
+            let index = match &self.indices {
+                PaletteIndices::Byte(indices) => indices[i] as usize,
+                PaletteIndices::Short(indices) => indices[i] as usize,
+            };
+            *block = *palette.get(index)?;
+            block.light = self.light[i];
+        }
+        Some(())
+    }
+}
+
+/// Names of the blocks a level was saved with, by the id used in its save file.
+///
+/// Saved chunks hold level ids. They are translated to the ids of the loaded blueprints
+/// when read and back when written, so renumbering the blueprints doesn't corrupt a level.
+/// Blocks whose blueprint is gone are loaded as air.
+/// Names saved before content packs had no namespace, they belong to the base pack.
+#[derive(Debug, Clone, Default)]
+pub struct LevelPalette {
+    names: BTreeMap<BlockId, String>,
+    to_level: HashMap<BlockId, BlockId>,
+    to_runtime: HashMap<BlockId, Block>,
+}
+
+impl LevelPalette {
+    /// `names` is the mapping stored in the level, empty for a new level.
+    /// Blueprints the level doesn't know about get a new level id, their own if it's free.
+    pub fn new(
+        names: Vec<(BlockId, String)>,
+        blueprints: &BlueprintList<BlockId, BlockBlueprint>,
+    ) -> Self {
+        let mut palette = Self {
+            names: names
+                .into_iter()
+                .map(|(id, name)| (id, qualified_name(&name).into_owned()))
+                .collect(),
+            ..Default::default()
+        };
+        let name2level: HashMap<String, BlockId> = palette
+            .names
+            .iter()
+            .map(|(id, name)| (name.clone(), *id))
+            .collect();
+
+        // sorted so that new level ids are assigned deterministically
+        let mut blueprints: Vec<&BlockBlueprint> = blueprints.iter().collect();
+        blueprints.sort_by_key(|bp| bp.id);
+        for bp in blueprints {
+            let level_id = if let Some(id) = name2level.get(&bp.name) {
+                *id
+            } else {
+                let id = palette.free_id(bp.id);
+                palette.names.insert(id, bp.name.clone());
+                id
+            };
+            palette.to_level.insert(bp.id, level_id);
+            palette.to_runtime.insert(level_id, Block::new(bp));
+        }
+        palette
+    }
+
+    fn free_id(&self, preferred: BlockId) -> BlockId {
+        if !self.names.contains_key(&preferred) {
+            return preferred;
+        }
+        (0..=u16::MAX)
+            .map(BlockId::from)
+            .find(|id| !self.names.contains_key(id))
+            .expect("the level has used every block id")
+    }
+
+    /// The mapping to be stored in the level.
+    pub fn names(&self) -> Vec<(BlockId, String)> {
+        self.names
+            .iter()
+            .map(|(id, name)| (*id, name.clone()))
+            .collect()
+    }
+
+    /// Names stored in the level that have no loaded blueprint.
+    pub fn missing(&self) -> impl Iterator<Item = &String> {
+        self.names
+            .iter()
+            .filter(|(id, _)| !self.to_runtime.contains_key(*id))
+            .map(|(_, name)| name)
+    }
+
+    pub fn to_level(&self, id: BlockId) -> BlockId {
+        self.to_level.get(&id).copied().unwrap_or_default()
+    }
+
+    pub fn to_runtime(&self, level_id: BlockId) -> Block {
+        self.to_runtime.get(&level_id).copied().unwrap_or_default()
+    }
+}
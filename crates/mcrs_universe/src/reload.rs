@@ -0,0 +1,145 @@
+use std::{fs, time::SystemTime};
+
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{
+    block::{BlockId, FlagBank},
+    journal::ChangeKind,
+    universe::Universe,
+    Blueprints, CHUNK_SIDE,
+};
+
+/// How often the blueprint files are checked for changes.
+const BLUEPRINTS_POLL_SECONDS: f32 = 1.0;
+
+/// Reloads `Blueprints` when a content pack changes, is added or is removed.
+/// The files are polled because the asset server isn't available when running as a server.
+pub struct BlueprintsReloadPlugin {
+    pub packs_path: String,
+}
+
+impl Plugin for BlueprintsReloadPlugin {
+    fn build(&self, app: &mut App) {
+        let mut watcher = BlueprintsWatcher {
+            packs_path: self.packs_path.clone(),
+            modified: None,
+            timer: Timer::from_seconds(BLUEPRINTS_POLL_SECONDS, TimerMode::Repeating),
+        };
+        watcher.modified = watcher.last_modified();
+        app.insert_resource(watcher)
+            .add_event::<BlueprintsReloadedEvent>()
+            .add_systems(Update, reload_blueprints);
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct BlueprintsWatcher {
+    pub packs_path: String,
+    modified: Option<SystemTime>,
+    timer: Timer,
+}
+
+impl BlueprintsWatcher {
+    /// The directory itself changes when a pack is added or removed.
+    fn last_modified(&self) -> Option<SystemTime> {
+        let files = fs::read_dir(&self.packs_path)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()));
+        files
+            .chain([self.packs_path.clone().into()])
+            .filter_map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+            .max()
+    }
+}
+
+/// Sent after new `Blueprints` have been swapped in.
+#[derive(Event, Debug, Clone)]
+pub struct BlueprintsReloadedEvent;
+
+pub fn reload_blueprints(
+    time: Res<Time>,
+    mut watcher: ResMut<BlueprintsWatcher>,
+    mut bp: ResMut<Blueprints>,
+    universe: Res<Universe>,
+    mut event: EventWriter<BlueprintsReloadedEvent>,
+) {
+    if !watcher.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let modified = watcher.last_modified();
+    if modified == watcher.modified {
+        return;
+    }
+    watcher.modified = modified;
+
+    // A broken file is reported and the previous blueprints are kept,
+    // saving again after fixing it triggers another reload.
+    match Blueprints::load(&watcher.packs_path) {
+        Ok(new_bp) => {
+            // the loaded chunks and the level palette hold the current ids
+            let renumbered = renumbered_blocks(&bp, &new_bp);
+            if !renumbered.is_empty() {
+                warn!(
+                    "blueprints not reloaded, restart to remove or renumber blocks: {}",
+                    renumbered.join(", ")
+                );
+                return;
+            }
+            let old_bp = std::mem::replace(&mut *bp, new_bp);
+            refresh_block_flags(&universe, &old_bp, &bp);
+            info!("blueprints reloaded");
+            event.send(BlueprintsReloadedEvent);
+        }
+        Err(err) => warn!("blueprints not reloaded: {}", err),
+    }
+}
+
+/// Names of the blocks of `old` that `new` removes or gives another id.
+/// A reload can only add blocks and change their properties.
+pub fn renumbered_blocks(old: &Blueprints, new: &Blueprints) -> Vec<String> {
+    let mut names: Vec<String> = old
+        .blocks
+        .iter()
+        .filter(|block| new.blocks.id_named_checked(&block.name) != Some(&block.id))
+        .map(|block| block.name.clone())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Copies the flags of `new` in the loaded blocks whose flags differ from `old`.
+/// Only the chunks holding such blocks are bumped, so their meshes and the gpu copy are rebuilt.
+pub fn refresh_block_flags(universe: &Universe, old: &Blueprints, new: &Blueprints) {
+    let changed: HashMap<BlockId, FlagBank> = new
+        .blocks
+        .iter()
+        .filter(|block_bp| {
+            old.blocks
+                .get_checked(&block_bp.id)
+                .is_some_and(|old_bp| old_bp.block_flags() != block_bp.block_flags())
+        })
+        .map(|block_bp| (block_bp.id, block_bp.block_flags()))
+        .collect();
+    if changed.is_empty() {
+        return;
+    }
+
+    let max = IVec3::splat(CHUNK_SIDE as i32 - 1);
+    for (chunk_pos, chunk) in universe.chunks.iter() {
+        if !chunk.any_block(|block| changed.contains_key(&block.id)) {
+            continue;
+        }
+        let edited = chunk.edit(IVec3::ZERO, max, |_, block| match changed.get(&block.id) {
+            Some(flags) if block.properties != *flags => {
+                block.properties = *flags;
+                true
+            }
+            _ => false,
+        });
+        if edited > 0 {
+            universe.journal.record_chunk(chunk_pos, ChangeKind::Block);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+
+use bevy::{prelude::*, utils::HashMap};
+use miniz_oxide::{deflate::compress_to_vec, inflate::decompress_to_vec_with_limit};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block::{Block, BlockId},
+    edit::{BlockVolume, VolumeTooLarge},
+    universe::Universe,
+    BlueprintError, Blueprints,
+};
+
+/// Bound on the decompressed size of a structure file.
+const MAX_STRUCTURE_BYTES: usize = 64 * 1024 * 1024;
+
+/// A box of blocks saved independently of the ids of the loaded blueprints.
+///
+/// Blocks are recorded by name and their state by the values of its properties,
+/// so a structure survives the blueprints being renumbered or gaining properties.
+/// Light isn't recorded, see `Structure::paste`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Structure {
+    pub size: [i32; 3],
+    pub palette: Vec<StructureBlock>,
+    /// Index in `palette` of each block, in the order of `BlockVolume::iter`.
+    pub indices: Vec<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StructureBlock {
+    pub name: String,
+    /// Value of each state property, by property name.
+    pub state: Vec<(String, String)>,
+}
+
+/// How a structure is placed, mirroring is applied before the rotation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StructureTransform {
+    /// Clockwise quarter turns around the y axis, looking down.
+    pub quarter_turns: u8,
+    pub mirror_x: bool,
+    pub mirror_z: bool,
+    /// Air blocks leave the blocks they are pasted on untouched.
+    pub skip_air: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StructureError {
+    Io { path: String, message: String },
+    Decode(String),
+    TooManyBlocks(usize),
+    TooLarge(VolumeTooLarge),
+    Blueprint(BlueprintError),
+}
+
+impl Display for StructureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, message } => write!(f, "{}: {}", path, message),
+            Self::Decode(message) => write!(f, "the structure is corrupted: {}", message),
+            Self::TooManyBlocks(count) => write!(
+                f,
+                "the structure has {} kinds of blocks, at most {} are supported",
+                count,
+                u16::MAX as usize + 1
+            ),
+            Self::TooLarge(err) => write!(f, "{}", err),
+            Self::Blueprint(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for StructureError {}
+
+impl From<VolumeTooLarge> for StructureError {
+    fn from(err: VolumeTooLarge) -> Self {
+        Self::TooLarge(err)
+    }
+}
+
+impl From<BlueprintError> for StructureError {
+    fn from(err: BlueprintError) -> Self {
+        Self::Blueprint(err)
+    }
+}
+
+impl Structure {
+    pub fn from_volume(volume: &BlockVolume, bp: &Blueprints) -> Result<Self, StructureError> {
+        let mut palette = vec![];
+        let mut lookup = HashMap::<(BlockId, u16), u16>::new();
+        let mut indices = Vec::with_capacity(volume.blocks().len());
+        for block in volume.blocks() {
+            let key = (block.id, *block.state);
+            let index = match lookup.get(&key) {
+                Some(index) => *index,
+                None => {
+                    if palette.len() > u16::MAX as usize {
+                        return Err(StructureError::TooManyBlocks(palette.len() + 1));
+                    }
+                    let block_bp = bp.blocks.get(&block.id)?;
+                    let state = block_bp
+                        .states
+                        .iter()
+                        .filter_map(|property| {
+                            let value = block_bp.get_state_value(block.state, &property.name)?;
+                            Some((property.name.clone(), value.to_string()))
+                        })
+                        .collect();
+                    palette.push(StructureBlock {
+                        name: block_bp.name.clone(),
+                        state,
+                    });
+                    let index = (palette.len() - 1) as u16;
+                    lookup.insert(key, index);
+                    index
+                }
+            };
+            indices.push(index);
+        }
+        Ok(Self {
+            size: volume.size.to_array(),
+            palette,
+            indices,
+        })
+    }
+
+    /// Copies the box between two opposite corners, both included.
+    pub fn from_universe(
+        universe: &Universe,
+        corner0: IVec3,
+        corner1: IVec3,
+        bp: &Blueprints,
+    ) -> Result<Self, StructureError> {
+        Self::from_volume(&universe.copy_box(corner0, corner1)?, bp)
+    }
+
+    /// The size once transformed.
+    pub fn transformed_size(&self, transform: &StructureTransform) -> IVec3 {
+        let [x, y, z] = self.size;
+        if transform.quarter_turns % 2 == 1 {
+            IVec3::new(z, y, x)
+        } else {
+            IVec3::new(x, y, z)
+        }
+    }
+
+    /// Builds the blocks with the loaded blueprints.
+    /// Fails if a block is unknown, unknown state properties and values are ignored.
+    pub fn to_volume(
+        &self,
+        bp: &Blueprints,
+        transform: &StructureTransform,
+    ) -> Result<BlockVolume, StructureError> {
+        let size = IVec3::from_array(self.size);
+        // the size is read from the file, it may be anything
+        if size.cmplt(IVec3::ZERO).any() || self.indices.len() != BlockVolume::block_count(size)? {
+            return Err(StructureError::Decode(
+                "the size doesn't match the blocks".to_string(),
+            ));
+        }
+
+        let palette = self
+            .palette
+            .iter()
+            .map(|structure_block| structure_block.to_block(bp, transform))
+            .collect::<Result<Vec<Block>, _>>()?;
+
+        let source = BlockVolume::filled(size, Block::default())?;
+        let mut volume = BlockVolume::filled(self.transformed_size(transform), Block::default())?;
+        for (xyz, index) in source.iter().zip(self.indices.iter()) {
+            let block = *palette.get(*index as usize).ok_or_else(|| {
+                StructureError::Decode(format!("the palette has no block {}", index))
+            })?;
+            volume.set(transform.apply(xyz, size), block);
+        }
+        Ok(volume)
+    }
+
+    /// Pastes the structure with its lowest corner at `origin`, see `Universe::edit_box`.
+    /// Returns the number of blocks whose kind or state changed.
+    ///
+    /// The pasted blocks keep the light of the blocks they replace and the sun beams aren't
+    /// cut, like the other bulk edits the lighting of the box is left to the caller.
+    pub fn paste(
+        &self,
+        universe: &Universe,
+        origin: IVec3,
+        bp: &Blueprints,
+        transform: &StructureTransform,
+    ) -> Result<usize, StructureError> {
+        let volume = self.to_volume(bp, transform)?;
+        if volume.size.cmple(IVec3::ZERO).any() {
+            return Ok(0);
+        }
+        let air = bp.air().id;
+        let changed =
+            universe.edit_box(
+                origin,
+                origin + volume.size - IVec3::ONE,
+                |pos, old| match volume.get(pos - origin) {
+                    Some(block)
+                        if !(transform.skip_air && block.id == air)
+                            && (old.id, old.state) != (block.id, block.state) =>
+                    {
+                        *old = Block {
+                            light: old.light,
+                            ..block
+                        };
+                        true
+                    }
+                    _ => false,
+                },
+            );
+        Ok(changed)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let bytes = bincode::serialize(self).expect("failed to serialize structure");
+        compress_to_vec(&bytes, 6)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StructureError> {
+        let bytes = decompress_to_vec_with_limit(bytes, MAX_STRUCTURE_BYTES)
+            .map_err(|err| StructureError::Decode(format!("{:?}", err.status)))?;
+        bincode::deserialize(&bytes).map_err(|err| StructureError::Decode(err.to_string()))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), StructureError> {
+        fs::write(path, self.to_bytes()).map_err(|err| StructureError::Io {
+            path: path.to_string(),
+            message: err.to_string(),
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Self, StructureError> {
+        let bytes = fs::read(path).map_err(|err| StructureError::Io {
+            path: path.to_string(),
+            message: err.to_string(),
+        })?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl StructureBlock {
+    /// Odd quarter turns swap the `x` and `z` values of the `axis` property.
+    fn to_block(
+        &self,
+        bp: &Blueprints,
+        transform: &StructureTransform,
+    ) -> Result<Block, StructureError> {
+        let block_bp = bp.blocks.get_named(&self.name)?;
+        let mut block = Block::new(block_bp);
+        for (name, value) in self.state.iter() {
+            let value = match (name.as_str(), value.as_str()) {
+                ("axis", "x") if transform.quarter_turns % 2 == 1 => "z",
+                ("axis", "z") if transform.quarter_turns % 2 == 1 => "x",
+                (_, value) => value,
+            };
+            if let Some(state) = block_bp.with_state_value(block.state, name, value) {
+                block.state = state;
+            }
+        }
+        Ok(block)
+    }
+}
+
+impl StructureTransform {
+    /// Where the block at `xyz` of a structure of `size` ends up.
+    pub fn apply(&self, xyz: IVec3, size: IVec3) -> IVec3 {
+        let mut xyz = xyz;
+        if self.mirror_x {
+            xyz.x = size.x - 1 - xyz.x;
+        }
+        if self.mirror_z {
+            xyz.z = size.z - 1 - xyz.z;
+        }
+        let mut size = size;
+        for _ in 0..self.quarter_turns % 4 {
+            xyz = IVec3::new(size.z - 1 - xyz.z, xyz.y, xyz.x);
+            size = IVec3::new(size.z, size.y, size.x);
+        }
+        xyz
+    }
+}
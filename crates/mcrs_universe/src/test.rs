@@ -1,11 +1,27 @@
-use crate::block::{BlockFlag, FlagBank};
+use bevy::math::IVec3;
+
+use crate::{
+    block::{
+        Block, BlockBlueprint, BlockFlag, BlockId, BlockMaterial, BlockMaterials, BlockState,
+        BlockStateProperty, FlagBank, LightType,
+    },
+    block_entity::BlockEntity,
+    chunk::Chunk,
+    edit::BlockVolume,
+    journal::{ChangeKind, JournalRead, JournalReader, JOURNAL_KEPT_TICKS},
+    pack::PackSource,
+    palette::{LevelPalette, PaletteIndices, PalettedChunk},
+    reload::{refresh_block_flags, renumbered_blocks},
+    structure::{Structure, StructureError, StructureTransform},
+    universe::Universe,
+    BlueprintError, BlueprintList, Blueprints,
+};
 
 #[test]
 fn smoke_test_flag_bank() {
-    assert_eq!(
-        BlockFlag::iter().count(),
-        8,
-        "BlockFlags hasn't exactly 8 variants: {:?}",
+    assert!(
+        BlockFlag::iter().count() <= 8,
+        "BlockFlags has more variants than the 8 bits of FlagBank: {:?}",
         BlockFlag::iter()
             .map(|f| f.to_string())
             .collect::<Vec<String>>()
@@ -20,3 +36,540 @@ fn smoke_test_flag_bank() {
     assert_eq!(Some(&BlockFlag::Opaque), flags_iter.next());
     assert_eq!(None, flags_iter.next());
 }
+
+#[test]
+fn paletted_chunk_round_trip() {
+    let chunk = Chunk::empty();
+    {
+        let mut blocks = chunk.get_mut();
+        for (i, block) in blocks.iter_mut().enumerate() {
+            block.id = BlockId::from((i % 3) as u16 * 300);
+            block.state = BlockState::from((i % 2) as u16);
+            block.set_light(LightType::Torch, (i % 16) as u8);
+            block.set_light(LightType::Sun, 15 - (i % 16) as u8);
+        }
+    }
+
+    let paletted = PalettedChunk::encode(&chunk.get_ref(), |id| id);
+    assert_eq!(paletted.palette.len(), 6);
+    assert!(matches!(paletted.indices, PaletteIndices::Byte(_)));
+
+    let decoded = Chunk::empty();
+    paletted
+        .decode(&mut decoded.get_mut(), |id| Block {
+            id,
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(chunk.get_ref().as_ref(), decoded.get_ref().as_ref());
+}
+
+#[test]
+fn level_palette_survives_renumbering() {
+    let blueprint = |name: &str, id: u16| BlockBlueprint {
+        name: format!("base:{}", name),
+        id: id.into(),
+        ..Default::default()
+    };
+    let saved = LevelPalette::new(
+        vec![],
+        &BlueprintList::from_list(vec![
+            blueprint("Air", 0),
+            blueprint("Stone", 1),
+            blueprint("Dirt", 2),
+        ]),
+    );
+    let stone = saved.to_level(1.into());
+
+    let renumbered = BlueprintList::from_list(vec![
+        blueprint("Air", 0),
+        blueprint("Dirt", 1),
+        blueprint("Stone", 2),
+        blueprint("Sand", 3),
+    ]);
+    let loaded = LevelPalette::new(saved.names(), &renumbered);
+    assert_eq!(loaded.to_runtime(stone).id, 2.into());
+    assert_eq!(loaded.to_level(2.into()), stone);
+
+    let sand = loaded.to_level(3.into());
+    assert!(saved.names().iter().all(|(id, _)| *id != sand));
+    assert_eq!(loaded.to_runtime(sand).id, 3.into());
+}
+
+#[test]
+fn reload_keeps_the_block_ids() {
+    let blueprint = |name: &str, id: u16| BlockBlueprint {
+        name: format!("base:{}", name),
+        id: id.into(),
+        ..Default::default()
+    };
+    let blueprints = |blocks| {
+        Blueprints::new(
+            BlueprintList::from_list(blocks),
+            BlueprintList::from_list(vec![]),
+        )
+    };
+    let old = blueprints(vec![
+        blueprint("Air", 0),
+        blueprint("Stone", 1),
+        blueprint("Dirt", 2),
+    ]);
+
+    let added = blueprints(vec![
+        blueprint("Air", 0),
+        blueprint("Stone", 1),
+        blueprint("Dirt", 2),
+        blueprint("Sand", 3),
+    ]);
+    assert!(renumbered_blocks(&old, &added).is_empty());
+
+    let swapped = blueprints(vec![
+        blueprint("Air", 0),
+        blueprint("Dirt", 1),
+        blueprint("Stone", 2),
+    ]);
+    assert_eq!(
+        renumbered_blocks(&old, &swapped),
+        vec!["base:Dirt".to_string(), "base:Stone".to_string()]
+    );
+
+    let removed = blueprints(vec![blueprint("Air", 0), blueprint("Stone", 1)]);
+    assert_eq!(
+        renumbered_blocks(&old, &removed),
+        vec!["base:Dirt".to_string()]
+    );
+}
+
+#[test]
+fn reload_refreshes_only_the_changed_flags() {
+    let blueprints = |stone_flags: FlagBank| {
+        let blocks = vec![
+            BlockBlueprint {
+                name: "base:Air".to_string(),
+                id: 0.into(),
+                ..Default::default()
+            },
+            BlockBlueprint {
+                name: "base:Stone".to_string(),
+                id: 1.into(),
+                flags: stone_flags,
+                ..Default::default()
+            },
+        ];
+        Blueprints::new(
+            BlueprintList::from_list(blocks),
+            BlueprintList::from_list(vec![]),
+        )
+    };
+    let old = blueprints(FlagBank::default());
+    let new = blueprints(FlagBank::from(vec![BlockFlag::Collidable]));
+
+    let universe = Universe::default();
+    let stone = Block {
+        id: 1.into(),
+        properties: old.blocks.get(&1.into()).unwrap().block_flags(),
+        ..Default::default()
+    };
+    let (stone_pos, air_pos) = (IVec3::ZERO, IVec3::new(32, 0, 0));
+    universe.chunks.insert(stone_pos, Chunk::filled(stone));
+    universe.chunks.insert(air_pos, Chunk::empty());
+    let stone_chunk = universe.chunks.get(&stone_pos).unwrap();
+    let air_chunk = universe.chunks.get(&air_pos).unwrap();
+    let (stone_version, air_version) = (stone_chunk.version(), air_chunk.version());
+
+    refresh_block_flags(&universe, &old, &new);
+    assert!(stone_chunk
+        .read_block(IVec3::ZERO)
+        .properties
+        .check(BlockFlag::Collidable));
+    assert!(!stone_chunk.is_dense());
+    assert_eq!(stone_chunk.version().bumps_since(&stone_version), 1);
+    assert_eq!(air_chunk.version(), air_version);
+    assert!(universe.journal.pending(&air_pos).is_none());
+
+    refresh_block_flags(&universe, &new, &new);
+    assert_eq!(stone_chunk.version().bumps_since(&stone_version), 1);
+}
+
+#[test]
+fn block_state_values() {
+    let property = |name: &str, values: &[&str]| BlockStateProperty {
+        name: name.to_string(),
+        values: values.iter().map(|v| v.to_string()).collect(),
+    };
+    let bp = BlockBlueprint {
+        states: vec![
+            property("axis", &["y", "x", "z"]),
+            property("open", &["false", "true"]),
+        ],
+        ..Default::default()
+    };
+    assert_eq!(bp.state_count(), 6);
+
+    let state = BlockState::default();
+    assert_eq!(bp.get_state_value(state, "axis"), Some("y"));
+    assert_eq!(bp.get_state_value(state, "open"), Some("false"));
+
+    let state = bp.with_state_value(state, "open", "true").unwrap();
+    let state = bp.with_state_value(state, "axis", "z").unwrap();
+    assert!(bp.is_valid_state(state));
+    assert_eq!(bp.get_state_value(state, "axis"), Some("z"));
+    assert_eq!(bp.get_state_value(state, "open"), Some("true"));
+
+    assert_eq!(bp.with_state_value(state, "axis", "w"), None);
+    assert_eq!(bp.get_state_value(state, "color"), None);
+    assert!(!bp.is_valid_state(BlockState::from(6)));
+}
+
+#[test]
+fn block_entity_follows_its_block() {
+    let chunk = Chunk::empty();
+    let xyz = IVec3::new(1, 2, 3);
+    let chest = Block {
+        id: BlockId::from(10),
+        ..Default::default()
+    };
+    chunk.set_block(xyz, chest);
+
+    let version = chunk.version();
+    let items = vec!["Stone".to_string(), "Dirt".to_string()];
+    chunk.set_block_entity(xyz, BlockEntity::new("Chest", &items).unwrap());
+    assert_ne!(version, chunk.version());
+    let entity = chunk.read_block_entity(xyz).unwrap();
+    assert_eq!(entity.read::<Vec<String>>().unwrap(), items);
+
+    // changing only the state keeps the entity
+    chunk.set_block(
+        xyz,
+        Block {
+            state: BlockState::from(1),
+            ..chest
+        },
+    );
+    assert!(chunk.read_block_entity(xyz).is_some());
+
+    chunk.set_block(xyz, Block::default());
+    assert!(chunk.read_block_entity(xyz).is_none());
+}
+
+#[test]
+fn material_flags_and_lookup() {
+    let sand = BlockBlueprint {
+        name: "Sand".to_string(),
+        id: 12.into(),
+        flags: FlagBank::from(vec![BlockFlag::Collidable, BlockFlag::Translucent]),
+        material: BlockMaterial {
+            friction: 0.5,
+            gravity: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let flags: Vec<BlockFlag> = Block::new(&sand).properties.into();
+    assert_eq!(
+        flags,
+        vec![BlockFlag::Collidable, BlockFlag::Opaque, BlockFlag::Gravity]
+    );
+
+    let materials = BlockMaterials::new([sand].iter());
+    assert_eq!(materials.get(12.into()).friction, 0.5);
+    assert_eq!(materials.get(3.into()), &BlockMaterial::default());
+    assert_eq!(materials.get(300.into()), &BlockMaterial::default());
+}
+
+#[test]
+fn blueprint_errors_point_at_the_source() {
+    let pack = |source: &str| PackSource::from_ron("base.ron", source);
+    let source = r#"(
+    namespace: "base",
+    blocks: [
+        (name: "Air", id: 0),
+        (name: "Stone", id: 1),
+        (name: "Stone", id: 2),
+    ],
+)"#;
+    let err = Blueprints::from_packs(vec![pack(source).unwrap()]).unwrap_err();
+    assert_eq!(
+        err,
+        BlueprintError::DuplicateName {
+            path: "base.ron".to_string(),
+            line: 6,
+            name: "base:Stone".to_string(),
+        }
+    );
+
+    let source =
+        "(namespace: \"base\", blocks: [\n(name: \"Air\", id: 0),\n(name: \"Stone\", id: 0)])";
+    let err = Blueprints::from_packs(vec![pack(source).unwrap()]).unwrap_err();
+    assert!(matches!(err, BlueprintError::DuplicateId { line: 3, .. }));
+
+    let err = pack("(namespace: \"base\", blocks: [(name: 1)])").unwrap_err();
+    assert!(matches!(err, BlueprintError::Parse { line: 1, .. }));
+}
+
+#[test]
+fn blueprints_require_air() {
+    let source = r#"(namespace: "base", blocks: [(name: "Stone", id: 0)])"#;
+    let base = PackSource::from_ron("base.ron", source).unwrap();
+    assert_eq!(
+        Blueprints::from_packs(vec![base]).unwrap_err(),
+        BlueprintError::MissingAir
+    );
+
+    let blocks = BlueprintList::from_list(vec![]);
+    let blueprints = Blueprints::new(blocks, BlueprintList::from_list(vec![]));
+    assert_eq!(
+        blueprints.blocks.get_named("Air").unwrap_err(),
+        BlueprintError::UnknownName("base:Air".to_string())
+    );
+}
+
+#[test]
+fn content_packs_merge_by_namespace() {
+    let base = r#"(namespace: "base", blocks: [(name: "Air", id: 0), (name: "Stone", id: 1)])"#;
+    let farming = r#"(namespace: "farming", blocks: [(name: "Wheat", id: 2)])"#;
+    let pack = |path: &str, source: &str| PackSource::from_ron(path, source).unwrap();
+
+    let blueprints =
+        Blueprints::from_packs(vec![pack("farming.ron", farming), pack("base.ron", base)]).unwrap();
+    assert_eq!(blueprints.packs, vec!["base", "farming"]);
+    assert_eq!(blueprints.blocks.id_named("Stone"), Ok(1.into()));
+    assert_eq!(blueprints.blocks.id_named("base:Stone"), Ok(1.into()));
+    assert_eq!(blueprints.blocks.id_named("farming:Wheat"), Ok(2.into()));
+    assert!(blueprints.blocks.id_named("Wheat").is_err());
+
+    let clash = r#"(namespace: "more", blocks: [(name: "Stone", id: 1)])"#;
+    let err =
+        Blueprints::from_packs(vec![pack("base.ron", base), pack("more.ron", clash)]).unwrap_err();
+    assert!(matches!(err, BlueprintError::DuplicateId { ref path, .. } if path == "more.ron"));
+
+    let err = Blueprints::from_packs(vec![pack("a.ron", base), pack("b.ron", base)]).unwrap_err();
+    assert!(matches!(err, BlueprintError::DuplicateNamespace { .. }));
+}
+
+#[test]
+fn journal_records_changed_blocks() {
+    let mut universe = Universe::default();
+    universe.chunks.insert(IVec3::ZERO, Chunk::empty());
+    let stone = Block {
+        id: BlockId::from(1),
+        ..Default::default()
+    };
+    let mut reader = JournalReader::default();
+
+    universe.set_chunk_block(&IVec3::new(1, 2, 3), stone);
+    universe.set_chunk_block(&IVec3::new(4, 5, 6), Block::default());
+    let lit = Block {
+        light: 0xf0,
+        ..Default::default()
+    };
+    universe.set_chunk_block(&IVec3::new(33, 0, 0), lit);
+    // not readable until the tick is closed
+    let JournalRead::Changes(changes) = universe.journal.read(&mut reader) else {
+        panic!("the reader didn't fall behind");
+    };
+    assert!(changes.is_empty());
+    universe.journal.next_tick();
+
+    let JournalRead::Changes(changes) = universe.journal.read(&mut reader) else {
+        panic!("the reader didn't fall behind");
+    };
+    assert_eq!(changes.len(), 2);
+    let changed = &changes[&IVec3::ZERO];
+    assert_eq!(
+        changed.blocks.iter().collect::<Vec<_>>(),
+        [&IVec3::new(1, 2, 3)]
+    );
+    assert!(!changed.is_light_only());
+    // the unchanged block didn't bump the version, every bump is journaled
+    let chunk_version = universe.chunks.get(&IVec3::ZERO).unwrap().version();
+    assert_eq!(changed.versions, 1);
+    assert_eq!(
+        chunk_version.bumps_since(&Chunk::empty().version()),
+        changed.versions
+    );
+    let lit_chunk = &changes[&IVec3::new(32, 0, 0)];
+    assert_eq!(
+        lit_chunk.light.iter().collect::<Vec<_>>(),
+        [&IVec3::new(1, 0, 0)]
+    );
+    assert!(lit_chunk.is_light_only());
+
+    // ticks are merged for a reader that skipped some
+    universe
+        .journal
+        .record_chunk(IVec3::ZERO, ChangeKind::Light);
+    universe.journal.next_tick();
+    universe.journal.next_tick();
+    let JournalRead::Changes(changes) = universe.journal.read(&mut reader) else {
+        panic!("the reader didn't fall behind");
+    };
+    assert_eq!(changes[&IVec3::ZERO].untracked, Some(ChangeKind::Light));
+    assert!(changes[&IVec3::ZERO].is_light_only());
+
+    for _ in 0..=JOURNAL_KEPT_TICKS {
+        universe.journal.next_tick();
+    }
+    assert!(matches!(
+        universe.journal.read(&mut reader),
+        JournalRead::Lost
+    ));
+    assert!(matches!(
+        universe.journal.read(&mut reader),
+        JournalRead::Changes(changes) if changes.is_empty()
+    ));
+}
+
+#[test]
+fn bulk_edits_bump_each_chunk_once() {
+    let universe = Universe::default();
+    for x in [0, 32] {
+        universe.chunks.insert(IVec3::new(x, 0, 0), Chunk::empty());
+    }
+    let stone = Block {
+        id: BlockId::from(1),
+        ..Default::default()
+    };
+    let dirt = Block {
+        id: BlockId::from(2),
+        ..Default::default()
+    };
+    let versions = |universe: &Universe| {
+        [IVec3::ZERO, IVec3::new(32, 0, 0)].map(|pos| universe.chunks.get(&pos).unwrap().version())
+    };
+    let bumped_once = Chunk::empty();
+    bumped_once.update_version();
+    let expected = [bumped_once.version(), bumped_once.version()];
+
+    // the box spans both chunks and an unloaded one
+    let changed = universe.fill_box(IVec3::new(30, 0, 0), IVec3::new(33, 1, -1), stone);
+    assert_eq!(changed, 8);
+    assert_eq!(versions(&universe), expected);
+    assert_eq!(
+        universe.read_chunk_block(&IVec3::new(33, 1, 0)),
+        Some(stone)
+    );
+    assert_eq!(
+        universe.read_chunk_block(&IVec3::new(34, 1, 0)),
+        Some(Block::default())
+    );
+
+    let changed = universe.replace_in_box(IVec3::ZERO, IVec3::new(31, 0, 0), stone.id, dirt);
+    assert_eq!(changed, 2);
+    assert_eq!(
+        universe.fill_box(IVec3::ZERO, IVec3::new(29, 0, 0), Block::default()),
+        0
+    );
+
+    let copy = universe
+        .copy_box(IVec3::new(30, 0, 0), IVec3::new(33, 1, 0))
+        .unwrap();
+    assert_eq!(copy.size, IVec3::new(4, 2, 1));
+    assert_eq!(copy.get(IVec3::ZERO), Some(dirt));
+    assert_eq!(copy.get(IVec3::new(3, 1, 0)), Some(stone));
+    assert_eq!(universe.paste(IVec3::new(0, 10, 0), &copy), 8);
+    assert_eq!(
+        universe.copy_box(IVec3::new(0, 10, 0), IVec3::new(3, 11, 0)),
+        Ok(copy)
+    );
+
+    let volume = BlockVolume::filled(IVec3::ONE, stone).unwrap();
+    assert_eq!(volume.iter().count(), 1);
+    assert!(BlockVolume::filled(IVec3::splat(i32::MAX), stone).is_err());
+    assert!(universe.copy_box(IVec3::MIN, IVec3::MAX).is_err());
+    assert_eq!(universe.fill_sphere(IVec3::splat(16), 2.0, stone), 33);
+}
+
+#[test]
+fn structure_round_trip_with_rotation() {
+    let base = r#"(namespace: "base", blocks: [
+        (name: "Air", id: 0),
+        (name: "Wood", id: 1, states: [(name: "axis", values: ["y", "x", "z"])]),
+    ])"#;
+    let bp = Blueprints::from_packs(vec![PackSource::from_ron("base.ron", base).unwrap()]).unwrap();
+    let wood_bp = bp.blocks.get_named("Wood").unwrap();
+    let mut log = Block::new(wood_bp);
+    log.state = wood_bp.with_state_value(log.state, "axis", "x").unwrap();
+
+    let universe = Universe::default();
+    universe.chunks.insert(IVec3::ZERO, Chunk::empty());
+    universe.fill_box(IVec3::ZERO, IVec3::new(2, 0, 0), log);
+
+    let structure =
+        Structure::from_universe(&universe, IVec3::ZERO, IVec3::new(2, 1, 0), &bp).unwrap();
+    let structure = Structure::from_bytes(&structure.to_bytes()).unwrap();
+    assert_eq!(structure.size, [3, 2, 1]);
+    assert_eq!(structure.palette.len(), 2);
+
+    let turned = StructureTransform {
+        quarter_turns: 1,
+        skip_air: true,
+        ..Default::default()
+    };
+    let origin = IVec3::new(10, 0, 10);
+    let changed = structure.paste(&universe, origin, &bp, &turned).unwrap();
+    assert_eq!(changed, 3);
+    for z in 0..3 {
+        let block = universe
+            .read_chunk_block(&(origin + IVec3::new(0, 0, z)))
+            .unwrap();
+        assert_eq!(block.id, wood_bp.id);
+        assert_eq!(wood_bp.get_state_value(block.state, "axis"), Some("z"));
+    }
+
+    // pasting it again changes nothing, the light of the pasted blocks is kept
+    let lit = origin + IVec3::Z;
+    let mut block = universe.read_chunk_block(&lit).unwrap();
+    block.set_light(LightType::Sun, 9);
+    universe.set_chunk_block(&lit, block);
+    assert_eq!(structure.paste(&universe, origin, &bp, &turned).unwrap(), 0);
+    let block = universe.read_chunk_block(&lit).unwrap();
+    assert_eq!(block.get_light(LightType::Sun), 9);
+
+    let mirrored = StructureTransform {
+        mirror_x: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        mirrored.apply(IVec3::ZERO, IVec3::new(3, 2, 1)),
+        IVec3::new(2, 0, 0)
+    );
+    assert!(Structure::from_bytes(&[1, 2, 3]).is_err());
+
+    // a corrupt size is refused before anything is allocated
+    let huge = Structure {
+        size: [i32::MAX, i32::MAX, 2],
+        ..structure
+    };
+    assert!(matches!(
+        huge.to_volume(&bp, &StructureTransform::default()),
+        Err(StructureError::TooLarge(_))
+    ));
+}
+
+#[test]
+fn disjoint_chunks_written_in_parallel() {
+    let universe = Universe::default();
+    let stone = Block {
+        id: BlockId::from(1),
+        ..Default::default()
+    };
+    std::thread::scope(|scope| {
+        for x in 0..4 {
+            let universe = &universe;
+            scope.spawn(move || {
+                let chunk_pos = IVec3::new(x * 32, 0, 0);
+                universe.chunks.insert(chunk_pos, Chunk::empty());
+                universe.fill_box(chunk_pos, chunk_pos + IVec3::splat(31), stone);
+            });
+        }
+    });
+    assert_eq!(universe.chunks.len(), 4);
+    for (chunk_pos, chunk) in universe.chunks.iter() {
+        assert_eq!(chunk.read_block(IVec3::ZERO), stone);
+        assert_eq!(
+            universe.journal.pending(&chunk_pos).unwrap().blocks.len(),
+            32 * 32 * 32
+        );
+    }
+}
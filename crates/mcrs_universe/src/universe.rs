@@ -1,25 +1,96 @@
-use std::sync::RwLockWriteGuard;
+use std::sync::Arc;
 
-use crate::{block::Block, chunk::Chunk, CHUNK_SIDE, CHUNK_VOLUME};
+use crate::{
+    block::Block,
+    block_entity::BlockEntity,
+    chunk::Chunk,
+    journal::{ChangeJournal, ChangeKind},
+    CHUNK_SIDE,
+};
 
-use bevy::{prelude::*, utils::HashMap};
+use bevy::prelude::*;
 
 /// Contains the loaded chunk
-#[derive(Resource, Debug, Clone, Default)]
+///
+/// Every method but the journal advance takes `&self`, so systems only need
+/// `Res<Universe>` and can read and write disjoint chunks in parallel.
+#[derive(Resource, Debug, Default)]
 pub struct Universe {
-    pub chunks: HashMap<IVec3, Chunk>,
+    pub chunks: ChunkMap,
+    /// Where the blocks changed in the last ticks.
+    pub journal: ChangeJournal,
 }
 
-impl Universe {
-    // Useful to lock everything when operating on all blocks
-    pub fn lock_write(
-        &mut self,
-    ) -> impl Iterator<Item = (IVec3, RwLockWriteGuard<[Block; CHUNK_VOLUME]>)> {
-        self.chunks
+/// Concurrent map of the loaded chunks, lookups don't take a lock.
+/// Chunks are handed out as `Arc` so they stay valid while being unloaded.
+#[derive(Debug, Default)]
+pub struct ChunkMap(papaya::HashMap<IVec3, Arc<Chunk>>);
+
+impl ChunkMap {
+    pub fn get(&self, chunk_pos: &IVec3) -> Option<Arc<Chunk>> {
+        self.0.pin().get(chunk_pos).cloned()
+    }
+
+    pub fn contains_key(&self, chunk_pos: &IVec3) -> bool {
+        self.0.pin().contains_key(chunk_pos)
+    }
+
+    /// Replaces the chunk at `chunk_pos` if it was loaded.
+    pub fn insert(&self, chunk_pos: IVec3, chunk: impl Into<Arc<Chunk>>) {
+        self.0.pin().insert(chunk_pos, chunk.into());
+    }
+
+    pub fn get_or_insert_with(&self, chunk_pos: IVec3, f: impl FnOnce() -> Chunk) -> Arc<Chunk> {
+        self.0
+            .pin()
+            .get_or_insert_with(chunk_pos, || Arc::new(f()))
+            .clone()
+    }
+
+    pub fn remove(&self, chunk_pos: &IVec3) -> Option<Arc<Chunk>> {
+        self.0.pin().remove(chunk_pos).cloned()
+    }
+
+    pub fn clear(&self) {
+        self.0.pin().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Snapshot of the loaded chunks, chunks loaded or unloaded meanwhile may be missed.
+    pub fn iter(&self) -> impl Iterator<Item = (IVec3, Arc<Chunk>)> {
+        let chunks: Vec<_> = self
+            .0
+            .pin()
             .iter()
-            .map(|(pos, chunk)| (pos.clone(), chunk.get_mut()))
+            .map(|(pos, chunk)| (*pos, chunk.clone()))
+            .collect();
+        chunks.into_iter()
     }
 
+    pub fn positions(&self) -> impl Iterator<Item = IVec3> {
+        let positions: Vec<_> = self.0.pin().keys().copied().collect();
+        positions.into_iter()
+    }
+}
+
+impl FromIterator<(IVec3, Chunk)> for ChunkMap {
+    fn from_iter<T: IntoIterator<Item = (IVec3, Chunk)>>(iter: T) -> Self {
+        Self(
+            iter.into_iter()
+                .map(|(pos, chunk)| (pos, Arc::new(chunk)))
+                .collect(),
+        )
+    }
+}
+
+impl Universe {
     pub fn pos_to_chunk_and_inner(&self, pos: &IVec3) -> (IVec3, IVec3) {
         const CHUNK_SIZE: IVec3 = IVec3::splat(CHUNK_SIDE as i32);
         let chunk_pos = (pos.div_euclid(CHUNK_SIZE)) * CHUNK_SIZE;
@@ -41,14 +112,36 @@ impl Universe {
             .map(|chunk| chunk.read_block(inner_pos))
     }
 
-    pub fn set_chunk_block(&mut self, pos: &IVec3, block: Block) {
+    /// The chunk is compacted again when the journal advances to the next tick.
+    pub fn set_chunk_block(&self, pos: &IVec3, block: Block) {
+        let (chunk_pos, inner_pos) = self.pos_to_chunk_and_inner(pos);
+        let chunk = self.chunks.get_or_insert_with(chunk_pos, Chunk::empty);
+        let old = chunk.read_block(inner_pos);
+        // an unchanged block keeps the version, so every bump is recorded in the journal
+        let Some(kind) = ChangeKind::between(&old, &block) else {
+            return;
+        };
+        chunk.set_block(inner_pos, block);
+        self.journal.record(chunk_pos, inner_pos, kind);
+    }
+
+    pub fn read_block_entity(&self, pos: &IVec3) -> Option<BlockEntity> {
+        let (chunk_pos, inner_pos) = self.pos_to_chunk_and_inner(pos);
+        self.chunks.get(&chunk_pos)?.read_block_entity(inner_pos)
+    }
+
+    /// Returns false if the chunk isn't loaded.
+    pub fn set_block_entity(&self, pos: &IVec3, entity: BlockEntity) -> bool {
+        let (chunk_pos, inner_pos) = self.pos_to_chunk_and_inner(pos);
+        let Some(chunk) = self.chunks.get(&chunk_pos) else {
+            return false;
+        };
+        chunk.set_block_entity(inner_pos, entity);
+        true
+    }
+
+    pub fn remove_block_entity(&self, pos: &IVec3) -> Option<BlockEntity> {
         let (chunk_pos, inner_pos) = self.pos_to_chunk_and_inner(pos);
-        if let Some(chunk) = self.chunks.get_mut(&chunk_pos) {
-            chunk.set_block(inner_pos, block);
-        } else {
-            let mut chunk = Chunk::empty();
-            chunk.set_block(inner_pos, block);
-            self.chunks.insert(chunk_pos, chunk);
-        }
+        self.chunks.get(&chunk_pos)?.remove_block_entity(inner_pos)
     }
 }
@@ -0,0 +1,130 @@
+use std::fmt::{self, Display, Formatter};
+
+use bevy::{prelude::*, utils::HashMap};
+use mcrs_universe::{CHUNK_AREA, CHUNK_SIDE};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum Biome {
+    #[default]
+    Plains,
+    Forest,
+    Desert,
+    Mountains,
+    Ocean,
+    Beach,
+}
+
+impl Biome {
+    pub const ALL: [Biome; 6] = [
+        Biome::Plains,
+        Biome::Forest,
+        Biome::Desert,
+        Biome::Mountains,
+        Biome::Ocean,
+        Biome::Beach,
+    ];
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Self::ALL.get(value as usize).copied()
+    }
+
+    /// Covered in sand instead of grass and dirt.
+    pub fn is_sandy(&self) -> bool {
+        matches!(self, Biome::Desert | Biome::Ocean | Biome::Beach)
+    }
+}
+
+impl Display for Biome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Temperature, humidity and continentalness of a column, each roughly in -1..1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Climate {
+    pub temperature: f64,
+    pub humidity: f64,
+    pub continent: f64,
+}
+
+impl Climate {
+    pub fn biome(&self) -> Biome {
+        if self.continent < -0.25 {
+            Biome::Ocean
+        } else if self.continent < -0.15 {
+            Biome::Beach
+        } else if self.continent > 0.35 {
+            Biome::Mountains
+        } else if self.temperature > 0.25 && self.humidity < 0.0 {
+            Biome::Desert
+        } else if self.humidity > 0.15 {
+            Biome::Forest
+        } else {
+            Biome::Plains
+        }
+    }
+}
+
+/// Noise of the climate of the columns of a level.
+pub struct BiomeNoise {
+    temperature: Fbm<Perlin>,
+    humidity: Fbm<Perlin>,
+    continent: Fbm<Perlin>,
+}
+
+impl BiomeNoise {
+    pub fn new(seed: u32) -> Self {
+        let noise = |seed: u32, frequency: f64| {
+            Fbm::<Perlin>::new(seed)
+                .set_frequency(frequency)
+                .set_octaves(3)
+        };
+        Self {
+            temperature: noise(seed.wrapping_add(1), 0.002),
+            humidity: noise(seed.wrapping_add(2), 0.002),
+            continent: noise(seed.wrapping_add(3), 0.0007),
+        }
+    }
+
+    pub fn climate(&self, xz: IVec2) -> Climate {
+        let point = xz.as_dvec2().to_array();
+        Climate {
+            temperature: self.temperature.get(point),
+            humidity: self.humidity.get(point),
+            continent: self.continent.get(point),
+        }
+    }
+
+    /// Scales the height of the terrain, low in oceans and high in mountains.
+    /// It only depends on the continentalness so it has no steps at the biome borders.
+    pub fn height_scale(&self, xz: IVec2) -> f64 {
+        let continent = self.continent.get(xz.as_dvec2().to_array());
+        if continent < -0.2 {
+            0.6 + (continent + 0.2) * 0.5
+        } else if continent < 0.2 {
+            0.6 + (continent + 0.2)
+        } else {
+            1.0 + (continent - 0.2) * 2.5
+        }
+    }
+}
+
+/// Biome of every generated column, by region like the sun beams.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct Biomes {
+    pub regions: HashMap<IVec2, [Biome; CHUNK_AREA]>,
+}
+
+impl Biomes {
+    pub fn get(&self, xz: IVec2) -> Option<Biome> {
+        const REGION_SIZE: IVec2 = IVec2::splat(CHUNK_SIDE as i32);
+        let region_pos = xz.div_euclid(REGION_SIZE) * REGION_SIZE;
+        let inner = xz.rem_euclid(REGION_SIZE);
+        let region = self.regions.get(&region_pos)?;
+        Some(region[(inner.x + inner.y * CHUNK_SIDE as i32) as usize])
+    }
+}
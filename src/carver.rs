@@ -0,0 +1,211 @@
+use std::{
+    f32::consts::{PI, TAU},
+    ops::{Range, RangeInclusive},
+};
+
+use bevy::prelude::*;
+use mcrs_universe::CHUNK_SIDE;
+
+use crate::generator::feature_hash;
+
+/// Cuts tunnels of air in the generated blocks.
+pub trait Carver: Send + Sync {
+    /// The carvings that reach into the chunk at `chunk_pos`, including the ones of tunnels
+    /// that start in other chunks. They are the same every time for the same seed.
+    fn carvings(&self, chunk_pos: IVec3) -> Vec<Carving>;
+}
+
+/// An ellipsoid of carved blocks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Carving {
+    pub center: Vec3,
+    pub radius: Vec3,
+}
+
+impl Carving {
+    /// Whether the center of the block at `pos` is inside the ellipsoid.
+    pub fn contains(&self, pos: IVec3) -> bool {
+        ((pos.as_vec3() + 0.5 - self.center) / self.radius).length_squared() <= 1.0
+    }
+
+    /// The lowest and the highest block that can be carved.
+    pub fn bounds(&self) -> (IVec3, IVec3) {
+        (
+            (self.center - self.radius).floor().as_ivec3(),
+            (self.center + self.radius).ceil().as_ivec3(),
+        )
+    }
+
+    fn intersects_chunk(&self, chunk_pos: IVec3) -> bool {
+        let (min, max) = self.bounds();
+        let chunk_max = chunk_pos + IVec3::splat(CHUNK_SIDE as i32 - 1);
+        min.cmple(chunk_max).all() && max.cmpge(chunk_pos).all()
+    }
+}
+
+/// Random numbers from a hash, the same on every run.
+struct HashRng(u64);
+
+impl HashRng {
+    fn next_u64(&mut self) -> u64 {
+        // splitmix64
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// In 0..1
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+}
+
+/// How far from its start a tunnel can reach, in chunks.
+const TUNNEL_REACH_CHUNKS: i32 = 4;
+
+/// How a tunnel winds and how wide it is.
+struct TunnelShape {
+    /// Chance of a chunk to start a tunnel.
+    chance: f32,
+    /// Heights of the chunks where the tunnels start.
+    start_heights: RangeInclusive<i32>,
+    /// Number of steps of one block.
+    length: Range<f32>,
+    /// Radius at the middle of the tunnel, the ends are narrower.
+    radius: Range<f32>,
+    /// The vertical radius is the horizontal one scaled by this.
+    stretch: Range<f32>,
+    /// The largest change of direction in radians for each step.
+    turn: f32,
+    max_pitch: f32,
+}
+
+impl TunnelShape {
+    fn carvings(&self, seed: u32, salt: u32, chunk_pos: IVec3) -> Vec<Carving> {
+        let side = CHUNK_SIDE as i32;
+        let mut carvings = vec![];
+        for z in -TUNNEL_REACH_CHUNKS..=TUNNEL_REACH_CHUNKS {
+            for y in -TUNNEL_REACH_CHUNKS..=TUNNEL_REACH_CHUNKS {
+                for x in -TUNNEL_REACH_CHUNKS..=TUNNEL_REACH_CHUNKS {
+                    let start_chunk = chunk_pos + IVec3::new(x, y, z) * side;
+                    if !self.start_heights.contains(&start_chunk.y) {
+                        continue;
+                    }
+                    let mut rng = HashRng(feature_hash(seed, start_chunk, salt));
+                    if rng.next_f32() >= self.chance {
+                        continue;
+                    }
+                    carvings.extend(
+                        self.tunnel(&mut rng, start_chunk)
+                            .into_iter()
+                            .filter(|carving| carving.intersects_chunk(chunk_pos)),
+                    );
+                }
+            }
+        }
+        carvings
+    }
+
+    /// Walks from a random block of the chunk, turning a bit at each step.
+    fn tunnel(&self, rng: &mut HashRng, start_chunk: IVec3) -> Vec<Carving> {
+        let side = CHUNK_SIDE as f32;
+        let mut pos = start_chunk.as_vec3()
+            + Vec3::new(
+                rng.range(0.0, side),
+                rng.range(0.0, side),
+                rng.range(0.0, side),
+            );
+        let mut yaw = rng.range(0.0, TAU);
+        let mut pitch = rng.range(-self.max_pitch, self.max_pitch);
+        let mut yaw_turn = 0.0;
+        let mut pitch_turn = 0.0;
+        let length = rng.range(self.length.start, self.length.end) as usize;
+        let radius = rng.range(self.radius.start, self.radius.end);
+        let stretch = rng.range(self.stretch.start, self.stretch.end);
+
+        let mut carvings = Vec::with_capacity(length);
+        for step in 0..length {
+            let progress = step as f32 / length as f32;
+            let step_radius = radius * (0.4 + 0.6 * (progress * PI).sin());
+            carvings.push(Carving {
+                center: pos,
+                radius: Vec3::new(step_radius, step_radius * stretch, step_radius),
+            });
+
+            pos += Vec3::new(
+                yaw.cos() * pitch.cos(),
+                pitch.sin(),
+                yaw.sin() * pitch.cos(),
+            );
+            yaw_turn = yaw_turn * 0.8 + rng.range(-self.turn, self.turn);
+            pitch_turn = pitch_turn * 0.6 + rng.range(-self.turn, self.turn) * 0.5;
+            yaw += yaw_turn;
+            pitch = (pitch + pitch_turn).clamp(-self.max_pitch, self.max_pitch);
+        }
+        carvings
+    }
+}
+
+/// Narrow winding tunnels that go up and down.
+pub struct WormCaves {
+    seed: u32,
+    shape: TunnelShape,
+}
+
+impl WormCaves {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            seed,
+            shape: TunnelShape {
+                chance: 0.25,
+                start_heights: -128..=64,
+                length: 48.0..112.0,
+                radius: 1.5..3.5,
+                stretch: 0.7..1.0,
+                turn: 0.12,
+                max_pitch: 0.8,
+            },
+        }
+    }
+}
+
+impl Carver for WormCaves {
+    fn carvings(&self, chunk_pos: IVec3) -> Vec<Carving> {
+        self.shape.carvings(self.seed, 0xca7e, chunk_pos)
+    }
+}
+
+/// Rare, long and deep cuts that barely turn.
+pub struct Ravines {
+    seed: u32,
+    shape: TunnelShape,
+}
+
+impl Ravines {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            seed,
+            shape: TunnelShape {
+                chance: 0.01,
+                start_heights: -64..=32,
+                length: 64.0..120.0,
+                radius: 2.0..4.0,
+                stretch: 4.0..6.0,
+                turn: 0.04,
+                max_pitch: 0.1,
+            },
+        }
+    }
+}
+
+impl Carver for Ravines {
+    fn carvings(&self, chunk_pos: IVec3) -> Vec<Carving> {
+        self.shape.carvings(self.seed, 0x7a1e, chunk_pos)
+    }
+}
@@ -2,12 +2,11 @@ use crate::LightSource;
 use bevy::prelude::*;
 use mcrs_universe::{
     block::{Block, BlockFlag, LightType},
-    chunk::Chunk,
+    chunk::{Chunk, ChunkWriteGuard},
     universe::Universe,
-    Blueprints, CHUNK_VOLUME,
+    Blueprints,
 };
 use std::collections::VecDeque;
-use std::sync::RwLockWriteGuard;
 
 const MAX_LIGHTING_PROPAGATION: usize = 1000000;
 const MAX_DARKNESS_PROPAGATION: usize = 100000;
@@ -22,10 +21,12 @@ pub const DIRS: [IVec3; 6] = [
 
 /// Propagate the light from `sources` and return the light sources leaving the chunk.
 /// Both `sources` positions and the return leaked sources positions are relative to this chunk
+/// The positions of the blocks that were lit are pushed to `lit`.
 pub fn propagate_light_chunk(
-    chunk_mut: &mut RwLockWriteGuard<[Block; CHUNK_VOLUME]>,
+    chunk_mut: &mut ChunkWriteGuard,
     sources: Vec<IVec3>,
     lt: LightType,
+    lit: &mut Vec<IVec3>,
 ) -> Vec<LightSource> {
     debug!(target: "lighting_chunk", "{} sources of {lt} light", sources.len());
 
@@ -48,6 +49,7 @@ pub fn propagate_light_chunk(
                 {
                     neighbor.set_light(lt, light - 1);
                     frontier.push_back(target);
+                    lit.push(target);
                 }
             } else if light > 0 {
                 leaking.push(LightSource {
@@ -67,7 +69,7 @@ pub fn propagate_light_chunk(
 // Remove one light source and set to 0 brightness the volume that was lit by that light source.
 // Then return the sources at the boundary and every other light source that was inside.
 pub fn propagate_darkness(
-    universe: &mut Universe,
+    universe: &Universe,
     bp: &Blueprints,
     sources: Vec<IVec3>,
     lt: LightType,
@@ -97,8 +99,8 @@ pub fn propagate_darkness(
                         let mut l = neighbor;
                         l.set_light(lt, 0);
                         unlit = Some(l);
-                        let target_bp = bp.blocks.get(&neighbor.id);
-                        if target_bp.is_light_source() {
+                        let target_bp = bp.blocks.get_checked(&neighbor.id);
+                        if let Some(target_bp) = target_bp.filter(|bp| bp.is_light_source()) {
                             new_lights.push(LightSource {
                                 pos: target,
                                 brightness: target_bp.light_level,
@@ -1,6 +1,6 @@
 use crate::{
-    client::open_client, server::open_server, Lobby, LocalPlayer, LocalPlayerId, NetSettings,
-    PlayerId,
+    biome::Biomes, client::open_client, server::open_server, Lobby, LocalPlayer, LocalPlayerId,
+    NetSettings, PlayerId,
 };
 use bevy::{
     diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, DiagnosticsStore, RegisterDiagnostic},
@@ -16,12 +16,12 @@ use mcrs_physics::{
     raycast::{cast_ray, RayFinite},
     TickStep,
 };
-use mcrs_universe::{universe::Universe, Blueprints, CHUNK_SIDE};
+use mcrs_universe::{block::LightType, universe::Universe, Blueprints, CHUNK_SIDE};
 use renet::{RenetClient, RenetServer};
 
 use crate::{
     player::spawn_camera, settings::McrsSettings, ChunkGenerationRequest, CloseLevelEvent,
-    GenerationPass, Level, OpenLevelEvent, SaveLevelEvent,
+    GenerationPass, GenerationProgress, Level, OpenLevelEvent, SaveLevelEvent, SaveLoadErrorEvent,
 };
 
 pub const DIAGNOSTIC_FPS: DiagnosticPath = DiagnosticPath::const_new("game/fps");
@@ -151,7 +151,10 @@ impl<'a> egui::Widget for WidgetBlockDebug<'a> {
         ui.group(|ui| {
             if let Some(block) = self.universe.read_chunk_block(&self.pos) {
                 egui::Grid::new("Block").striped(true).show(ui, |ui| {
-                    let block_bp = self.bp.blocks.get(&block.id);
+                    let Ok(block_bp) = self.bp.blocks.get(&block.id) else {
+                        ui.label(format!("Unknown block {:?}", block.id));
+                        return;
+                    };
                     ui.label("Position");
                     ui.add(egui::Label::new(format!("{}", self.pos)));
                     ui.end_row();
@@ -164,16 +167,26 @@ impl<'a> egui::Widget for WidgetBlockDebug<'a> {
                     ui.label(format!("{:?}", block_bp.id));
                     ui.end_row();
 
+                    for property in block_bp.states.iter() {
+                        ui.label(format!("State ({})", property.name));
+                        ui.label(
+                            block_bp
+                                .get_state_value(block.state, &property.name)
+                                .unwrap_or("?"),
+                        );
+                        ui.end_row();
+                    }
+
                     ui.label("Brightness");
                     ui.label(format!("{}", block_bp.light_level));
                     ui.end_row();
 
                     ui.label("Lighting (torch)");
-                    ui.label(format!("{}", block.light0));
+                    ui.label(format!("{}", block.get_light(LightType::Torch)));
                     ui.end_row();
 
                     ui.label("Lighting (sun)");
-                    ui.label(format!("{}", block.light1));
+                    ui.label(format!("{}", block.get_light(LightType::Sun)));
                     ui.end_row();
                 });
             }
@@ -262,6 +275,9 @@ impl Modifier {
     }
 }
 
+/// The newest errors are shown, the older ones are dropped.
+const MAX_SHOWN_SAVE_LOAD_ERRORS: usize = 5;
+
 pub fn debug_saveload_ui(
     mut contexts: EguiContexts,
     keys: Res<ButtonInput<KeyCode>>,
@@ -271,7 +287,15 @@ pub fn debug_saveload_ui(
     mut save_event: EventWriter<SaveLevelEvent>,
     mut edit_level_name: Local<Option<String>>,
     settings: Res<McrsSettings>,
+    universe: Res<Universe>,
+    progress: Res<GenerationProgress>,
+    mut error_events: EventReader<SaveLoadErrorEvent>,
+    mut errors: Local<Vec<String>>,
 ) {
+    errors.extend(error_events.read().map(|event| event.message.clone()));
+    let excess = errors.len().saturating_sub(MAX_SHOWN_SAVE_LOAD_ERRORS);
+    errors.drain(..excess);
+
     let Some(edit_level_name) = edit_level_name.as_mut() else {
         *edit_level_name = Some(settings.open_level_name.clone());
         return;
@@ -283,6 +307,21 @@ pub fn debug_saveload_ui(
         .show(ctx, |ui| {
             if let Some(level) = level {
                 ui.label(format!("Loaded level: {}", level.name));
+                ui.label(format!(
+                    "Seed: {}, {} mode, tick {}",
+                    level.seed, level.game_mode, level.world_time
+                ));
+                let memory: usize = universe.chunks.iter().map(|(_, c)| c.memory_usage()).sum();
+                ui.label(format!(
+                    "Loaded chunks: {} ({:.1} MiB)",
+                    universe.chunks.len(),
+                    memory as f64 / (1024.0 * 1024.0)
+                ));
+                ui.add(egui::ProgressBar::new(progress.fraction()).text(format!(
+                    "Generated: {}/{}",
+                    progress.loaded,
+                    progress.loaded + progress.pending
+                )));
             } else {
                 ui.label("No loaded level");
             }
@@ -301,6 +340,8 @@ pub fn debug_saveload_ui(
             ) {
                 open_event.send(OpenLevelEvent {
                     level_name: edit_level_name.clone(),
+                    generator: settings.generator.clone(),
+                    seed: settings.seed,
                 });
             }
             if ui_button_shortcut(
@@ -321,6 +362,16 @@ pub fn debug_saveload_ui(
             ) {
                 close_event.send(CloseLevelEvent);
             }
+
+            if !errors.is_empty() {
+                ui.separator();
+                for error in errors.iter() {
+                    ui.colored_label(egui::Color32::LIGHT_RED, error);
+                }
+                if ui.button("Dismiss").clicked() {
+                    errors.clear();
+                }
+            }
         });
 }
 
@@ -669,20 +720,21 @@ pub fn debug_chunks(
         return;
     }
 
-    for (chunk_pos, gen_request) in &chunk_gen.requested {
+    for (chunk_pos, gen_request) in chunk_gen.iter() {
         let scale = Vec3::splat(CHUNK_SIDE as f32);
         let center = chunk_pos.as_vec3() + scale * 0.5;
-        let color = match gen_request.pass {
+        let color = match gen_request.pass() {
             GenerationPass::Blocks => Color::srgb(1.0, 0.0, 0.0),
             GenerationPass::Lighting => Color::srgb(1.0, 0.2, 0.0),
             GenerationPass::WaitingForSunbeams => Color::srgb(1.0, 0.4, 0.0),
             GenerationPass::Sunbeams => Color::srgb(1.0, 0.6, 0.0),
             GenerationPass::Biome => Color::srgb(1.0, 0.8, 0.0),
+            GenerationPass::Decoration => Color::srgb(1.0, 0.9, 0.0),
             GenerationPass::Done => Color::srgb(1.0, 1.0, 0.0),
         };
         gizmos.cuboid(Transform::from_translation(center).with_scale(scale), color);
     }
-    for (chunk_pos, _) in &universe.chunks {
+    for chunk_pos in universe.chunks.positions() {
         let scale = Vec3::splat(CHUNK_SIDE as f32);
         let center = chunk_pos.as_vec3() + scale * 0.5;
         gizmos.cuboid(
@@ -697,6 +749,7 @@ pub fn debug_terrain_editing(
     mut player_query: Query<(&Transform, &Rigidbody)>,
     universe: Res<Universe>,
     bp: Res<Blueprints>,
+    biomes: Res<Biomes>,
     mut gizmos: Gizmos,
     mut contexts: EguiContexts,
     mut hide_red_cube: Local<bool>,
@@ -718,6 +771,11 @@ pub fn debug_terrain_editing(
         egui::Window::new("Debug Player Raycast Hit")
             .anchor(egui::Align2::LEFT_CENTER, egui::Vec2::new(5.0, 0.0))
             .show(contexts.ctx_mut(), |ui| {
+                let column = tr_player.translation.xz().floor().as_ivec2();
+                match biomes.get(column) {
+                    Some(biome) => ui.label(format!("Biome: {}", biome)),
+                    None => ui.label("Biome: not generated"),
+                };
                 if let Some(hit) = &hit_option {
                     ui.add(WidgetBlockDebug::new(hit.grid_pos, &universe, &bp));
                     if !*hide_red_cube {
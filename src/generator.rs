@@ -0,0 +1,638 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::{
+    biome::{Biome, BiomeNoise},
+    carver::{Carver, Ravines, WormCaves},
+};
+use bevy::{prelude::*, utils::HashMap};
+use mcrs_universe::{
+    block::{Block, BlockBlueprint, BlockFlag, BlockId},
+    chunk::Chunk,
+    pack::qualified_name,
+    BlueprintError, Blueprints, CHUNK_SIDE, CHUNK_VOLUME,
+};
+use noise::{HybridMulti, MultiFractal, NoiseFn, Seedable};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_GENERATOR: &str = "crazy_hill";
+/// Layers of the superflat generator when it has no params.
+pub const DEFAULT_SUPERFLAT_PRESET: &str = "Stone,3*Dirt,Grass";
+/// Carved blocks below this height are filled with a fluid, if the packs have one.
+pub const CAVE_FLUID_LEVEL: i32 = -96;
+/// Top of the world of the generators that don't know how high their blocks go.
+pub const DEFAULT_SKY_HEIGHT: i32 = 256;
+/// How high the features can reach above the ground.
+const FEATURE_HEIGHT: i32 = 8;
+
+/// Makes the blocks of a world, built by the `GeneratorRegistry` from a `GeneratorConfig`.
+pub trait WorldGenerator: Send + Sync {
+    /// The block at `pos`, the same for every call with the same seed and params.
+    fn gen_block(&self, pos: IVec3) -> Block;
+
+    /// The column at `xz` has no blocks at or above this height, the sun starts there.
+    /// A lower height needs less chunks above the ground to be generated.
+    fn sky_height(&self, _xz: IVec2) -> i32 {
+        DEFAULT_SKY_HEIGHT
+    }
+
+    /// Biome of the column at `xz`, the same for every call with the same seed and params.
+    fn biome(&self, _xz: IVec2) -> Biome {
+        Biome::default()
+    }
+
+    /// Blocks laid by the biome pass under the lowest sunbeam of the columns of `biome`, top first.
+    fn surface(&self, _biome: Biome) -> &[Block] {
+        &[]
+    }
+
+    /// Run in order on the blocks of each chunk once they are generated.
+    fn carvers(&self) -> &[Box<dyn Carver>] {
+        &[]
+    }
+
+    /// The block that replaces a carved block at the height `y`, air if `None`.
+    fn carved_fluid(&self, _y: i32) -> Option<Block> {
+        None
+    }
+
+    /// Trees, ores and plants placed by the decoration pass in the chunk at `chunk_pos`.
+    /// `block` reads the blocks of the chunk by their position in it. They are already generated,
+    /// but features of other chunks may have been placed in them and have to be ignored,
+    /// so that the features are the same every time for the same seed.
+    /// Each block of a feature must be in this chunk or in one of its 26 neighbours.
+    fn features(&self, _chunk_pos: IVec3, _block: &dyn Fn(IVec3) -> Block) -> Vec<Feature> {
+        vec![]
+    }
+}
+
+/// Blocks placed together by the decoration pass, in world coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Feature {
+    pub blocks: Vec<(IVec3, Block)>,
+    /// Only the blocks with this id are replaced, or the ones that aren't opaque if `None`.
+    pub replaces: Option<BlockId>,
+}
+
+impl Feature {
+    pub fn can_replace(&self, block: &Block) -> bool {
+        match self.replaces {
+            Some(id) => block.id == id,
+            None => !block.properties.check(BlockFlag::Opaque),
+        }
+    }
+}
+
+/// The chunks whose features can reach into the chunk at `chunk_pos`, itself and its 26
+/// neighbours. They are always in the same order so that the features overlap the same way
+/// whichever chunk is decorated first.
+pub fn feature_sources(chunk_pos: IVec3) -> Vec<IVec3> {
+    (-1..=1)
+        .flat_map(|z| (-1..=1).flat_map(move |y| (-1..=1).map(move |x| IVec3::new(x, y, z))))
+        .map(|offset| chunk_pos + offset * CHUNK_SIDE as i32)
+        .collect()
+}
+
+/// Places the blocks of `features` that are in the chunk at `chunk_pos`, in order.
+pub fn place_features(chunk_pos: IVec3, blocks: &mut [Block; CHUNK_VOLUME], features: &[Feature]) {
+    for feature in features.iter() {
+        for (pos, feature_block) in feature.blocks.iter() {
+            let xyz = *pos - chunk_pos;
+            if !Chunk::contains(&xyz) {
+                continue;
+            }
+            let block = &mut blocks[Chunk::xyz2idx(xyz)];
+            if feature.can_replace(block) {
+                *block = *feature_block;
+            }
+        }
+    }
+}
+
+/// Mixes the seed, a position and an index into a number that is the same on every run.
+pub fn feature_hash(seed: u32, pos: IVec3, index: u32) -> u64 {
+    let mut hash = seed as u64 ^ 0x9e37_79b9_7f4a_7c15;
+    for value in [pos.x as u32, pos.y as u32, pos.z as u32, index] {
+        hash = (hash ^ value as u64).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        hash ^= hash >> 31;
+    }
+    hash
+}
+
+/// Which generator made a level, stored with the level so it is regenerated the same way.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GeneratorConfig {
+    pub name: String,
+    /// Read by the generator, the format depends on it.
+    pub params: String,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            name: DEFAULT_GENERATOR.to_string(),
+            params: String::new(),
+        }
+    }
+}
+
+/// Reads `name` or `name:params`, the format of `Display`.
+impl From<&str> for GeneratorConfig {
+    fn from(s: &str) -> Self {
+        let (name, params) = s.split_once(':').unwrap_or((s, ""));
+        Self {
+            name: name.trim().to_string(),
+            params: params.to_string(),
+        }
+    }
+}
+
+impl Display for GeneratorConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.params.is_empty() {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{}:{}", self.name, self.params)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeneratorError {
+    Unknown(String),
+    Params { name: String, message: String },
+    Blueprint(BlueprintError),
+}
+
+impl Display for GeneratorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(name) => write!(f, "there is no generator named {}", name),
+            Self::Params { name, message } => {
+                write!(f, "invalid params for the generator {}: {}", name, message)
+            }
+            Self::Blueprint(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for GeneratorError {}
+
+impl From<BlueprintError> for GeneratorError {
+    fn from(err: BlueprintError) -> Self {
+        Self::Blueprint(err)
+    }
+}
+
+/// What a generator is built from.
+pub struct GeneratorContext<'a> {
+    pub seed: u32,
+    pub params: &'a str,
+    pub bp: &'a Blueprints,
+}
+
+pub type GeneratorBuilder =
+    fn(&GeneratorContext) -> Result<Box<dyn WorldGenerator>, GeneratorError>;
+
+/// Generators by name, the built-in ones are registered by default.
+#[derive(Resource)]
+pub struct GeneratorRegistry {
+    builders: HashMap<String, GeneratorBuilder>,
+}
+
+impl Default for GeneratorRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            builders: HashMap::new(),
+        };
+        registry.register("void", |ctx| Ok(Box::new(GeneratorVoid::new(ctx.bp))));
+        registry.register("flat", |ctx| Ok(Box::new(GeneratorFlat::new(ctx.bp)?)));
+        registry.register("superflat", |ctx| {
+            Ok(Box::new(GeneratorSuperflat::new(ctx.params, ctx.bp)?))
+        });
+        registry.register(DEFAULT_GENERATOR, |ctx| {
+            Ok(Box::new(GeneratorCrazyHill::new(ctx.seed, ctx.bp, 1.0)?))
+        });
+        registry.register("amplified", |ctx| {
+            Ok(Box::new(GeneratorCrazyHill::new(ctx.seed, ctx.bp, 2.0)?))
+        });
+        registry
+    }
+}
+
+impl GeneratorRegistry {
+    /// Replaces the generator with the same name.
+    pub fn register(&mut self, name: &str, builder: GeneratorBuilder) {
+        self.builders.insert(name.to_string(), builder);
+    }
+
+    pub fn build(
+        &self,
+        config: &GeneratorConfig,
+        seed: u32,
+        bp: &Blueprints,
+    ) -> Result<Box<dyn WorldGenerator>, GeneratorError> {
+        let builder = self
+            .builders
+            .get(&config.name)
+            .ok_or_else(|| GeneratorError::Unknown(config.name.clone()))?;
+        builder(&GeneratorContext {
+            seed,
+            params: &config.params,
+            bp,
+        })
+    }
+}
+
+/// Only air.
+pub struct GeneratorVoid {
+    air: Block,
+}
+
+impl GeneratorVoid {
+    fn new(bp: &Blueprints) -> Self {
+        Self {
+            air: Block::new(bp.air()),
+        }
+    }
+}
+
+impl WorldGenerator for GeneratorVoid {
+    fn gen_block(&self, _pos: IVec3) -> Block {
+        self.air
+    }
+
+    fn sky_height(&self, _xz: IVec2) -> i32 {
+        0
+    }
+}
+
+/// Stone up to y = -4, then 3 layers of dirt and grass at y = 0.
+pub struct GeneratorFlat {
+    air: Block,
+    stone: Block,
+    grass: Block,
+    dirt: Block,
+}
+
+impl GeneratorFlat {
+    /// Fails if one of the blocks used by the generator has no blueprint.
+    fn new(bp: &Blueprints) -> Result<Self, BlueprintError> {
+        Ok(Self {
+            air: Block::new(bp.air()),
+            stone: Block::new(bp.blocks.get_named("Stone")?),
+            grass: Block::new(bp.blocks.get_named("Grass")?),
+            dirt: Block::new(bp.blocks.get_named("Dirt")?),
+        })
+    }
+}
+
+impl WorldGenerator for GeneratorFlat {
+    fn gen_block(&self, pos: IVec3) -> Block {
+        match pos.y {
+            1.. => self.air,
+            0 => self.grass,
+            -3..=-1 => self.dirt,
+            _ => self.stone,
+        }
+    }
+
+    fn sky_height(&self, _xz: IVec2) -> i32 {
+        1
+    }
+}
+
+/// Horizontal layers of blocks with the top one at y = 0 and air below the bottom one.
+pub struct GeneratorSuperflat {
+    air: Block,
+    /// The lowest y of each layer and its block, top layer first.
+    layers: Vec<(i32, Block)>,
+}
+
+impl GeneratorSuperflat {
+    /// `preset` lists the layers from the bottom up, separated by commas.
+    /// Each is a block name with an optional thickness, as in `Stone,3*Dirt,Grass`.
+    /// The names are those of the blueprints whatever their case, `stone` is `base:Stone`.
+    /// There is no bedrock in the base pack, `Cobblestone` can stand in for it.
+    pub fn new(preset: &str, bp: &Blueprints) -> Result<Self, GeneratorError> {
+        let preset = match preset.trim() {
+            "" => DEFAULT_SUPERFLAT_PRESET,
+            preset => preset,
+        };
+        let invalid = |message: String| GeneratorError::Params {
+            name: "superflat".to_string(),
+            message,
+        };
+        let mut layers = vec![];
+        let mut top = 0i32;
+        for layer in preset.split(',').rev() {
+            let (count, name) = match layer.split_once('*') {
+                Some((count, name)) => {
+                    let count = count
+                        .trim()
+                        .parse::<i32>()
+                        .ok()
+                        .filter(|count| *count > 0)
+                        .ok_or_else(|| invalid(format!("bad layer thickness in {:?}", layer)))?;
+                    (count, name)
+                }
+                None => (1, layer),
+            };
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(invalid(format!("missing block name in {:?}", layer)));
+            }
+            let bottom = top.saturating_sub(count - 1);
+            layers.push((bottom, Block::new(block_named_ignoring_case(bp, name)?)));
+            top = bottom.saturating_sub(1);
+        }
+        Ok(Self {
+            air: Block::new(bp.air()),
+            layers,
+        })
+    }
+}
+
+/// Names without a namespace refer to the base pack, an exact match wins over the others.
+fn block_named_ignoring_case<'a>(
+    bp: &'a Blueprints,
+    name: &str,
+) -> Result<&'a BlockBlueprint, BlueprintError> {
+    if let Some(block_bp) = bp.blocks.get_named_checked(name) {
+        return Ok(block_bp);
+    }
+    let name = qualified_name(name);
+    bp.blocks
+        .iter()
+        .filter(|block_bp| block_bp.name.eq_ignore_ascii_case(&name))
+        .min_by_key(|block_bp| block_bp.id)
+        .ok_or_else(|| BlueprintError::UnknownName(name.into_owned()))
+}
+
+impl WorldGenerator for GeneratorSuperflat {
+    fn gen_block(&self, pos: IVec3) -> Block {
+        if pos.y > 0 {
+            return self.air;
+        }
+        self.layers
+            .iter()
+            .find(|(bottom, _)| pos.y >= *bottom)
+            .map_or(self.air, |(_, block)| *block)
+    }
+
+    fn sky_height(&self, _xz: IVec2) -> i32 {
+        1
+    }
+}
+
+pub struct GeneratorCrazyHill {
+    seed: u32,
+    terrain_noise: noise::Exponent<f64, HybridMulti<noise::Perlin>, 2>,
+    sponge_noise: HybridMulti<noise::Perlin>,
+    biome_noise: BiomeNoise,
+    carvers: Vec<Box<dyn Carver>>,
+    /// Scales the height of the hills.
+    amplitude: f64,
+    air: Block,
+    stone: Block,
+    soil: [Block; 3],
+    sand: [Block; 3],
+    log: Block,
+    leaves: Block,
+    cactus: Block,
+    coal_ore: Block,
+    iron_ore: Block,
+    /// Lava, or water if there is no lava.
+    cave_fluid: Option<Block>,
+}
+
+impl GeneratorCrazyHill {
+    /// Fails if one of the blocks used by the generator has no blueprint.
+    fn new(seed: u32, bp: &Blueprints, amplitude: f64) -> Result<Self, BlueprintError> {
+        let grass = Block::new(bp.blocks.get_named("Grass")?);
+        let dirt = Block::new(bp.blocks.get_named("Dirt")?);
+        let sand = Block::new(bp.blocks.get_named("Sand")?);
+        Ok(Self {
+            seed,
+            terrain_noise: noise::Exponent::new(
+                HybridMulti::<noise::Perlin>::default()
+                    .set_frequency(0.001)
+                    .set_octaves(4)
+                    .set_seed(seed),
+            ),
+            sponge_noise: HybridMulti::<noise::Perlin>::default()
+                .set_frequency(0.003)
+                .set_octaves(5)
+                .set_persistence(0.5)
+                .set_seed(seed),
+            biome_noise: BiomeNoise::new(seed),
+            carvers: vec![Box::new(WormCaves::new(seed)), Box::new(Ravines::new(seed))],
+            amplitude,
+            air: Block::new(bp.air()),
+            stone: Block::new(bp.blocks.get_named("Stone")?),
+            soil: [grass, dirt, dirt],
+            sand: [sand; 3],
+            log: Block::new(bp.blocks.get_named("Wood")?),
+            leaves: Block::new(bp.blocks.get_named("Oak Leaves")?),
+            cactus: Block::new(bp.blocks.get_named("Cactus")?),
+            coal_ore: Block::new(bp.blocks.get_named("Coal Ore")?),
+            iron_ore: Block::new(bp.blocks.get_named("Iron Ore")?),
+            cave_fluid: bp
+                .blocks
+                .get_named_checked("Lava")
+                .or_else(|| bp.blocks.get_named_checked("Water"))
+                .map(Block::new),
+        })
+    }
+
+    /// Above this height there is only air.
+    fn sky(&self, xz: IVec2) -> f64 {
+        128.0 * self.amplitude * self.biome_noise.height_scale(xz)
+    }
+
+    /// Opaque blocks that aren't part of a plant.
+    fn is_ground(&self, block: &Block) -> bool {
+        block.properties.check(BlockFlag::Opaque)
+            && block.id != self.log.id
+            && block.id != self.cactus.id
+    }
+
+    /// The highest ground block of the column at `xz` in the chunk, if there is air above it.
+    fn ground_height(&self, xz: IVec2, block: &dyn Fn(IVec3) -> Block) -> Option<i32> {
+        let top = CHUNK_SIDE as i32 - 1;
+        let y = (0..=top)
+            .rev()
+            .find(|y| self.is_ground(&block(IVec3::new(xz.x, *y, xz.y))))?;
+        (y < top).then_some(y)
+    }
+
+    /// Built in code rather than loaded as a `Structure`, its height varies with the seed.
+    /// A fixed structure would be resolved once with `Structure::to_volume` when the
+    /// generator is built, then placed as a `Feature` like this one.
+    fn tree(&self, ground: IVec3, hash: u64) -> Feature {
+        let height = 4 + (hash % 3) as i32;
+        let mut blocks = vec![];
+        for y in 1..=height {
+            blocks.push((ground + IVec3::Y * y, self.log));
+        }
+        for y in height - 2..=height + 1 {
+            let radius = if y < height { 2 } else { 1 };
+            for x in -radius..=radius {
+                for z in -radius..=radius {
+                    let is_corner = x.abs() == radius && z.abs() == radius;
+                    if is_corner && (radius == 2 || y == height + 1) {
+                        continue;
+                    }
+                    blocks.push((ground + IVec3::new(x, y, z), self.leaves));
+                }
+            }
+        }
+        Feature {
+            blocks,
+            replaces: None,
+        }
+    }
+
+    fn cactus(&self, ground: IVec3, hash: u64) -> Feature {
+        let height = 1 + (hash % 3) as i32;
+        Feature {
+            blocks: (1..=height)
+                .map(|y| (ground + IVec3::Y * y, self.cactus))
+                .collect(),
+            replaces: None,
+        }
+    }
+
+    /// A blob of ore around `center` in the stone, up to a block away from it.
+    fn ore_vein(&self, center: IVec3, hash: u64, ore: Block) -> Feature {
+        let offsets = [
+            IVec3::X,
+            IVec3::NEG_X,
+            IVec3::Y,
+            IVec3::NEG_Y,
+            IVec3::Z,
+            IVec3::NEG_Z,
+        ];
+        let mut blocks = vec![(center, ore)];
+        for (i, offset) in offsets.into_iter().enumerate() {
+            if (hash >> (32 + i)) & 1 == 1 {
+                blocks.push((center + offset, ore));
+            }
+        }
+        Feature {
+            blocks,
+            replaces: Some(self.stone.id),
+        }
+    }
+}
+
+impl WorldGenerator for GeneratorCrazyHill {
+    fn gen_block(&self, pos: IVec3) -> Block {
+        // create an envelope of 3d noise in -192..192
+        // squish that envelope in the y direction using a 2d perlin noise
+
+        let dpos = pos.as_dvec3();
+
+        let block;
+        let caves: f64 = -128.0;
+        let sky: f64 = self.sky(pos.xz());
+        let mid = (sky + caves) * 0.5;
+        let amp = (sky - caves).abs() * 0.5;
+        if dpos.y > sky {
+            block = self.air;
+        } else if dpos.y < caves {
+            block = self.stone;
+        } else {
+            let flatness = self.terrain_noise.get(dpos.xz().to_array());
+            let flatness_norm = flatness * 0.5 + 0.5;
+            if dpos.y < mid - amp * flatness_norm {
+                block = self.stone;
+            } else if dpos.y > mid + amp * flatness_norm {
+                block = self.air;
+            } else {
+                let sample = self.sponge_noise.get(dpos.to_array());
+                if sample > 1.0 - flatness_norm {
+                    block = self.stone;
+                } else {
+                    block = self.air;
+                }
+            }
+        }
+
+        block
+    }
+
+    fn sky_height(&self, xz: IVec2) -> i32 {
+        self.sky(xz).floor() as i32 + 1 + FEATURE_HEIGHT
+    }
+
+    fn biome(&self, xz: IVec2) -> Biome {
+        self.biome_noise.climate(xz).biome()
+    }
+
+    fn surface(&self, biome: Biome) -> &[Block] {
+        if biome.is_sandy() {
+            &self.sand
+        } else {
+            &self.soil
+        }
+    }
+
+    fn carvers(&self) -> &[Box<dyn Carver>] {
+        &self.carvers
+    }
+
+    fn carved_fluid(&self, y: i32) -> Option<Block> {
+        self.cave_fluid.filter(|_| y < CAVE_FLUID_LEVEL)
+    }
+
+    fn features(&self, chunk_pos: IVec3, block: &dyn Fn(IVec3) -> Block) -> Vec<Feature> {
+        const ORE_VEINS: u32 = 12;
+        const PLANT_TRIES: u32 = 16;
+
+        let mut features = vec![];
+        let side = CHUNK_SIDE as u64;
+
+        for i in 0..ORE_VEINS {
+            let hash = feature_hash(self.seed, chunk_pos, i);
+            let inner = IVec3::new(
+                (hash % side) as i32,
+                (hash / side % side) as i32,
+                (hash / side.pow(2) % side) as i32,
+            );
+            let center = chunk_pos + inner;
+            // Iron is only found deep underground
+            let ore = if center.y < 0 && (hash >> 48) & 3 == 0 {
+                self.iron_ore
+            } else {
+                self.coal_ore
+            };
+            features.push(self.ore_vein(center, hash, ore));
+        }
+
+        for i in 0..PLANT_TRIES {
+            let hash = feature_hash(self.seed, chunk_pos, ORE_VEINS + i);
+            let inner = IVec2::new((hash % side) as i32, (hash / side % side) as i32);
+            let biome = self.biome(chunk_pos.xz() + inner);
+            // How many of the tries place a plant, out of `PLANT_TRIES`
+            let density = match biome {
+                Biome::Forest => 8,
+                Biome::Plains | Biome::Mountains | Biome::Desert => 1,
+                Biome::Ocean | Biome::Beach => 0,
+            };
+            if (hash >> 16) & 0xf >= density {
+                continue;
+            }
+            let Some(y) = self.ground_height(inner, block) else {
+                continue;
+            };
+            let ground = chunk_pos + IVec3::new(inner.x, y, inner.y);
+            if biome == Biome::Desert {
+                features.push(self.cactus(ground, hash >> 24));
+            } else {
+                features.push(self.tree(ground, hash >> 24));
+            }
+        }
+
+        features
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use bevy::{prelude::*, utils::HashSet};
+use mcrs_universe::{chunk::Chunk, palette::LevelPalette, CHUNK_AREA};
+use redb::{Database, WriteTransaction};
+
+use crate::{
+    biome::{Biome, Biomes},
+    saveload::{
+        sun_beams_region, write_biomes_region, write_block_names, write_chunk, write_level,
+        write_player, write_sun_beams_region, write_transaction, BeamPod, Level, SaveLoadError,
+        SerdePlayer, TABLE_BIOMES, TABLE_BLOCKS, TABLE_PLAYERS, TABLE_SUN_BEAMS,
+    },
+    SunBeams,
+};
+
+/// The queued batches are merged in one transaction until it has this many chunks.
+const MAX_CHUNKS_PER_TRANSACTION: usize = 256;
+
+/// What a save, or the unloading and the generation of chunks, hands to the writer thread.
+/// Everything is snapshotted when the batch is made, except the blocks of the chunks:
+/// they are encoded when the batch is written, so they are never older than when it was sent.
+#[derive(Default)]
+pub struct WriteBatch {
+    /// Translates the block ids of the chunks, written as the block names with the level info.
+    pub palette: LevelPalette,
+    pub level: Option<Level>,
+    pub players: Vec<SerdePlayer>,
+    pub chunks: Vec<(IVec3, Arc<Chunk>)>,
+    pub sun_beam_regions: Vec<(IVec2, [BeamPod; CHUNK_AREA])>,
+    pub biome_regions: Vec<(IVec2, [Biome; CHUNK_AREA])>,
+    /// The last batch of a save.
+    pub ends_save: bool,
+}
+
+impl WriteBatch {
+    /// The chunks with the sun beams and biomes regions of their columns.
+    pub fn with_chunks(
+        palette: LevelPalette,
+        chunks: Vec<(IVec3, Arc<Chunk>)>,
+        sun_beams: &SunBeams,
+        biomes: &Biomes,
+    ) -> Self {
+        let regions: HashSet<IVec2> = chunks.iter().map(|(chunk_pos, _)| chunk_pos.xz()).collect();
+        Self {
+            palette,
+            sun_beam_regions: regions
+                .iter()
+                .map(|region_pos| (*region_pos, sun_beams_region(*region_pos, sun_beams)))
+                .collect(),
+            // columns whose biome isn't chosen yet aren't written
+            biome_regions: regions
+                .iter()
+                .filter_map(|region_pos| Some((*region_pos, *biomes.regions.get(region_pos)?)))
+                .collect(),
+            chunks,
+            ..default()
+        }
+    }
+}
+
+/// Sent back by the writer thread for each batch.
+pub struct WriteReport {
+    pub id: u64,
+    pub chunks: Vec<IVec3>,
+    pub ends_save: bool,
+    /// Why the transaction of the batch failed, nothing of it was written then.
+    pub error: Option<String>,
+}
+
+/// Writes the batches to the level db on a thread of its own, so that the ticks never wait
+/// for redb or the compression.
+pub struct LevelWriter {
+    sender: Mutex<Option<Sender<(u64, WriteBatch)>>>,
+    reports: Mutex<Receiver<WriteReport>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+    next_id: AtomicU64,
+}
+
+impl LevelWriter {
+    pub fn spawn(db: Arc<Database>) -> Self {
+        let (sender, batches) = mpsc::channel();
+        let (report_sender, reports) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("level writer".to_string())
+            .spawn(move || write_batches(&db, batches, report_sender))
+            .expect("failed to spawn the level writer thread");
+        Self {
+            sender: Mutex::new(Some(sender)),
+            reports: Mutex::new(reports),
+            thread: Mutex::new(Some(thread)),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Queues the batch, returns the id of its report.
+    pub fn send(&self, batch: WriteBatch) -> Result<u64, SaveLoadError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let sender = self.sender.lock().unwrap();
+        let sent = sender
+            .as_ref()
+            .is_some_and(|sender| sender.send((id, batch)).is_ok());
+        if !sent {
+            return Err(SaveLoadError::WriterStopped);
+        }
+        Ok(id)
+    }
+
+    /// The reports of the batches written since the last call.
+    pub fn reports(&self) -> Vec<WriteReport> {
+        self.reports.lock().unwrap().try_iter().collect()
+    }
+
+    /// Waits for the queued batches to be written, and stops the thread.
+    pub fn finish(&self) {
+        drop(self.sender.lock().unwrap().take());
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            if thread.join().is_err() {
+                error!("the level writer thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for LevelWriter {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Runs until the `LevelWriter` is finished, the batches queued meanwhile are still written.
+fn write_batches(
+    db: &Database,
+    batches: Receiver<(u64, WriteBatch)>,
+    reports: Sender<WriteReport>,
+) {
+    while let Ok(first) = batches.recv() {
+        // the batches queued while the previous transaction was committed go together
+        let mut chunk_count = first.1.chunks.len();
+        let mut merged = vec![first];
+        while chunk_count < MAX_CHUNKS_PER_TRANSACTION {
+            let Ok(next) = batches.try_recv() else {
+                break;
+            };
+            chunk_count += next.1.chunks.len();
+            merged.push(next);
+        }
+
+        let error = write_transaction(db, |tx| {
+            for (_, batch) in merged.iter() {
+                write_batch(tx, batch)?;
+            }
+            Ok(())
+        })
+        .err()
+        .map(|err| err.to_string());
+
+        for (id, batch) in merged {
+            let report = WriteReport {
+                id,
+                chunks: batch
+                    .chunks
+                    .iter()
+                    .map(|(chunk_pos, _)| *chunk_pos)
+                    .collect(),
+                ends_save: batch.ends_save,
+                error: error.clone(),
+            };
+            // nobody listens once the level is closed
+            let _ = reports.send(report);
+        }
+    }
+}
+
+fn write_batch(tx: &WriteTransaction, batch: &WriteBatch) -> Result<(), SaveLoadError> {
+    if let Some(level) = &batch.level {
+        write_level(tx, level)?;
+        write_block_names(tx, &batch.palette)?;
+    }
+    let mut player_table = tx.open_table(TABLE_PLAYERS)?;
+    for player in batch.players.iter() {
+        write_player(tx, player, Some(&mut player_table))?;
+    }
+    let mut block_table = tx.open_table(TABLE_BLOCKS)?;
+    for (chunk_pos, chunk) in batch.chunks.iter() {
+        write_chunk(tx, &batch.palette, chunk_pos, chunk, Some(&mut block_table))?;
+    }
+    let mut sun_table = tx.open_table(TABLE_SUN_BEAMS)?;
+    for (region_pos, region) in batch.sun_beam_regions.iter() {
+        write_sun_beams_region(tx, *region_pos, region, Some(&mut sun_table))?;
+    }
+    let mut biome_table = tx.open_table(TABLE_BIOMES)?;
+    for (region_pos, region) in batch.biome_regions.iter() {
+        write_biomes_region(tx, *region_pos, region, Some(&mut biome_table))?;
+    }
+    Ok(())
+}
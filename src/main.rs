@@ -7,12 +7,17 @@ use mcrs_physics::plugin::{FixedPhysicsSet, McrsPhysicsPlugin};
 use mcrs_render::{
     chunk_mesh::TextureHandles, plugin::McrsVoxelRenderPlugin, settings::RenderSettings,
 };
-use mcrs_universe::McrsUniversePlugin;
+use mcrs_universe::{Blueprints, McrsUniversePlugin};
 
+mod biome;
 mod camera;
+mod carver;
 mod chemistry;
 mod debug;
+mod generator;
 mod input;
+mod level_writer;
+mod migration;
 mod net;
 mod player;
 mod saveload;
@@ -20,7 +25,12 @@ mod settings;
 mod terrain;
 mod ui;
 
+#[cfg(test)]
+mod test;
+
+use biome::Biomes;
 use debug::DebugDiagnosticPlugin;
+use generator::GeneratorRegistry;
 use input::*;
 use net::*;
 use player::*;
@@ -62,12 +72,30 @@ fn main() -> AppExit {
     app.insert_resource(ClearColor(Color::srgb(1.0, 1.0, 1.0)));
     app.insert_resource(settings.clone());
 
-    app.add_plugins((McrsUniversePlugin, McrsPhysicsPlugin, SaveLoadPlugin));
+    // nothing works without the blueprints
+    match Blueprints::load(&settings.packs_path) {
+        Ok(blueprints) => app.insert_resource(blueprints),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return AppExit::error();
+        }
+    };
+
+    app.add_plugins((
+        McrsUniversePlugin {
+            packs_path: settings.packs_path.clone(),
+        },
+        McrsPhysicsPlugin,
+        SaveLoadPlugin,
+    ));
     app.init_resource::<UniverseChanges>();
     app.init_resource::<PlayerUniverseChanges>();
     app.init_resource::<LightSources>();
     app.init_resource::<ChunkGenerationRequest>();
+    app.init_resource::<GenerationProgress>();
+    app.init_resource::<GeneratorRegistry>();
     app.init_resource::<SunBeams>();
+    app.init_resource::<Biomes>();
     app.init_resource::<LobbySpawnedPlayers>();
 
     app.add_plugins(NetPlugin);
@@ -205,6 +233,8 @@ pub fn auto_open_level(mut event_writer: EventWriter<OpenLevelEvent>, settings:
         _ => {
             event_writer.send(OpenLevelEvent {
                 level_name: settings.open_level_name.clone(),
+                generator: settings.generator.clone(),
+                seed: settings.seed,
             });
         }
     }
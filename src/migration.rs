@@ -0,0 +1,185 @@
+use std::mem::size_of;
+
+use bevy::prelude::*;
+use bytemuck::{Pod, Zeroable};
+use mcrs_universe::{
+    block::{Block, BlockId, LightType},
+    chunk::Chunk,
+    palette::{LevelPalette, PalettedChunk},
+    BlueprintList, CHUNK_VOLUME, MAX_LIGHT,
+};
+use miniz_oxide::{deflate::compress_to_vec, inflate::decompress_to_vec_with_limit};
+use redb::{ReadTransaction, ReadableTable, WriteTransaction};
+use serde::Deserialize;
+
+use crate::{
+    generator::{GeneratorConfig, DEFAULT_SKY_HEIGHT},
+    saveload::{
+        open_read_table, quarantine_chunk, write_block_names, write_level, Db, GameMode, Level,
+        SaveLoadError, LEVEL_FORMAT_VERSION, TABLE_BLOCKS, TABLE_LEVEL,
+    },
+};
+
+/// Upgrades a level database from the version at its index to the next one.
+type Migration = fn(&WriteTransaction) -> Result<(), SaveLoadError>;
+
+/// One migration for each version before `LEVEL_FORMAT_VERSION`.
+const MIGRATIONS: [Migration; LEVEL_FORMAT_VERSION as usize] = [migrate_v0_to_v1];
+
+/// Upgrades the level step by step to `LEVEL_FORMAT_VERSION`, one transaction per step,
+/// so that a level is never left between two versions.
+/// Returns the version the level had, `None` if it was already up to date or is a new level.
+pub fn migrate(db: &Db) -> Result<Option<u32>, SaveLoadError> {
+    let Some(version) = db.read(read_format_version)? else {
+        return Ok(None);
+    };
+    if version > LEVEL_FORMAT_VERSION {
+        return Err(SaveLoadError::NewerFormat(version));
+    }
+    if version == LEVEL_FORMAT_VERSION {
+        return Ok(None);
+    }
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        db.write(|tx| {
+            migration(tx)?;
+            write_format_version(tx, from as u32 + 1)
+        })?;
+        info!("level migrated from version {} to {}", from, from + 1);
+    }
+    Ok(Some(version))
+}
+
+/// `None` for a new level. The levels written before the version was stored are version 0.
+pub fn read_format_version<'txn>(
+    read_txn: &'txn ReadTransaction,
+) -> Result<Option<u32>, SaveLoadError> {
+    let Some(table) = open_read_table(read_txn, TABLE_LEVEL)? else {
+        let has_blocks = open_read_table(read_txn, TABLE_BLOCKS)?.is_some();
+        return Ok(has_blocks.then_some(0));
+    };
+    let Some(value) = table.get("version")? else {
+        return Ok(Some(0));
+    };
+    let bytes = value.value().try_into().map_err(|_| {
+        SaveLoadError::Corrupt(format!("the version has {} bytes", value.value().len()))
+    })?;
+    Ok(Some(u32::from_le_bytes(bytes)))
+}
+
+pub fn write_format_version<'txn>(
+    write_txn: &'txn WriteTransaction,
+    version: u32,
+) -> Result<(), SaveLoadError> {
+    let mut table = write_txn.open_table(TABLE_LEVEL)?;
+    table.insert("version", &version.to_le_bytes()[..])?;
+    Ok(())
+}
+
+/// The level info of version 0, only the name and the seed were stored.
+#[derive(Deserialize)]
+struct LevelV0 {
+    name: String,
+    seed: u32,
+}
+
+/// A block of version 0, stored as raw bytes: the id was a byte, the flags were stored
+/// with it and each light had a byte of its own.
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct BlockV0 {
+    id: u8,
+    _properties: u8,
+    torch_light: u8,
+    sun_light: u8,
+}
+
+/// The blocks of version 0 by id, there were no content packs then.
+const BLOCK_NAMES_V0: [&str; 10] = [
+    "Air",
+    "Stone",
+    "Dirt",
+    "Cobblestone",
+    "Wood",
+    "Glowstone",
+    "Grass",
+    "Oak Planks",
+    "Diamond Block",
+    "Brick",
+];
+
+/// Writes the level info with the generator of the time, and the names of the blocks
+/// of version 0 under their old ids. The chunks are encoded again with a palette of
+/// these ids, the ones that can't be read are quarantined.
+fn migrate_v0_to_v1(write_txn: &WriteTransaction) -> Result<(), SaveLoadError> {
+    let table = write_txn.open_table(TABLE_LEVEL)?;
+    let info = match table.get("info")? {
+        Some(value) => Some(bincode::deserialize::<LevelV0>(value.value())?),
+        None => None,
+    };
+    drop(table);
+    let (name, seed) = info.map_or((String::new(), 0), |info| (info.name, info.seed));
+    let level = Level {
+        name,
+        seed,
+        generator: GeneratorConfig::default(),
+        created_at: 0,
+        game_mode: GameMode::default(),
+        spawn_point: Vec3::new(0.5, DEFAULT_SKY_HEIGHT as f32, 0.5),
+        world_time: 0,
+    };
+    write_level(write_txn, &level)?;
+    let names = BLOCK_NAMES_V0
+        .iter()
+        .enumerate()
+        .map(|(id, name)| (BlockId::from(id as u16), name.to_string()))
+        .collect();
+    write_block_names(
+        write_txn,
+        &LevelPalette::new(names, &BlueprintList::default()),
+    )?;
+
+    let mut table = write_txn.open_table(TABLE_BLOCKS)?;
+    let chunk_positions = table
+        .iter()?
+        .map(|row| Ok(row?.0.value()))
+        .collect::<Result<Vec<[i32; 3]>, SaveLoadError>>()?;
+    let chunk = Chunk::empty();
+    let mut corrupt_chunks = vec![];
+    for chunk_pos in chunk_positions {
+        let Some(value) = table.get(chunk_pos)? else {
+            continue;
+        };
+        let chunk_bytes = CHUNK_VOLUME * size_of::<BlockV0>();
+        let block_bytes = match decompress_to_vec_with_limit(value.value(), chunk_bytes) {
+            Ok(bytes) if bytes.len() == chunk_bytes => bytes,
+            _ => {
+                corrupt_chunks.push(chunk_pos);
+                continue;
+            }
+        };
+        drop(value);
+        let old_blocks: &[BlockV0] = bytemuck::cast_slice(&block_bytes);
+        for (block, old) in chunk.get_mut().iter_mut().zip(old_blocks) {
+            *block = Block {
+                id: BlockId::from(old.id as u16),
+                ..default()
+            };
+            block.set_light(LightType::Torch, old.torch_light.min(MAX_LIGHT));
+            block.set_light(LightType::Sun, old.sun_light.min(MAX_LIGHT));
+        }
+        let paletted = PalettedChunk::encode(&chunk.get_ref(), |id| id);
+        let block_compressed = compress_to_vec(&bincode::serialize(&paletted)?, 6);
+        table.insert(chunk_pos, &*block_compressed)?;
+    }
+    drop(table);
+
+    for chunk_pos in corrupt_chunks {
+        let chunk_pos = IVec3::from_array(chunk_pos);
+        warn!(
+            "the chunk at {} can't be migrated, it is quarantined",
+            chunk_pos
+        );
+        quarantine_chunk(write_txn, &chunk_pos)?;
+    }
+    Ok(())
+}
@@ -1,17 +1,16 @@
 use super::{
     connection_config, Lobby, LocalPlayerId, NetPlayerSpawned, NetworkMode, PlayerId,
-    PlayerReplica, PlayerState, PlayersReplica, ServerChannel, ServerMessages, PORT,
-    PROTOCOL_ID,
+    PlayerReplica, PlayerState, PlayersReplica, ServerChannel, ServerMessages, PORT, PROTOCOL_ID,
 };
 use crate::net::SyncUniverse;
 use crate::{ClientChannel, ClientMessages, LocalPlayer, NetSettings, PlayerUniverseChanges};
 use bevy::{prelude::*, utils::HashMap};
 use bevy_renet::{
     netcode::{ClientAuthentication, NetcodeClientTransport},
-    renet::{ RenetClient},
+    renet::RenetClient,
 };
 use mcrs_universe::CHUNK_VOLUME;
-use mcrs_universe::{chunk::Chunk, universe::Universe};
+use mcrs_universe::{block::Block, chunk::Chunk, journal::ChangeKind, universe::Universe};
 use miniz_oxide::inflate::decompress_to_vec_with_limit;
 use std::{
     net::{ToSocketAddrs, UdpSocket},
@@ -114,22 +113,31 @@ fn send_login_to_server(client: &mut RenetClient, local_id: &PlayerId) {
     client.send_message(ClientChannel::ClientMessages, message);
 }
 
-pub fn client_receive_universe(mut client: ResMut<RenetClient>, mut universe: ResMut<Universe>) {
+pub fn client_receive_universe(mut client: ResMut<RenetClient>, universe: Res<Universe>) {
     while let Some(message) = client.receive_message(ServerChannel::Universe) {
         let server_message: SyncUniverse = bincode::deserialize(&message).unwrap();
         debug!(target: "net_client", "{:?}", server_message.chunks.len());
         info!(target: "net_client", "{:?}", server_message.chunks.len());
+        // before the chunks, which may be newer than the deltas
+        for (pos, block) in server_message.deltas.iter() {
+            let (chunk_pos, _) = universe.pos_to_chunk_and_inner(pos);
+            if universe.chunks.contains_key(&chunk_pos) {
+                universe.set_chunk_block(pos, *block);
+            }
+        }
         for (pos, chunk_bytes) in server_message.chunks.iter() {
             let block_decompressed =
-                decompress_to_vec_with_limit(chunk_bytes, CHUNK_VOLUME * 4 + 12)
+                decompress_to_vec_with_limit(chunk_bytes, CHUNK_VOLUME * size_of::<Block>() + 12)
                     .expect("failed to decompress chunk");
-            if let Some(chunk) = universe.chunks.get_mut(pos) {
+            if let Some(chunk) = universe.chunks.get(pos) {
                 {
                     let mut write = chunk.get_mut();
                     let bytes: &mut [u8] = bytemuck::cast_slice_mut(&mut (*write));
                     bytes.copy_from_slice(&block_decompressed);
                 }
-                chunk.version.update();
+                chunk.compact();
+                chunk.update_version();
+                universe.journal.record_chunk(*pos, ChangeKind::Block);
             } else {
                 let chunk = Chunk::empty();
                 {
@@ -137,6 +145,7 @@ pub fn client_receive_universe(mut client: ResMut<RenetClient>, mut universe: Re
                     let bytes: &mut [u8] = bytemuck::cast_slice_mut(&mut (*write));
                     bytes.copy_from_slice(&block_decompressed);
                 }
+                chunk.compact();
                 universe.chunks.insert(*pos, chunk);
             }
         }
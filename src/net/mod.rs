@@ -4,7 +4,7 @@ pub mod server;
 
 use bevy::{prelude::*, };
 use bevy_renet::renet::{ChannelConfig, ClientId, ConnectionConfig, SendType};
-use mcrs_universe::{chunk::ChunkVersion, CHUNK_VOLUME};
+use mcrs_universe::{block::Block, chunk::ChunkVersion, CHUNK_VOLUME};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, time::Duration};
 
@@ -14,6 +14,8 @@ const PROTOCOL_ID: u64 = 7;
 pub const DEFAULT_NETWORK_ADDRESS: &str = "127.0.0.1";
 const PORT: u32 = 54550;
 pub const DEFAULT_REPLICATION_DISTANCE: u32 = 64;
+/// Above this many changed blocks a chunk is sent whole instead of block by block.
+const MAX_DELTA_BLOCKS_PER_CHUNK: usize = 512;
 
 #[derive(Resource, Debug, Clone, PartialEq, Eq, Default)]
 pub enum NetworkMode {
@@ -124,6 +126,8 @@ pub struct PlayersChunkReplication {
 pub struct ChunkReplication {
     requested: HashMap<IVec3, ChunkVersion>,
     sent: HashMap<IVec3, ChunkVersion>,
+    /// Changed blocks of sent chunks, by world position, waiting to be sent.
+    deltas: Vec<(IVec3, Block)>,
 }
 
 /// Messages sent by the server to the clients
@@ -218,7 +222,7 @@ impl ServerChannel {
             },
             ChannelConfig {
                 channel_id: Self::Universe.into(),
-                max_memory_usage_bytes: 100 * (CHUNK_VOLUME * 4),
+                max_memory_usage_bytes: 100 * (CHUNK_VOLUME * size_of::<Block>()),
                 send_type: SendType::ReliableOrdered {
                     resend_time: Duration::from_millis(200),
                 },
@@ -238,6 +242,8 @@ pub fn connection_config() -> ConnectionConfig {
 #[derive(Clone, Serialize, Deserialize, Default)]
 struct SyncUniverse {
     chunks: Vec<(IVec3, Vec<u8>)>,
+    /// Blocks changed in chunks the client already has, by world position.
+    deltas: Vec<(IVec3, Block)>,
     heightfield: Vec<(IVec2, i32)>,
 }
 
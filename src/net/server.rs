@@ -1,7 +1,7 @@
 use super::{
     connection_config, ClientChannel, ClientMessages, Lobby, 
     Player, PlayerId, PlayerReplica, PlayerState, PlayersChunkReplication, PlayersState,
-    SyncUniverse, PORT, PROTOCOL_ID,
+    SyncUniverse, MAX_DELTA_BLOCKS_PER_CHUNK, PORT, PROTOCOL_ID,
 };
 use crate::{NetSettings, RemotePlayer, ServerChannel, ServerMessages};
 use bevy::{
@@ -13,7 +13,12 @@ use bevy_renet::{
     renet::{ RenetServer, ServerEvent},
 };
 use mcrs_physics::intersect::get_chunks_in_sphere;
-use mcrs_universe::{chunk::ChunkVersion, universe::Universe, };
+use mcrs_universe::{
+    block::Block,
+    chunk::ChunkVersion,
+    journal::{JournalRead, JournalReader},
+    universe::Universe,
+};
 use miniz_oxide::deflate::compress_to_vec;
 use std::{
     net::{SocketAddr, UdpSocket},
@@ -137,7 +142,19 @@ pub fn server_send_universe(
     lobby: Res<Lobby>,
     player_query: Query<(&RemotePlayer, &Transform)>,
     settings: Res<NetSettings>,
+    mut journal_reader: Local<JournalReader>,
 ) {
+    let changes = match universe.journal.read(&mut journal_reader) {
+        JournalRead::Changes(changes) => changes,
+        // which blocks changed is unknown, every chunk is sent whole
+        JournalRead::Lost => {
+            for chunk_rep in chunk_replication.players.values_mut() {
+                chunk_rep.sent.clear();
+            }
+            HashMap::default()
+        }
+    };
+
     for id in lobby.remote_players.iter() {
         if let Some(player_tr) = player_query
             .iter()
@@ -147,15 +164,53 @@ pub fn server_send_universe(
             let request =
                 get_chunks_in_sphere(player_tr.translation, settings.replication_distance as f32);
 
+            // chunks the player has get only their changed blocks, if they are few and known
+            for (chunk_pos, chunk_changes) in changes.iter() {
+                if chunk_rep.requested.contains_key(chunk_pos) {
+                    continue;
+                }
+                let (Some(sent), Some(chunk)) =
+                    (chunk_rep.sent.get_mut(chunk_pos), universe.chunks.get(chunk_pos))
+                else {
+                    continue;
+                };
+                let changed: Vec<&IVec3> =
+                    chunk_changes.blocks.union(&chunk_changes.light).collect();
+                // a write that wasn't journaled bumped the version without saying where
+                let journaled_bumps = chunk_changes.versions
+                    + universe
+                        .journal
+                        .pending(chunk_pos)
+                        .map_or(0, |changes| changes.versions);
+                if chunk_changes.untracked.is_some()
+                    || changed.len() > MAX_DELTA_BLOCKS_PER_CHUNK
+                    || chunk.version().bumps_since(sent) > journaled_bumps
+                {
+                    chunk_rep.sent.remove(chunk_pos);
+                    continue;
+                }
+                chunk_rep.deltas.extend(
+                    changed
+                        .into_iter()
+                        .map(|xyz| (*chunk_pos + *xyz, chunk.read_block(*xyz))),
+                );
+                *sent = chunk.version();
+            }
+
             let request_versions: HashMap<IVec3, ChunkVersion> = request
                 .iter()
                 .filter_map(|chunk_pos| {
                     universe
                         .chunks
                         .get(chunk_pos)
-                        .map(|c| (*chunk_pos, c.version.clone()))
+                        .map(|c| (*chunk_pos, c.version()))
                 })
                 .filter(|(pos, v)| match chunk_rep.sent.get(pos) {
+                    // the changes of this tick will be sent as deltas on the next one
+                    Some(_) if universe
+                        .journal
+                        .pending(pos)
+                        .is_some_and(|changes| changes.untracked.is_none()) => false,
                     Some(w) => v != w,
                     None => true,
                 })
@@ -183,6 +238,12 @@ pub fn server_send_universe(
 
         let mut sync = SyncUniverse::default();
 
+        let deltas_bytes = (chunk_rep.deltas.len() * size_of::<(IVec3, Block)>()) as i32;
+        if !chunk_rep.deltas.is_empty() && available_bytes > deltas_bytes + 12 {
+            available_bytes -= deltas_bytes;
+            sync.deltas = std::mem::take(&mut chunk_rep.deltas);
+        }
+
         let mut sent_chunks = HashMap::<IVec3, ChunkVersion>::new();
 
         for (chunk_pos, version) in chunk_rep.requested.iter() {
@@ -199,7 +260,7 @@ pub fn server_send_universe(
             }
         }
 
-        if !sent_chunks.is_empty() {
+        if !sent_chunks.is_empty() || !sync.deltas.is_empty() {
             let sync_message = bincode::serialize(&sync).unwrap();
             info!(target: "net_server", "sending to {} universe ({} bytes)", player_id.name, sync_message.len());
             server.send_message(*client_id, ServerChannel::Universe, sync_message);
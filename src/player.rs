@@ -1,8 +1,9 @@
 use crate::{
-    get_single_event, read_player, settings::McrsSettings, Db, LevelOwned, LevelReady,
-    LevelReadyEvent, Lobby, LocalPlayer, LocalPlayerId, NetPlayerSpawned, NetworkMode, Player,
-    PlayerHand, PlayerId, PlayerInput, PlayerInputBuffer, PlayersReplica, PlayersState,
-    RemotePlayer, SerdePlayer, ServerChannel, ServerMessages, UniverseChange, UniverseChanges,
+    get_single_event, read_player, report_save_load_error, settings::McrsSettings, Db, Level,
+    LevelOwned, LevelReady, LevelReadyEvent, Lobby, LocalPlayer, LocalPlayerId, NetPlayerSpawned,
+    NetworkMode, Player, PlayerHand, PlayerId, PlayerInput, PlayerInputBuffer, PlayersReplica,
+    PlayersState, RemotePlayer, SaveLoadErrorEvent, SerdePlayer, ServerChannel, ServerMessages,
+    UniverseChange, UniverseChanges,
 };
 use bevy::{
     core_pipeline::tonemapping::{DebandDither, Tonemapping},
@@ -237,19 +238,21 @@ pub fn spawn_players_server(
     lobby: Res<Lobby>,
     level_ready: Option<Res<LevelReady>>,
     db: Option<Res<Db>>,
+    level: Option<Res<Level>>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut server: ResMut<RenetServer>,
     mut players_state: ResMut<PlayersState>,
     mut players_replica: ResMut<PlayersReplica>,
+    mut errors: EventWriter<SaveLoadErrorEvent>,
 ) {
-    let (Some(db), Some(_)) = (db, level_ready.as_ref()) else {
+    let (Some(db), Some(level), Some(_)) = (db, level, level_ready.as_ref()) else {
         return;
     };
 
     for id in lobby.local_players.iter() {
         if !spawned.local_players.contains_key(id) {
-            let serde_player = get_or_spawn_player(&db, &id.name);
+            let serde_player = get_or_spawn_player(&db, &level, &id.name, &mut errors);
             let entity = spawn_local_player(&mut commands, &settings, serde_player, id.clone());
             spawned.local_players.insert(id.clone(), entity);
         }
@@ -257,7 +260,7 @@ pub fn spawn_players_server(
 
     for id in lobby.remote_players.iter() {
         if !spawned.remote_players.contains_key(id) {
-            let serde_player = get_or_spawn_player(&db, &id.name);
+            let serde_player = get_or_spawn_player(&db, &level, &id.name, &mut errors);
             let entity = spawn_remote_player(
                 &mut commands,
                 serde_player.clone(),
@@ -311,11 +314,13 @@ pub fn spawn_local_players_on_level_loaded(
     mut spawned: ResMut<LobbySpawnedPlayers>,
     lobby: Option<ResMut<Lobby>>,
     db: Option<Res<Db>>,
+    level: Option<Res<Level>>,
+    mut errors: EventWriter<SaveLoadErrorEvent>,
 ) {
     let Some(_) = get_single_event(level_ready_event) else {
         return;
     };
-    let Some(db) = db else {
+    let (Some(db), Some(level)) = (db, level) else {
         return;
     };
     let Some(mut lobby) = lobby else {
@@ -327,7 +332,7 @@ pub fn spawn_local_players_on_level_loaded(
             let Some(id) = local_player_id.id.clone() else {
                 panic!("No local player name set");
             };
-            let serde_player = get_or_spawn_player(&db, &id.name);
+            let serde_player = get_or_spawn_player(&db, &level, &id.name, &mut errors);
             let entity = spawn_local_player(&mut commands, &settings, serde_player, id.clone());
             spawned.local_players.insert(id.clone(), entity);
             lobby.local_players.push(id);
@@ -341,7 +346,7 @@ pub fn spawn_local_players_on_level_loaded(
                 .unwrap_or(PlayerId {
                     name: format!("Nameless"),
                 });
-            let serde_player = get_or_spawn_player(&db, &id.name);
+            let serde_player = get_or_spawn_player(&db, &level, &id.name, &mut errors);
             let entity = spawn_local_player(&mut commands, &settings, serde_player, id.clone());
             spawned.local_players.insert(id.clone(), entity);
         }
@@ -349,16 +354,26 @@ pub fn spawn_local_players_on_level_loaded(
     }
 }
 
-pub fn get_or_spawn_player(db: &Db, player_name: &str) -> SerdePlayer {
-    match db.get(|tx| read_player(tx, player_name)) {
+/// A player that can't be read starts again at the spawn point.
+pub fn get_or_spawn_player(
+    db: &Db,
+    level: &Level,
+    player_name: &str,
+    errors: &mut EventWriter<SaveLoadErrorEvent>,
+) -> SerdePlayer {
+    let stored = db.read(|tx| read_player(tx, player_name));
+    if let Err(err) = &stored {
+        let message = format!("Failed to load the player {}: {}", player_name, err);
+        report_save_load_error(errors, message);
+    }
+    match stored.ok().flatten() {
         Some(p) => {
             info!("Found player in save.");
             p
         }
         None => SerdePlayer {
             name: player_name.to_string(),
-            // Todo: find spawnpoint in spawn chunks
-            translation: Vec3::ZERO,
+            translation: level.spawn_point,
             body_rotation: Quat::IDENTITY,
             camera_rotation: Quat::IDENTITY,
         },
@@ -503,16 +518,30 @@ pub fn terrain_editing(
             for input in input.buffer.iter() {
                 match input {
                     PlayerInput::Placing(true) => {
-                        if let Some(block_id) = hand.block_id {
+                        if let Some(block_bp) =
+                            hand.block_id.and_then(|id| bp.blocks.get_checked(&id))
+                        {
                             let block_pos = hit.grid_pos + hit.normal();
                             if !intersect_aabb_block(
                                 tr_player.translation,
                                 rigidbody.size,
                                 block_pos,
                             ) {
+                                let mut block = Block::new(block_bp);
+                                // oriented blocks follow the face they are placed on
+                                let axis = match hit.normal().abs() {
+                                    IVec3::X => "x",
+                                    IVec3::Z => "z",
+                                    _ => "y",
+                                };
+                                if let Some(state) =
+                                    block_bp.with_state_value(block.state, "axis", axis)
+                                {
+                                    block.state = state;
+                                }
                                 changes.push(UniverseChange::Add {
                                     pos: hit.grid_pos + hit.normal(),
-                                    block: Block::new(bp.blocks.get(&block_id)),
+                                    block,
                                 });
                             }
                         }
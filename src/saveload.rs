@@ -1,22 +1,57 @@
 use crate::{
-    terrain::{get_spawn_chunks, UniverseChanges},
+    biome::{Biome, Biomes},
+    generator::{GeneratorConfig, GeneratorRegistry, DEFAULT_SKY_HEIGHT},
+    level_writer::{LevelWriter, WriteBatch},
+    migration::{migrate, write_format_version},
+    settings::McrsSettings,
+    terrain::{get_spawn_chunks, ChunkGenerationRequest, GenerationProgress, UniverseChanges},
     FixedMainSet, LightSources, Player, SunBeam, SunBeams,
 };
-use bevy::{prelude::*, utils::HashSet};
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
 use bytemuck::{Pod, Zeroable};
-use mcrs_physics::{
-    TickStep,
+use mcrs_physics::{run_if_tickstep, TickStep};
+use mcrs_universe::{
+    block::BlockId,
+    block_entity::BlockEntity,
+    chunk::{Chunk, ChunkVersion},
+    palette::{LevelPalette, PalettedChunk},
+    reload::BlueprintsReloadedEvent,
+    universe::Universe,
+    Blueprints, CHUNK_AREA, CHUNK_SIDE, CHUNK_VOLUME,
+};
+use miniz_oxide::{
+    deflate::compress_to_vec,
+    inflate::{decompress_to_vec_with_limit, DecompressError},
+};
+use redb::{
+    CommitError, Database, Error, Key, ReadOnlyTable, ReadTransaction, StorageError, Table,
+    TableDefinition, TableError, TransactionError, Value, WriteTransaction,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+    mem::size_of,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use mcrs_universe::{chunk::Chunk, universe::Universe, CHUNK_AREA, CHUNK_SIDE, CHUNK_VOLUME};
-use miniz_oxide::{deflate::compress_to_vec, inflate::decompress_to_vec_with_limit};
-use redb::{Database, Error, ReadTransaction, Table, TableDefinition, WriteTransaction};
-use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf};
 
 pub const TABLE_BLOCKS: TableDefinition<[i32; 3], &[u8]> = TableDefinition::new("blocks");
+pub const TABLE_BLOCK_ENTITIES: TableDefinition<[i32; 3], &[u8]> =
+    TableDefinition::new("block_entities");
 pub const TABLE_SUN_BEAMS: TableDefinition<[i32; 2], &[u8]> = TableDefinition::new("sun_beams");
+pub const TABLE_BIOMES: TableDefinition<[i32; 2], &[u8]> = TableDefinition::new("biomes");
 pub const TABLE_PLAYERS: TableDefinition<&str, &[u8]> = TableDefinition::new("players");
 pub const TABLE_LEVEL: TableDefinition<&str, &[u8]> = TableDefinition::new("level");
+/// Chunks that couldn't be read, by `quarantine_chunk`.
+pub const TABLE_QUARANTINED_BLOCKS: TableDefinition<[i32; 3], &[u8]> =
+    TableDefinition::new("quarantined_blocks");
+
+/// Version of the layout of the level database, stored in the `version` row of `TABLE_LEVEL`.
+/// Any change to the stored bytes, like a new field of `Block`, `SerdePlayer` or `BeamPod`,
+/// must bump it and add a migration from the previous version.
+pub const LEVEL_FORMAT_VERSION: u32 = 1;
 
 pub struct SaveLoadPlugin;
 
@@ -26,50 +61,191 @@ impl Plugin for SaveLoadPlugin {
             .add_event::<CloseLevelEvent>()
             .add_event::<SaveLevelEvent>()
             .add_event::<LevelReadyEvent>()
+            .add_event::<SaveLoadErrorEvent>()
+            .init_resource::<SaveState>()
             .add_systems(
                 FixedUpdate,
-                (open_level, save_level, close_level, is_level_ready)
+                (
+                    open_level,
+                    receive_write_reports,
+                    save_level,
+                    close_level,
+                    is_level_ready,
+                    advance_world_time.run_if(run_if_tickstep),
+                )
                     .chain()
                     .in_set(FixedMainSet::SaveLoad),
-            );
+            )
+            .add_systems(Update, refresh_level_palette)
+            .add_systems(Last, save_level_on_exit);
     }
 }
 
 #[derive(Resource)]
 pub struct Db {
-    db: Database,
+    db: Arc<Database>,
+    /// Translates the block ids between the save file and the loaded blueprints.
+    pub palette: LevelPalette,
+    /// Does the writes of the saves and of the unloaded and generated chunks.
+    pub writer: LevelWriter,
 }
 
-// Todo: make a new error that allows removal of .expect
+/// Why the level couldn't be read or written.
+#[derive(Debug)]
+pub enum SaveLoadError {
+    Db(Error),
+    Bincode(bincode::Error),
+    Decompress(DecompressError),
+    /// The stored bytes don't have the layout they should.
+    Corrupt(String),
+    /// The level was written by a newer version of the game.
+    NewerFormat(u32),
+    /// The writer thread is gone, the level is being closed.
+    WriterStopped,
+}
+
+impl Display for SaveLoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveLoadError::Db(err) => write!(f, "database error: {}", err),
+            SaveLoadError::Bincode(err) => write!(f, "malformed data: {}", err),
+            SaveLoadError::Decompress(err) => {
+                write!(f, "failed to decompress: {:?}", err.status)
+            }
+            SaveLoadError::Corrupt(reason) => write!(f, "corrupt data: {}", reason),
+            SaveLoadError::NewerFormat(version) => write!(
+                f,
+                "the level has the format version {}, the newest known is {}",
+                version, LEVEL_FORMAT_VERSION
+            ),
+            SaveLoadError::WriterStopped => write!(f, "the level writer is stopped"),
+        }
+    }
+}
+
+impl SaveLoadError {
+    /// Whether the stored bytes are wrong, rather than the database unreachable.
+    pub fn is_corrupt(&self) -> bool {
+        matches!(
+            self,
+            SaveLoadError::Bincode(_) | SaveLoadError::Decompress(_) | SaveLoadError::Corrupt(_)
+        )
+    }
+}
+
+impl From<bincode::Error> for SaveLoadError {
+    fn from(err: bincode::Error) -> Self {
+        SaveLoadError::Bincode(err)
+    }
+}
+
+impl From<DecompressError> for SaveLoadError {
+    fn from(err: DecompressError) -> Self {
+        SaveLoadError::Decompress(err)
+    }
+}
+
+/// Every error of redb converts into `redb::Error`.
+macro_rules! from_redb_error {
+    ($($err:ty),*) => {
+        $(
+            impl From<$err> for SaveLoadError {
+                fn from(err: $err) -> Self {
+                    SaveLoadError::Db(err.into())
+                }
+            }
+        )*
+    };
+}
+
+from_redb_error!(
+    Error,
+    TableError,
+    StorageError,
+    TransactionError,
+    CommitError
+);
+
+/// Sent when the level couldn't be read or written, for the UI to show.
+#[derive(Event, Debug, Clone)]
+pub struct SaveLoadErrorEvent {
+    pub message: String,
+}
+
+/// Logs the error and tells the UI about it.
+pub fn report_save_load_error(events: &mut EventWriter<SaveLoadErrorEvent>, message: String) {
+    error!("{}", message);
+    events.send(SaveLoadErrorEvent { message });
+}
 
 impl Db {
-    pub fn write<F>(&self, f: F) -> Result<(), Error>
+    pub fn new(db: Database) -> Self {
+        let db = Arc::new(db);
+        Self {
+            writer: LevelWriter::spawn(db.clone()),
+            db,
+            palette: LevelPalette::default(),
+        }
+    }
+
+    /// Writes right away, on the calling thread. What's written often goes to `writer`.
+    pub fn write<F>(&self, f: F) -> Result<(), SaveLoadError>
     where
-        F: FnOnce(&WriteTransaction) -> Result<(), Error>,
+        F: FnOnce(&WriteTransaction) -> Result<(), SaveLoadError>,
     {
-        let write_txn = self.db.begin_write()?;
-        {
-            f(&write_txn)?
-        }
-        write_txn.commit()?;
-        Ok(())
+        write_transaction(&self.db, f)
     }
 
-    pub fn get<F, R>(&self, f: F) -> Option<R>
+    pub fn read<F, R>(&self, f: F) -> Result<R, SaveLoadError>
     where
-        F: FnOnce(&ReadTransaction) -> Option<R>,
+        F: FnOnce(&ReadTransaction) -> Result<R, SaveLoadError>,
     {
-        let read_txn = self.db.begin_read().ok()?;
-        let opt = f(&read_txn);
+        let read_txn = self.db.begin_read()?;
+        let res = f(&read_txn);
         drop(read_txn);
-        opt
+        res
+    }
+}
+
+pub fn write_transaction<F>(db: &Database, f: F) -> Result<(), SaveLoadError>
+where
+    F: FnOnce(&WriteTransaction) -> Result<(), SaveLoadError>,
+{
+    let write_txn = db.begin_write()?;
+    {
+        f(&write_txn)?
     }
+    write_txn.commit()?;
+    Ok(())
 }
 
+/// Stored in the `info` row of `TABLE_LEVEL`.
 #[derive(Resource, Debug, Clone, Serialize, Deserialize)]
 pub struct Level {
     pub name: String,
     pub seed: u32,
+    /// The generator the level was created with.
+    pub generator: GeneratorConfig,
+    /// Seconds since the unix epoch.
+    pub created_at: u64,
+    pub game_mode: GameMode,
+    /// Where the new players appear.
+    pub spawn_point: Vec3,
+    /// Ticks played in the level.
+    pub world_time: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameMode {
+    Survival,
+    #[default]
+    Creative,
+}
+
+impl Display for GameMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
 }
 
 #[derive(Resource, Debug, Clone)]
@@ -83,6 +259,10 @@ pub struct LevelOwned;
 #[derive(Event, Debug, Clone)]
 pub struct OpenLevelEvent {
     pub level_name: String,
+    /// Used only if the level is created.
+    pub generator: GeneratorConfig,
+    /// Used only if the level is created, random if `None`.
+    pub seed: Option<u32>,
 }
 
 #[derive(Event, Debug, Clone)]
@@ -100,6 +280,9 @@ pub fn open_level(
     mut tickstep: ResMut<TickStep>,
     existing_level: Option<Res<Level>>,
     existing_db: Option<Res<Db>>,
+    bp: Res<Blueprints>,
+    registry: Res<GeneratorRegistry>,
+    mut errors: EventWriter<SaveLoadErrorEvent>,
 ) {
     let Some(event) = get_single_event(event_reader) else {
         return;
@@ -111,8 +294,8 @@ pub fn open_level(
     }
 
     if existing_db.is_some() {
-        warn!("Another db is open");
-        panic!();
+        report_save_load_error(&mut errors, "Another db is open".to_string());
+        return;
     }
 
     if event.level_name.is_empty() {
@@ -122,19 +305,116 @@ pub fn open_level(
 
     let path = get_save_path().map_or(String::new(), |p| p.to_str().unwrap_or("").to_string());
 
-    let Ok(db) = Database::create(&format!("{}/{}.redb", path, event.level_name)) else {
-        warn!("Failed to open level db");
-        return;
+    let db = match Database::create(&format!("{}/{}.redb", path, event.level_name)) {
+        Ok(db) => db,
+        Err(err) => {
+            let message = format!("Failed to open level {}: {}", event.level_name, err);
+            report_save_load_error(&mut errors, message);
+            return;
+        }
     };
 
-    // todo read `Level`
+    let mut db = Db::new(db);
 
-    commands.insert_resource(Db { db });
+    match migrate(&db) {
+        Ok(Some(version)) => info!(
+            "level {} upgraded from version {} to {}",
+            event.level_name, version, LEVEL_FORMAT_VERSION
+        ),
+        Ok(None) => {}
+        Err(err) => {
+            let message = format!("Failed to upgrade level {}: {}", event.level_name, err);
+            report_save_load_error(&mut errors, message);
+            return;
+        }
+    }
 
-    commands.insert_resource(Level {
-        name: event.level_name,
-        seed: 0,
+    let stored = db.read(|tx| {
+        Ok((
+            read_level_packs(tx)?,
+            read_block_names(tx)?,
+            read_level(tx)?,
+        ))
     });
+    let (stored_packs, stored_block_names, stored_level) = match stored {
+        Ok(stored) => stored,
+        Err(err) => {
+            let message = format!("Failed to read level {}: {}", event.level_name, err);
+            report_save_load_error(&mut errors, message);
+            return;
+        }
+    };
+
+    // the blocks of a missing pack would be lost, so the level isn't opened at all
+    let stored_packs = stored_packs.unwrap_or_default();
+    let missing_packs: Vec<&String> = stored_packs
+        .iter()
+        .filter(|p| !bp.packs.contains(p))
+        .collect();
+    if !missing_packs.is_empty() {
+        let message = format!(
+            "Failed to open level {}, it needs the content packs {:?} which aren't loaded",
+            event.level_name, missing_packs
+        );
+        report_save_load_error(&mut errors, message);
+        return;
+    }
+
+    // the block names are stored as soon as a level is created
+    let is_new_level = stored_block_names.is_none();
+    let block_names = stored_block_names.unwrap_or_default();
+    db.palette = LevelPalette::new(block_names, &bp.blocks);
+    for name in db.palette.missing() {
+        warn!(
+            "the level uses the unknown block {}, it will be loaded as air",
+            name
+        );
+    }
+    let level = match stored_level {
+        Some(level) => Level {
+            name: event.level_name.clone(),
+            ..level
+        },
+        None if is_new_level => {
+            let seed = event.seed.unwrap_or_else(rand::random);
+            new_level(
+                &event.level_name,
+                seed,
+                event.generator.clone(),
+                &registry,
+                &bp,
+            )
+        }
+        None => {
+            let message = format!("The info of level {} is missing", event.level_name);
+            report_save_load_error(&mut errors, message);
+            return;
+        }
+    };
+
+    // store the ids of new blueprints before any chunk uses them
+    let written = db.write(|tx| {
+        write_format_version(tx, LEVEL_FORMAT_VERSION)?;
+        write_level(tx, &level)?;
+        // the level keeps needing the packs it was created with, not the ones loaded later
+        if is_new_level {
+            write_level_packs(tx, &bp.packs)?;
+        }
+        write_block_names(tx, &db.palette)
+    });
+    if let Err(err) = written {
+        let message = format!("Failed to write level {}: {}", event.level_name, err);
+        report_save_load_error(&mut errors, message);
+        return;
+    }
+
+    commands.insert_resource(db);
+
+    info!(
+        "level {} is generated by {} with the seed {}",
+        level.name, level.generator, level.seed
+    );
+    commands.insert_resource(level);
 
     *tickstep = TickStep::Tick;
 
@@ -142,17 +422,76 @@ pub fn open_level(
     // The chunks and sun beams are loaded when they are needed
 }
 
+/// The players of a new level spawn above the top of the world at the origin.
+fn new_level(
+    name: &str,
+    seed: u32,
+    generator: GeneratorConfig,
+    registry: &GeneratorRegistry,
+    bp: &Blueprints,
+) -> Level {
+    let spawn_height = match registry.build(&generator, seed, bp) {
+        Ok(world_generator) => world_generator.sky_height(IVec2::ZERO),
+        Err(err) => {
+            warn!("the spawn point of level {} isn't known: {}", name, err);
+            0
+        }
+    };
+    Level {
+        name: name.to_string(),
+        seed,
+        generator,
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs()),
+        game_mode: GameMode::default(),
+        spawn_point: Vec3::new(0.5, spawn_height as f32, 0.5),
+        world_time: 0,
+    }
+}
+
+pub fn advance_world_time(level: Option<ResMut<Level>>) {
+    if let Some(mut level) = level {
+        level.world_time += 1;
+    }
+}
+
+/// New blueprints may add blocks that need a level id.
+pub fn refresh_level_palette(
+    mut events: EventReader<BlueprintsReloadedEvent>,
+    db: Option<ResMut<Db>>,
+    bp: Res<Blueprints>,
+    mut errors: EventWriter<SaveLoadErrorEvent>,
+) {
+    if events.read().count() == 0 {
+        return;
+    }
+    let Some(mut db) = db else {
+        return;
+    };
+    db.palette = LevelPalette::new(db.palette.names(), &bp.blocks);
+    if let Err(err) = db.write(|tx| write_block_names(tx, &db.palette)) {
+        let message = format!("Failed to write the level block palette: {}", err);
+        report_save_load_error(&mut errors, message);
+    }
+}
+
 pub fn close_level(
     event_reader: EventReader<CloseLevelEvent>,
     mut commands: Commands,
     existing_level: Option<Res<Level>>,
     existing_db: Option<Res<Db>>,
-    mut universe: ResMut<Universe>,
+    universe: Res<Universe>,
     mut universe_changes: ResMut<UniverseChanges>,
     mut tickstep: ResMut<TickStep>,
     level_owned_query: Query<(Entity, &LevelOwned)>,
     mut light_sources: ResMut<LightSources>,
     mut sun_beams: ResMut<SunBeams>,
+    mut biomes: ResMut<Biomes>,
+    mut request: ResMut<ChunkGenerationRequest>,
+    mut progress: ResMut<GenerationProgress>,
+    mut save_state: ResMut<SaveState>,
+    mut errors: EventWriter<SaveLoadErrorEvent>,
 ) {
     let Some(_) = get_single_event(event_reader) else {
         return;
@@ -164,10 +503,14 @@ pub fn close_level(
     }
 
     if existing_db.is_none() {
-        warn!("No db was opened");
-        panic!();
+        report_save_load_error(&mut errors, "No db was opened".to_string());
+        return;
     }
 
+    // waits for the batches of the last save, the level file can be opened again after this
+    if let Some(db) = existing_db {
+        db.writer.finish();
+    }
     commands.remove_resource::<Db>();
     commands.remove_resource::<Level>();
     commands.remove_resource::<LevelReady>();
@@ -177,6 +520,12 @@ pub fn close_level(
     light_sources.leaked_sources.clear();
     light_sources.chunked_sources.clear();
     sun_beams.beams.clear();
+    biomes.regions.clear();
+    // cancels the generation tasks of the level
+    request.clear();
+    *progress = GenerationProgress::default();
+    // the level was written by `save_level` before it's closed
+    *save_state = SaveState::default();
     *tickstep = TickStep::STOP;
 
     for (entity, _) in level_owned_query.iter() {
@@ -218,74 +567,261 @@ pub struct BeamPod {
     top: i32,
 }
 
-pub fn save_level(
-    event_reader: EventReader<SaveLevelEvent>,
-    level: Option<Res<Level>>,
+/// Chunks snapshotted in each tick by a save, the others wait for the next ticks.
+pub const MAX_SAVED_CHUNKS_PER_TICK: usize = 32;
+
+/// What of the loaded level is already written to the db, or on its way to the writer.
+#[derive(Resource, Default)]
+pub struct SaveState {
+    /// The version of each loaded chunk when it was last sent to the writer or read.
+    pub saved_versions: HashMap<IVec3, ChunkVersion>,
+    /// Chunks sent to the writer that aren't written yet, with the id of their batch.
+    /// They are loaded from here rather than from the db.
+    pub writing: HashMap<IVec3, (u64, Arc<Chunk>)>,
+    /// Chunks of the save in progress that aren't sent yet.
+    pending: Vec<IVec3>,
+    /// Time since the last save started.
+    since_save: Duration,
+}
+
+impl SaveState {
+    /// Whether the chunk changed since it was last sent to the writer or read.
+    pub fn is_dirty(&self, chunk_pos: &IVec3, chunk: &Chunk) -> bool {
+        self.saved_versions.get(chunk_pos) != Some(&chunk.version())
+    }
+
+    /// Hands the batch to the writer. Its chunks count as saved unless the writer fails.
+    pub fn send(&mut self, db: &Db, batch: WriteBatch) -> Result<(), SaveLoadError> {
+        // the versions are taken before the blocks are encoded, at worst the chunks are saved twice
+        let chunks: Vec<(IVec3, Arc<Chunk>, ChunkVersion)> = batch
+            .chunks
+            .iter()
+            .map(|(chunk_pos, chunk)| (*chunk_pos, chunk.clone(), chunk.version()))
+            .collect();
+        let id = db.writer.send(batch)?;
+        for (chunk_pos, chunk, version) in chunks {
+            self.saved_versions.insert(chunk_pos, version);
+            self.writing.insert(chunk_pos, (id, chunk));
+        }
+        Ok(())
+    }
+}
+
+/// Takes the reports of the writer. The chunks that couldn't be written are dirty again,
+/// and the ones unloaded meanwhile are loaded back so that their changes aren't lost.
+pub fn receive_write_reports(
+    db: Option<Res<Db>>,
     universe: Res<Universe>,
-    players_query: Query<(Entity, &Player, &Children)>,
-    query_transform: Query<&Transform>,
-    sun_beams: Res<SunBeams>,
-    existing_db: Option<ResMut<Db>>,
+    mut save_state: ResMut<SaveState>,
+    mut errors: EventWriter<SaveLoadErrorEvent>,
 ) {
-    let Some(_) = get_single_event(event_reader) else {
+    let Some(db) = db else {
         return;
     };
+    for report in db.writer.reports() {
+        for chunk_pos in report.chunks.iter() {
+            // a later batch writes the chunk again, its report decides
+            let is_latest = save_state
+                .writing
+                .get(chunk_pos)
+                .is_some_and(|(id, _)| *id == report.id);
+            if !is_latest {
+                continue;
+            }
+            let Some((_, chunk)) = save_state.writing.remove(chunk_pos) else {
+                continue;
+            };
+            if report.error.is_some() {
+                save_state.saved_versions.remove(chunk_pos);
+                if !universe.chunks.contains_key(chunk_pos) {
+                    universe.chunks.insert(*chunk_pos, chunk);
+                }
+            }
+        }
+        match report.error {
+            Some(err) => report_save_load_error(&mut errors, format!("Failed to save: {}", err)),
+            None if report.ends_save => info!("save successful"),
+            None => {}
+        }
+    }
+}
 
-    let Some(level) = level.as_ref() else {
-        warn!("There is no level to save");
-        return;
-    };
+/// Everything a save writes.
+#[derive(SystemParam)]
+pub struct LevelSaver<'w, 's> {
+    level: Option<Res<'w, Level>>,
+    db: Option<Res<'w, Db>>,
+    universe: Res<'w, Universe>,
+    players_query: Query<'w, 's, (Entity, &'static Player, &'static Children)>,
+    query_transform: Query<'w, 's, &'static Transform>,
+    sun_beams: Res<'w, SunBeams>,
+    biomes: Res<'w, Biomes>,
+    state: ResMut<'w, SaveState>,
+    errors: EventWriter<'w, SaveLoadErrorEvent>,
+}
 
-    let Some(db) = existing_db else {
-        warn!("No db was opened");
-        panic!();
-    };
+impl LevelSaver<'_, '_> {
+    /// Sends the level info and the players to the writer,
+    /// and queues the chunks that changed since they were sent.
+    fn start(&mut self) {
+        let (Some(level), Some(db)) = (self.level.as_ref(), self.db.as_ref()) else {
+            return;
+        };
+
+        info!("saving to: {:?}", get_save_path());
+
+        let mut serde_players = vec![];
+        for (player_entity, player, children) in self.players_query.iter() {
+            let player_tr = self.query_transform.get(player_entity).unwrap();
+            let camera_tr = self.query_transform.get(children[0]).unwrap();
+            let player = SerdePlayer {
+                name: player.id.name.clone(),
+                translation: player_tr.translation,
+                body_rotation: player_tr.rotation,
+                camera_rotation: camera_tr.rotation,
+            };
+            serde_players.push(player);
+        }
+
+        let pending: Vec<IVec3> = self
+            .universe
+            .chunks
+            .iter()
+            .filter(|(chunk_pos, chunk)| self.state.is_dirty(chunk_pos, chunk))
+            .map(|(chunk_pos, _)| chunk_pos)
+            .collect();
+        let batch = WriteBatch {
+            palette: db.palette.clone(),
+            level: Some((**level).clone()),
+            players: serde_players,
+            ends_save: pending.is_empty(),
+            ..default()
+        };
+        // a failed save waits for the next autosave too, instead of being retried every tick
+        self.state.since_save = Duration::ZERO;
+        if let Err(err) = db.writer.send(batch) {
+            report_save_load_error(&mut self.errors, format!("Failed to save: {}", err));
+            return;
+        }
+
+        self.state.pending = pending;
+    }
 
-    info!("saving to: {:?}", get_save_path());
-
-    let mut serde_players = vec![];
-    for (player_entity, player, children) in players_query.iter() {
-        let player_tr = query_transform.get(player_entity).unwrap();
-        let camera_tr = query_transform.get(children[0]).unwrap();
-        let player = SerdePlayer {
-            name: player.id.name.clone(),
-            translation: player_tr.translation,
-            body_rotation: player_tr.rotation,
-            camera_rotation: camera_tr.rotation,
+    /// Sends up to `max` of the queued chunks to the writer in one batch.
+    fn send_pending(&mut self, max: usize) {
+        if self.state.pending.is_empty() {
+            return;
+        }
+        let Some(db) = self.db.as_ref() else {
+            return;
         };
-        serde_players.push(player);
+
+        // chunks unloaded meanwhile were sent when they were unloaded
+        let count = max.min(self.state.pending.len());
+        let chunks: Vec<(IVec3, Arc<Chunk>)> = self
+            .state
+            .pending
+            .drain(..count)
+            .filter_map(|chunk_pos| Some((chunk_pos, self.universe.chunks.get(&chunk_pos)?)))
+            .collect();
+
+        let mut batch =
+            WriteBatch::with_chunks(db.palette.clone(), chunks, &self.sun_beams, &self.biomes);
+        batch.ends_save = self.state.pending.is_empty();
+        if let Err(err) = self.state.send(db, batch) {
+            self.state.pending.clear();
+            report_save_load_error(&mut self.errors, format!("Failed to save: {}", err));
+        }
     }
+}
 
-    db.write(|tx| {
-        write_level(tx, level)?;
-        write_sun_beams(tx, &sun_beams, &universe)?;
-        write_chunks(tx, &universe)?;
+/// Saves on request, every `autosave_interval_seconds` and before the level is closed.
+/// The chunks of a save are spread across the next ticks, unless the level is closing.
+/// The writes themselves are done by the writer thread.
+pub fn save_level(
+    mut save_events: EventReader<SaveLevelEvent>,
+    mut close_events: EventReader<CloseLevelEvent>,
+    time: Res<Time>,
+    settings: Res<McrsSettings>,
+    mut saver: LevelSaver,
+) {
+    let requested = save_events.read().count() > 0;
+    let closing = close_events.read().count() > 0;
 
-        let mut table = tx.open_table(TABLE_PLAYERS)?;
-        for serde_player in serde_players.iter() {
-            write_player(tx, serde_player, Some(&mut table))?
+    if saver.level.is_none() {
+        if requested {
+            warn!("There is no level to save");
         }
+        return;
+    }
 
-        Ok(())
-    })
-    .expect("db write failed");
+    saver.state.since_save += time.delta();
+    let interval = Duration::from_secs(settings.autosave_interval_seconds as u64);
+    let autosave = !interval.is_zero() && saver.state.since_save >= interval;
+    if autosave {
+        info!("autosave");
+    }
 
-    info!("save successful");
+    if requested || autosave || closing {
+        saver.start();
+    }
+    saver.send_pending(if closing {
+        usize::MAX
+    } else {
+        MAX_SAVED_CHUNKS_PER_TICK
+    });
 }
 
-pub fn write_level<'txn>(write_txn: &'txn WriteTransaction, level: &Level) -> Result<(), Error> {
+/// Writes everything that changed when the app is closed with a level open,
+/// and waits for the writer to be done.
+pub fn save_level_on_exit(mut exit_events: EventReader<AppExit>, mut saver: LevelSaver) {
+    if exit_events.read().count() == 0 || saver.level.is_none() {
+        return;
+    }
+    saver.start();
+    saver.send_pending(usize::MAX);
+    if let Some(db) = saver.db.as_ref() {
+        db.writer.finish();
+    }
+}
+
+pub fn write_level<'txn>(
+    write_txn: &'txn WriteTransaction,
+    level: &Level,
+) -> Result<(), SaveLoadError> {
     let mut table = write_txn.open_table(TABLE_LEVEL)?;
-    let bytes = bincode::serialize(level).expect("failed to serialize level");
+    let bytes = bincode::serialize(level)?;
     table.insert("info", &*bytes)?;
     Ok(())
 }
 
+pub fn write_block_names<'txn>(
+    write_txn: &'txn WriteTransaction,
+    palette: &LevelPalette,
+) -> Result<(), SaveLoadError> {
+    let mut table = write_txn.open_table(TABLE_LEVEL)?;
+    let bytes = bincode::serialize(&palette.names())?;
+    table.insert("block_names", &*bytes)?;
+    Ok(())
+}
+
+/// Namespaces of the content packs the level was created with.
+pub fn write_level_packs<'txn>(
+    write_txn: &'txn WriteTransaction,
+    packs: &[String],
+) -> Result<(), SaveLoadError> {
+    let mut table = write_txn.open_table(TABLE_LEVEL)?;
+    let bytes = bincode::serialize(packs)?;
+    table.insert("packs", &*bytes)?;
+    Ok(())
+}
+
 pub fn write_player<'txn>(
     write_txn: &'txn WriteTransaction,
     player: &SerdePlayer,
     table: Option<&mut Table<'txn, &str, &[u8]>>,
-) -> Result<(), Error> {
-    let player_bytes = bincode::serialize(player).expect("failed player serialization");
+) -> Result<(), SaveLoadError> {
+    let player_bytes = bincode::serialize(player)?;
     let table = if let Some(table) = table {
         table
     } else {
@@ -295,29 +831,8 @@ pub fn write_player<'txn>(
     Ok(())
 }
 
-pub fn write_sun_beams<'txn>(
-    write_txn: &'txn WriteTransaction,
-    sun_beams: &SunBeams,
-    universe: &Universe,
-) -> Result<(), Error> {
-    let mut sun_beams_by_region = HashSet::<IVec2>::new();
-    for (xz, _) in sun_beams.beams.iter() {
-        let (region_pos, _) = universe.pos_to_region_and_inner(xz);
-        sun_beams_by_region.insert(region_pos);
-    }
-    let mut table = write_txn.open_table(TABLE_SUN_BEAMS)?;
-    for region_pos in sun_beams_by_region {
-        write_sun_beams_region(write_txn, region_pos, sun_beams, Some(&mut table))?
-    }
-    Ok(())
-}
-
-pub fn write_sun_beams_region<'txn>(
-    write_txn: &'txn WriteTransaction,
-    region_pos: IVec2,
-    sun_beams: &SunBeams,
-    table: Option<&mut Table<'txn, [i32; 2], &[u8]>>,
-) -> Result<(), Error> {
+/// The sun beams of the region, the columns without a beam get the default sky.
+pub fn sun_beams_region(region_pos: IVec2, sun_beams: &SunBeams) -> [BeamPod; CHUNK_AREA] {
     let mut region = [BeamPod::default(); CHUNK_AREA];
     for (x, z) in (0..CHUNK_SIDE as i32)
         .map(|x| (0..CHUNK_SIDE as i32).map(move |z| (x, z)))
@@ -327,13 +842,22 @@ pub fn write_sun_beams_region<'txn>(
         let beam = if let Some(beam) = sun_beams.beams.get(&xz) {
             beam
         } else {
-            &SunBeam::new_top(&xz)
+            &SunBeam::new_top(DEFAULT_SKY_HEIGHT)
         };
         let region_index = (x + z * CHUNK_SIDE as i32) as usize;
         region[region_index].bottom = beam.bottom;
         region[region_index].top = beam.top;
     }
-    let beams_bytes: &[u8] = bytemuck::cast_slice(&region);
+    region
+}
+
+pub fn write_sun_beams_region<'txn>(
+    write_txn: &'txn WriteTransaction,
+    region_pos: IVec2,
+    region: &[BeamPod; CHUNK_AREA],
+    table: Option<&mut Table<'txn, [i32; 2], &[u8]>>,
+) -> Result<(), SaveLoadError> {
+    let beams_bytes: &[u8] = bytemuck::cast_slice(region);
     let table = if let Some(table) = table {
         table
     } else {
@@ -343,80 +867,234 @@ pub fn write_sun_beams_region<'txn>(
     Ok(())
 }
 
-pub fn write_chunks<'txn>(
+pub fn write_biomes_region<'txn>(
     write_txn: &'txn WriteTransaction,
-    universe: &Universe,
-) -> Result<(), Error> {
-    let mut table = write_txn.open_table(TABLE_BLOCKS)?;
-    for (chunk_pos, chunk) in universe.chunks.iter() {
-        write_chunk(write_txn, chunk_pos, &chunk, Some(&mut table))?
-    }
+    region_pos: IVec2,
+    region: &[Biome; CHUNK_AREA],
+    table: Option<&mut Table<'txn, [i32; 2], &[u8]>>,
+) -> Result<(), SaveLoadError> {
+    let biome_bytes = region.map(|biome| biome as u8);
+    let table = if let Some(table) = table {
+        table
+    } else {
+        &mut write_txn.open_table(TABLE_BIOMES)?
+    };
+    table.insert(region_pos.to_array(), &biome_bytes[..])?;
     Ok(())
 }
 
 pub fn write_chunk<'txn>(
     write_txn: &'txn WriteTransaction,
+    palette: &LevelPalette,
     chunk_pos: &IVec3,
     chunk: &Chunk,
     table: Option<&mut Table<'txn, [i32; 3], &[u8]>>,
-) -> Result<(), Error> {
-    let chunk_ref = chunk.get_ref();
-    let block_bytes: &[u8] = bytemuck::cast_slice(chunk_ref.as_ref());
-    let block_compressed = compress_to_vec(block_bytes, 6);
+) -> Result<(), SaveLoadError> {
+    let paletted = PalettedChunk::encode(&chunk.get_ref(), |id| palette.to_level(id));
+    let block_bytes = bincode::serialize(&paletted)?;
+    let block_compressed = compress_to_vec(&block_bytes, 6);
     let table = if let Some(table) = table {
         table
     } else {
         &mut write_txn.open_table(TABLE_BLOCKS)?
     };
     table.insert(&chunk_pos.to_array(), &*block_compressed)?;
+
+    // most chunks don't have block entities, so they are kept in their own table
+    let mut entity_table = write_txn.open_table(TABLE_BLOCK_ENTITIES)?;
+    let entities: Vec<(IVec3, BlockEntity)> = chunk
+        .get_entities()
+        .get_ref()
+        .iter()
+        .map(|(xyz, entity)| (*xyz, entity.clone()))
+        .collect();
+    if entities.is_empty() {
+        entity_table.remove(&chunk_pos.to_array())?;
+    } else {
+        let entity_bytes = bincode::serialize(&entities)?;
+        entity_table.insert(&chunk_pos.to_array(), &*compress_to_vec(&entity_bytes, 6))?;
+    }
     Ok(())
 }
 
+/// Moves the stored blocks of the chunk at `chunk_pos` aside so that it is generated again.
+/// The bytes are kept in `TABLE_QUARANTINED_BLOCKS` to be inspected, the block entities
+/// of the chunk are dropped.
+pub fn quarantine_chunk<'txn>(
+    write_txn: &'txn WriteTransaction,
+    chunk_pos: &IVec3,
+) -> Result<(), SaveLoadError> {
+    let mut table = write_txn.open_table(TABLE_BLOCKS)?;
+    let mut quarantine_table = write_txn.open_table(TABLE_QUARANTINED_BLOCKS)?;
+    if let Some(value) = table.remove(chunk_pos.to_array())? {
+        quarantine_table.insert(chunk_pos.to_array(), value.value())?;
+    }
+    let mut entity_table = write_txn.open_table(TABLE_BLOCK_ENTITIES)?;
+    entity_table.remove(chunk_pos.to_array())?;
+    Ok(())
+}
+
+/// `None` if nothing was ever written to the table.
+pub fn open_read_table<K: Key + 'static, V: Value + 'static>(
+    read_txn: &ReadTransaction,
+    definition: TableDefinition<K, V>,
+) -> Result<Option<ReadOnlyTable<K, V>>, SaveLoadError> {
+    match read_txn.open_table(definition) {
+        Ok(table) => Ok(Some(table)),
+        Err(TableError::TableDoesNotExist(_)) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn read_level_row<T: DeserializeOwned>(
+    read_txn: &ReadTransaction,
+    key: &str,
+) -> Result<Option<T>, SaveLoadError> {
+    let Some(table) = open_read_table(read_txn, TABLE_LEVEL)? else {
+        return Ok(None);
+    };
+    let Some(value) = table.get(key)? else {
+        return Ok(None);
+    };
+    Ok(Some(bincode::deserialize(value.value())?))
+}
+
 pub fn read_player<'txn>(
     read_txn: &'txn ReadTransaction,
     player_name: &str,
-) -> Option<SerdePlayer> {
-    let table = read_txn.open_table(TABLE_PLAYERS).ok()?;
-    let option = table.get(player_name).ok()?;
-    let value = option?;
-    let player = bincode::deserialize(value.value()).expect("failed to deserialize player");
-    Some(player)
-}
-
-pub fn read_chunk<'txn>(read_txn: &'txn ReadTransaction, chunk_pos: &IVec3) -> Option<Chunk> {
-    let table = read_txn.open_table(TABLE_BLOCKS).ok()?;
-    let option = table.get(chunk_pos.to_array()).ok()?;
-    let value = option?;
-    let block_decompressed = decompress_to_vec_with_limit(value.value(), CHUNK_VOLUME * 4)
-        .expect("failed to decompress chunk");
+) -> Result<Option<SerdePlayer>, SaveLoadError> {
+    let Some(table) = open_read_table(read_txn, TABLE_PLAYERS)? else {
+        return Ok(None);
+    };
+    let Some(value) = table.get(player_name)? else {
+        return Ok(None);
+    };
+    Ok(Some(bincode::deserialize(value.value())?))
+}
+
+pub fn read_block_names<'txn>(
+    read_txn: &'txn ReadTransaction,
+) -> Result<Option<Vec<(BlockId, String)>>, SaveLoadError> {
+    read_level_row(read_txn, "block_names")
+}
+
+pub fn read_level_packs<'txn>(
+    read_txn: &'txn ReadTransaction,
+) -> Result<Option<Vec<String>>, SaveLoadError> {
+    read_level_row(read_txn, "packs")
+}
+
+pub fn read_level<'txn>(read_txn: &'txn ReadTransaction) -> Result<Option<Level>, SaveLoadError> {
+    read_level_row(read_txn, "info")
+}
+
+/// Upper bound of a serialized `PalettedChunk`: two bytes per index,
+/// one for the light and a palette as big as the chunk.
+const MAX_PALETTED_CHUNK_BYTES: usize = CHUNK_VOLUME * 5 + 64;
+
+/// Upper bound of the serialized block entities of a chunk, so that a corrupt row
+/// can't make the game allocate without limit.
+const MAX_BLOCK_ENTITIES_BYTES: usize = 16 * 1024 * 1024;
+
+pub fn read_chunk<'txn>(
+    read_txn: &'txn ReadTransaction,
+    palette: &LevelPalette,
+    chunk_pos: &IVec3,
+) -> Result<Option<Chunk>, SaveLoadError> {
+    let Some(table) = open_read_table(read_txn, TABLE_BLOCKS)? else {
+        return Ok(None);
+    };
+    let Some(value) = table.get(chunk_pos.to_array())? else {
+        return Ok(None);
+    };
+    let block_decompressed = decompress_to_vec_with_limit(value.value(), MAX_PALETTED_CHUNK_BYTES)?;
+    let paletted: PalettedChunk = bincode::deserialize(&block_decompressed)?;
     let chunk = Chunk::empty();
-    {
-        let mut write = chunk.get_mut();
-        let bytes: &mut [u8] = bytemuck::cast_slice_mut(&mut (*write));
-        bytes.copy_from_slice(&*block_decompressed);
+    paletted
+        .decode(&mut chunk.get_mut(), |id| palette.to_runtime(id))
+        .ok_or_else(|| {
+            SaveLoadError::Corrupt(format!("the chunk at {} is incomplete", chunk_pos))
+        })?;
+    chunk.compact();
+    Ok(Some(chunk))
+}
+
+/// The block entities of the chunk at `chunk_pos`, they are read apart from its blocks
+/// so that a corrupt row loses only them.
+pub fn read_block_entities<'txn>(
+    read_txn: &'txn ReadTransaction,
+    chunk_pos: &IVec3,
+) -> Result<Vec<(IVec3, BlockEntity)>, SaveLoadError> {
+    let Some(entity_table) = open_read_table(read_txn, TABLE_BLOCK_ENTITIES)? else {
+        return Ok(vec![]);
+    };
+    let Some(value) = entity_table.get(chunk_pos.to_array())? else {
+        return Ok(vec![]);
+    };
+    let entity_bytes = decompress_to_vec_with_limit(value.value(), MAX_BLOCK_ENTITIES_BYTES)?;
+    let entities: Vec<(IVec3, BlockEntity)> = bincode::deserialize(&entity_bytes)?;
+    if let Some((xyz, _)) = entities.iter().find(|(xyz, _)| !Chunk::contains(xyz)) {
+        return Err(SaveLoadError::Corrupt(format!(
+            "the block entity at {} is outside of the chunk at {}",
+            xyz, chunk_pos
+        )));
     }
-    Some(chunk)
+    Ok(entities)
 }
 
 pub fn read_sun_beams<'txn>(
     read_txn: &'txn ReadTransaction,
     region_pos: &IVec2,
-) -> Option<Vec<(IVec2, SunBeam)>> {
-    let table = read_txn.open_table(TABLE_SUN_BEAMS).ok()?;
-    let option = table.get(region_pos.to_array()).ok()?;
-    let value = option?;
+) -> Result<Option<Vec<(IVec2, SunBeam)>>, SaveLoadError> {
+    let Some(table) = open_read_table(read_txn, TABLE_SUN_BEAMS)? else {
+        return Ok(None);
+    };
+    let Some(value) = table.get(region_pos.to_array())? else {
+        return Ok(None);
+    };
+    let beam_bytes = value.value();
+    if beam_bytes.len() != CHUNK_AREA * size_of::<BeamPod>() {
+        return Err(SaveLoadError::Corrupt(format!(
+            "the sun beams region at {} has {} bytes",
+            region_pos,
+            beam_bytes.len()
+        )));
+    }
     let mut beams = vec![];
-    let beams_pod: &[BeamPod] = bytemuck::cast_slice(value.value());
-    for i in 0..CHUNK_AREA as i32 {
-        let (x, z) = (i % CHUNK_SIDE as i32, i / CHUNK_SIDE as i32);
-        let pos = IVec2::new(region_pos.x + x, region_pos.y + z);
+    for (i, pod_bytes) in beam_bytes.chunks_exact(size_of::<BeamPod>()).enumerate() {
+        let (x, z) = (i % CHUNK_SIDE, i / CHUNK_SIDE);
+        let pos = IVec2::new(region_pos.x + x as i32, region_pos.y + z as i32);
+        let beam_pod: BeamPod = bytemuck::pod_read_unaligned(pod_bytes);
         let beam = SunBeam {
-            bottom: beams_pod[i as usize].bottom,
-            top: beams_pod[i as usize].top,
+            bottom: beam_pod.bottom,
+            top: beam_pod.top,
         };
         beams.push((pos, beam));
     }
-    Some(beams)
+    Ok(Some(beams))
+}
+
+pub fn read_biomes<'txn>(
+    read_txn: &'txn ReadTransaction,
+    region_pos: &IVec2,
+) -> Result<Option<[Biome; CHUNK_AREA]>, SaveLoadError> {
+    let Some(table) = open_read_table(read_txn, TABLE_BIOMES)? else {
+        return Ok(None);
+    };
+    let Some(value) = table.get(region_pos.to_array())? else {
+        return Ok(None);
+    };
+    let biome_bytes = value.value();
+    if biome_bytes.len() != CHUNK_AREA {
+        return Err(SaveLoadError::Corrupt(format!(
+            "the biomes region at {} has {} bytes",
+            region_pos,
+            biome_bytes.len()
+        )));
+    }
+    Ok(Some(std::array::from_fn(|i| {
+        Biome::from_u8(biome_bytes[i]).unwrap_or_default()
+    })))
 }
 
 pub fn is_level_ready(
@@ -425,13 +1103,25 @@ pub fn is_level_ready(
     universe: Res<Universe>,
     level: Option<ResMut<Level>>,
     level_ready: Option<ResMut<LevelReady>>,
+    progress: Res<GenerationProgress>,
 ) {
-    if level.is_some()
-        && level_ready.is_none()
-        && get_spawn_chunks().all(|pos| universe.chunks.contains_key(&pos))
-    {
+    if level.is_none() || level_ready.is_some() {
+        return;
+    }
+    if get_spawn_chunks().all(|pos| universe.chunks.contains_key(&pos)) {
+        info!(
+            "level ready, {} chunks loaded and {} pending",
+            progress.loaded, progress.pending
+        );
         commands.insert_resource(LevelReady);
         event.send(LevelReadyEvent);
+    } else if progress.is_changed() {
+        info!(
+            "loading the level: {:.0}% ({}/{} chunks)",
+            progress.fraction() * 100.0,
+            progress.loaded,
+            progress.loaded + progress.pending
+        );
     }
 }
 
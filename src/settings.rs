@@ -1,10 +1,12 @@
-use crate::{NetSettings, NetworkMode, DEFAULT_NETWORK_ADDRESS};
+use crate::{generator::GeneratorConfig, NetSettings, NetworkMode, DEFAULT_NETWORK_ADDRESS};
 use bevy::prelude::*;
 use clap::Parser;
 use mcrs_render::settings::{RenderMode, RenderSettings, DEFAULT_VIEW_DISTANCE};
+use mcrs_universe::PACKS_PATH;
 
 pub const DEFAULT_TICKS_PER_SECOND: u32 = 64;
 pub const DEFAULT_LOAD_DISTANCE: u32 = 192;
+pub const DEFAULT_AUTOSAVE_INTERVAL: u32 = 60;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -29,6 +31,23 @@ pub struct Args {
 
     #[arg(short, long)]
     pub player_name: Option<String>,
+
+    /// Generator of the new levels, as `name` or `name:params`,
+    /// for example `superflat:Stone,3*Dirt,Grass`
+    #[arg(short, long)]
+    pub generator: Option<String>,
+
+    /// Seed of the new levels, random if missing
+    #[arg(short, long)]
+    pub seed: Option<u32>,
+
+    /// Seconds between two autosaves, 0 disables them
+    #[arg(long)]
+    pub autosave_interval: Option<u32>,
+
+    /// Directory of the content packs
+    #[arg(long)]
+    pub packs_path: Option<String>,
 }
 
 #[derive(Resource, Debug, Clone, PartialEq, Eq)]
@@ -41,6 +60,10 @@ pub struct McrsSettings {
     pub render_mode: RenderMode,
     pub open_level_name: String,
     pub player_name: Option<String>,
+    pub generator: GeneratorConfig,
+    pub seed: Option<u32>,
+    pub autosave_interval_seconds: u32,
+    pub packs_path: String,
 }
 
 impl Default for McrsSettings {
@@ -54,6 +77,10 @@ impl Default for McrsSettings {
             render_mode: RenderMode::default(),
             open_level_name: format!("world"),
             player_name: None,
+            generator: GeneratorConfig::default(),
+            seed: None,
+            autosave_interval_seconds: DEFAULT_AUTOSAVE_INTERVAL,
+            packs_path: PACKS_PATH.to_string(),
         }
     }
 }
@@ -69,6 +96,12 @@ impl From<Args> for McrsSettings {
             network_mode: args.network_mode.into(),
             render_mode: args.render_mode.into(),
             player_name: args.player_name,
+            generator: args
+                .generator
+                .map_or(GeneratorConfig::default(), |g| g.as_str().into()),
+            seed: args.seed,
+            autosave_interval_seconds: args.autosave_interval.unwrap_or(DEFAULT_AUTOSAVE_INTERVAL),
+            packs_path: args.packs_path.unwrap_or(PACKS_PATH.to_string()),
             ..Default::default()
         }
     }
@@ -1,26 +1,39 @@
 use crate::{
-    chemistry::lighting::*, read_chunk, read_sun_beams, settings::McrsSettings, write_chunk,
-    write_sun_beams_region, Db, Level, LocalPlayer, TABLE_BLOCKS, TABLE_SUN_BEAMS,
+    biome::{Biome, Biomes},
+    chemistry::lighting::*,
+    generator::{
+        feature_sources, place_features, GeneratorConfig, GeneratorRegistry, WorldGenerator,
+        DEFAULT_SKY_HEIGHT,
+    },
+    level_writer::WriteBatch,
+    quarantine_chunk, read_biomes, read_block_entities, read_chunk, read_sun_beams,
+    report_save_load_error,
+    settings::McrsSettings,
+    Db, Level, LocalPlayer, SaveLoadErrorEvent, SaveState,
 };
 use bevy::{
     prelude::*,
+    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task},
     utils::{HashMap, HashSet},
 };
 use mcrs_physics::intersect::get_chunks_in_sphere;
 use mcrs_universe::{
-    block::{Block, BlockFlag, LightType},
+    block::{Block, BlockFlag, BlockId, LightType},
     chunk::Chunk,
+    journal::ChangeKind,
     universe::Universe,
     Blueprints, CHUNK_AREA, CHUNK_SIDE, CHUNK_VOLUME, MAX_LIGHT,
 };
-use noise::{HybridMulti, MultiFractal, NoiseFn, Seedable};
 use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, sync::Arc};
 
 // Todo: refactor this big file into lighting, generation and modification modules
 
-const BLOCK_GENERATION_LOW_MULTIPLIER: usize = 4;
-const BLOCK_GENERATION_LOW_THRESHOLD: usize = 1000;
-const MAX_BLOCK_GENERATION_PER_FRAME: usize = 20000;
+const MAX_GENERATION_TASKS: usize = 16;
+/// Cosine of the half angle of the cone in front of the players where chunks are favoured.
+const VIEW_CONE_COS: f32 = 0.6;
+const VIEW_PRIORITY_WEIGHT: f32 = 0.5;
+const SURFACE_PRIORITY_WEIGHT: f32 = 0.75;
 const MAX_SUN_BEAM_EXTENSION: i32 = 100000;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -51,6 +64,8 @@ pub struct UniverseChanges {
     pub queue: Vec<UniverseChange>,
 }
 
+/// The blocks of a column lit directly by the sun.
+/// The sun starts at `top`, the top of the world for the column, which is saved with the beam.
 #[derive(Default, Clone, Debug)]
 pub struct SunBeam {
     pub bottom: i32,
@@ -65,8 +80,8 @@ impl SunBeam {
         }
     }
 
-    pub fn new_top(xz: &IVec2) -> Self {
-        let sun_height = get_sun_heightfield(*xz);
+    /// The beam of a column that has no blocks at or above `sun_height`.
+    pub fn new_top(sun_height: i32) -> Self {
         Self::new(sun_height, sun_height)
     }
 
@@ -82,17 +97,18 @@ impl SunBeam {
         self.top = self.top.max(new_beam.top);
     }
 
-    /// If `at` is inside the beam, return the two parts of the beam:
+    /// If `at` is inside the beam or above it, return the two parts of the beam:
     /// ```(self.start..=at, (at+1)..=self.end)```
+    /// A block at or above the top raises the top of the world above it.
     pub fn cut(&mut self, at: i32) -> Option<(SunBeam, SunBeam)> {
-        if (self.bottom..=self.top).contains(&at) {
-            let lower = SunBeam::new(self.bottom, at);
-            let higher = SunBeam::new(at + 1, self.top);
-            self.bottom = (at + 1).min(self.top);
-            Some((lower, higher))
-        } else {
-            None
+        if at < self.bottom {
+            return None;
         }
+        self.top = self.top.max(at + 1);
+        let lower = SunBeam::new(self.bottom, at);
+        let higher = SunBeam::new(at + 1, self.top);
+        self.bottom = at + 1;
+        Some((lower, higher))
     }
 
     pub fn contains(&self, at: &i32) -> bool {
@@ -106,8 +122,11 @@ pub struct SunBeams {
 }
 
 impl SunBeams {
+    /// Columns that were never generated start at `DEFAULT_SKY_HEIGHT`.
     pub fn get_at_mut<'a>(&'a mut self, xz: &'a IVec2) -> &'a mut SunBeam {
-        self.beams.entry(*xz).or_insert(SunBeam::new_top(xz))
+        self.beams
+            .entry(*xz)
+            .or_insert(SunBeam::new_top(DEFAULT_SKY_HEIGHT))
     }
 
     pub fn extend_beam(&mut self, xz: &IVec2, new_beam: SunBeam) {
@@ -122,7 +141,7 @@ impl SunBeams {
 }
 
 pub fn apply_terrain_changes(
-    mut universe: ResMut<Universe>,
+    universe: Res<Universe>,
     mut changes: ResMut<UniverseChanges>,
     mut light_sources: ResMut<LightSources>,
     mut sun_beams: ResMut<SunBeams>,
@@ -134,9 +153,13 @@ pub fn apply_terrain_changes(
                 debug!(target: "terrain_editing", "removed block at {}", pos);
 
                 if let Some(block) = universe.read_chunk_block(&pos) {
-                    if bp.blocks.get(&block.id).is_light_source() {
+                    let is_light_source = bp
+                        .blocks
+                        .get_checked(&block.id)
+                        .is_some_and(|block_bp| block_bp.is_light_source());
+                    if is_light_source {
                         let mut new_sources =
-                            propagate_darkness(&mut universe, &bp, vec![*pos], LightType::Torch);
+                            propagate_darkness(&universe, &bp, vec![*pos], LightType::Torch);
                         light_sources
                             .leaked_sources
                             .entry(LightType::Torch)
@@ -145,7 +168,7 @@ pub fn apply_terrain_changes(
                     }
                 }
 
-                universe.set_chunk_block(&pos, Block::new(bp.blocks.get_named("Air")));
+                universe.set_chunk_block(&pos, Block::new(bp.air()));
 
                 for dir in DIRS.iter() {
                     let sample = pos + *dir;
@@ -186,6 +209,10 @@ pub fn apply_terrain_changes(
                                     brightness: MAX_LIGHT,
                                 });
                             }
+                        } else {
+                            // The generation of the chunks below extends the rest of the beam
+                            beam.bottom = h + 1;
+                            break;
                         }
                     }
                     light_sources
@@ -197,9 +224,23 @@ pub fn apply_terrain_changes(
             }
             UniverseChange::Add { pos, block } => {
                 debug!(target: "terrain_editing", "placed block at {}", pos);
-                universe.set_chunk_block(&pos, *block);
+                let block_bp = match bp.blocks.get(&block.id) {
+                    Ok(block_bp) => block_bp,
+                    Err(err) => {
+                        warn!("not placing the block at {}: {}", pos, err);
+                        continue;
+                    }
+                };
+                let mut block = *block;
+                if !block_bp.is_valid_state(block.state) {
+                    warn!(
+                        "invalid state {} for {} at {}, using the default",
+                        *block.state, block_bp.name, pos
+                    );
+                    block.state = default();
+                }
+                universe.set_chunk_block(&pos, block);
 
-                let block_bp = bp.blocks.get(&block.id);
                 light_sources
                     .leaked_sources
                     .entry(LightType::Torch)
@@ -215,7 +256,7 @@ pub fn apply_terrain_changes(
                         .map(|y| IVec3::new(pos.x, y, pos.z))
                         .collect();
                     let mut new_sources =
-                        propagate_darkness(&mut universe, &bp, sources, LightType::Sun);
+                        propagate_darkness(&universe, &bp, sources, LightType::Sun);
                     light_sources
                         .leaked_sources
                         .entry(LightType::Sun)
@@ -251,10 +292,7 @@ pub fn group_leaked_light_into_chunks(
     }
 }
 
-pub fn apply_lighting_sources(
-    mut universe: ResMut<Universe>,
-    mut light_sources: ResMut<LightSources>,
-) {
+pub fn apply_lighting_sources(universe: Res<Universe>, mut light_sources: ResMut<LightSources>) {
     for (light_type, sources) in light_sources.leaked_sources.iter_mut() {
         debug!(target: "lighting_leaked", "leaked {}: {}",
             light_type,
@@ -283,10 +321,10 @@ pub fn apply_lighting_sources(
     let mut processed_chunks = vec![];
     for (light_type, chunked_sources_list) in light_sources.chunked_sources.iter() {
         for (chunk_pos, chunked_sources) in chunked_sources_list {
-            if let Some(chunk) = universe.chunks.get_mut(chunk_pos) {
+            let mut lit = vec![];
+            if let Some(chunk) = universe.chunks.get(chunk_pos) {
                 processed_chunks.push(*chunk_pos);
 
-                chunk.version.update();
                 let mut chunk_mut = chunk.get_mut();
                 let mut sources = vec![];
                 for source in chunked_sources.sources.iter() {
@@ -298,9 +336,10 @@ pub fn apply_lighting_sources(
                         sources.push(source.pos);
                     }
                 }
+                lit.extend(sources.iter());
                 if !sources.is_empty() {
                     let mut leaked_from_chunk =
-                        propagate_light_chunk(&mut chunk_mut, sources, *light_type);
+                        propagate_light_chunk(&mut chunk_mut, sources, *light_type, &mut lit);
                     for source in leaked_from_chunk.iter_mut() {
                         source.pos += chunk_pos;
                     }
@@ -309,7 +348,22 @@ pub fn apply_lighting_sources(
                         .or_default()
                         .append(&mut leaked_from_chunk);
                 }
+                drop(chunk_mut);
+                // bumped once with the lit blocks recorded below
+                if !lit.is_empty() {
+                    chunk.update_version();
+                }
             }
+            universe.journal.record_all(
+                *chunk_pos,
+                lit.into_iter().map(|xyz| (xyz, ChangeKind::Light)),
+            );
+        }
+    }
+    // the propagation makes the chunks dense, they shrink again once every light type is done
+    for chunk_pos in processed_chunks.iter() {
+        if let Some(chunk) = universe.chunks.get(chunk_pos) {
+            chunk.compact();
         }
     }
     for (_, chunked_sources_list) in light_sources.chunked_sources.iter_mut() {
@@ -322,75 +376,150 @@ pub fn apply_lighting_sources(
 
 #[derive(Resource, Default)]
 pub struct ChunkGenerationRequest {
-    pub requested: HashMap<IVec3, ChunkGenerationState>,
+    requested: HashMap<IVec3, ChunkGenerationState>,
+    /// The requested chunks of each pass by priority, then by position.
+    /// Kept in step with the requests, their pass and priority only change through `update`.
+    by_pass: HashMap<GenerationPass, BTreeSet<(i32, [i32; 3])>>,
 }
 
 impl ChunkGenerationRequest {
+    /// Requests the chunk, or replaces the priority of the request.
     fn insert_priority(&mut self, chunk_pos: IVec3, priority: i32) {
-        let req = self
-            .requested
-            .entry(chunk_pos)
-            .or_insert(ChunkGenerationState {
-                pos: chunk_pos,
-                priority,
-                ..Default::default()
-            });
-        req.priority = req.priority.min(priority);
+        self.insert_new(chunk_pos, priority, false);
+        self.update(chunk_pos, |req| {
+            req.priority = priority;
+            req.blocks_only = false;
+        });
+    }
+
+    fn insert_blocks_only(&mut self, chunk_pos: IVec3, priority: i32) {
+        self.insert_new(chunk_pos, priority, true);
+        self.update(chunk_pos, |req| req.priority = req.priority.min(priority));
+    }
+
+    /// Adds a request in the first pass if the chunk isn't requested yet.
+    fn insert_new(&mut self, chunk_pos: IVec3, priority: i32, blocks_only: bool) {
+        if self.requested.contains_key(&chunk_pos) {
+            return;
+        }
+        let req = ChunkGenerationState {
+            pos: chunk_pos,
+            priority,
+            blocks_only,
+            ..Default::default()
+        };
+        self.by_pass
+            .entry(req.pass)
+            .or_default()
+            .insert((priority, chunk_pos.to_array()));
+        self.requested.insert(chunk_pos, req);
+    }
+
+    /// Changes the request of the chunk and moves it in the index of its pass.
+    fn update(&mut self, chunk_pos: IVec3, f: impl FnOnce(&mut ChunkGenerationState)) {
+        let Some(req) = self.requested.get_mut(&chunk_pos) else {
+            return;
+        };
+        let key = (req.priority, chunk_pos.to_array());
+        self.by_pass.entry(req.pass).or_default().remove(&key);
+        f(req);
+        self.by_pass
+            .entry(req.pass)
+            .or_default()
+            .insert((req.priority, chunk_pos.to_array()));
+    }
+
+    fn set_pass(&mut self, chunk_pos: IVec3, pass: GenerationPass) {
+        self.update(chunk_pos, |req| req.pass = pass);
+    }
+
+    /// Dropping the request cancels its task.
+    fn remove(&mut self, chunk_pos: &IVec3) -> Option<ChunkGenerationState> {
+        let req = self.requested.remove(chunk_pos)?;
+        if let Some(index) = self.by_pass.get_mut(&req.pass) {
+            index.remove(&(req.priority, chunk_pos.to_array()));
+        }
+        Some(req)
+    }
+
+    pub fn clear(&mut self) {
+        self.requested.clear();
+        self.by_pass.clear();
     }
 
-    fn get_for_pass_mut<'a>(
-        &'a mut self,
-        pass: &'a GenerationPass,
-    ) -> impl Iterator<Item = (&'a IVec3, &'a mut ChunkGenerationState)> {
-        self.requested
-            .iter_mut()
-            .filter(|(_, state)| state.pass == *pass)
+    pub fn iter(&self) -> impl Iterator<Item = (&IVec3, &ChunkGenerationState)> {
+        self.requested.iter()
+    }
+
+    /// The chunks in `pass`, the most urgent first, in the same order for the same priorities.
+    fn in_pass(&self, pass: GenerationPass) -> Vec<IVec3> {
+        self.by_pass
+            .get(&pass)
+            .into_iter()
+            .flatten()
+            .map(|(_, chunk_pos)| IVec3::from_array(*chunk_pos))
+            .collect()
     }
 }
 
-#[derive(Default, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum GenerationPass {
     #[default]
-    /// The chunk is requesting for blocks to be generated
+    /// The chunk is requesting for blocks to be generated and carved in a task
     Blocks,
     /// The chunk is waiting on the chunk above to be done before reverting to `Lighting`
     WaitingForSunbeams,
     /// The chunk is requesting for sunbeams to be propagated through it
     Sunbeams,
-    /// The chunk is requesting biome to be added
+    /// The surface of the biomes is laid on the chunk in a task
     Biome,
-    /// The chunk is requesting lighting to be recalculated
+    /// The chunk is waiting on the blocks of its neighbours to gather their features
+    Decoration,
+    /// The features are placed in the chunk and it's lit for the first time in a task
     Lighting,
     /// The chunk is ready to be added to the universe
     Done,
 }
 
 pub struct ChunkGenerationState {
+    /// Taken by the task of the pass while it runs.
     pub chunk: Option<Chunk>,
+    /// Runs the pass of the chunk, dropping it cancels the generation.
+    pub task: Option<Task<GeneratedPart>>,
     pub pos: IVec3,
-    pub current_block: usize,
-    pub pass: GenerationPass,
+    /// Changed through the request, which indexes the chunks by pass and priority.
+    pass: GenerationPass,
     pub blocks_lowest: [i32; CHUNK_AREA],
     pub blocks_beams: [i32; CHUNK_AREA],
-    pub priority: i32,
+    priority: i32,
     pub depends_on: Vec<IVec3>,
+    /// Only the blocks are generated, because the chunk is only needed by the decoration of its
+    /// neighbours. It's generated fully once it's requested by itself.
+    pub blocks_only: bool,
 }
 
 impl Default for ChunkGenerationState {
     fn default() -> Self {
         Self {
             chunk: None,
+            task: None,
             pos: IVec3::default(),
-            current_block: 0,
             pass: GenerationPass::default(),
             blocks_lowest: [0; CHUNK_AREA],
             blocks_beams: [CHUNK_SIDE as i32 - 1; CHUNK_AREA],
             priority: 0,
             depends_on: vec![],
+            blocks_only: false,
         }
     }
 }
 
+impl ChunkGenerationState {
+    pub fn pass(&self) -> GenerationPass {
+        self.pass
+    }
+}
+
 pub fn get_spawn_chunks() -> impl Iterator<Item = IVec3> {
     (-1..=1)
         .map(|z| {
@@ -401,49 +530,105 @@ pub fn get_spawn_chunks() -> impl Iterator<Item = IVec3> {
         .flatten()
 }
 
-pub fn get_sun_heightfield(_xz: IVec2) -> i32 {
-    256
-}
-
+/// The chunks needed around the players and their priority.
+/// Each player ranks the chunks around it on its own and a chunk keeps the best rank it gets,
+/// so the players near each other don't slow down the chunks of the ones far away.
 pub fn requested_chunks<'a>(
     players: impl Iterator<Item = (&'a Transform, &'a LocalPlayer)>,
     settings: &'a McrsSettings,
-) -> Vec<(IVec3, i32)> {
-    let players_pos = players.map(|(tr, _)| tr.translation).collect::<Vec<Vec3>>();
-
-    let mut requested = vec![];
+    sun_beams: &'a SunBeams,
+) -> HashMap<IVec3, i32> {
+    let mut requested = HashMap::new();
 
     // Check the spawn chunks
     for chunk_pos in get_spawn_chunks() {
-        requested.push((chunk_pos.clone(), 0));
+        requested.insert(chunk_pos, 0);
     }
 
     // Check near every player
-    for player_pos in players_pos.iter() {
-        let chunks = get_chunks_in_sphere(*player_pos, settings.load_distance_blocks as f32);
-        for chunk_pos in chunks.iter() {
-            requested.push((
-                chunk_pos.clone(),
-                (player_pos - chunk_pos.as_vec3()).length() as i32,
-            ));
+    for (player_tr, _) in players {
+        let chunks =
+            get_chunks_in_sphere(player_tr.translation, settings.load_distance_blocks as f32);
+        for chunk_pos in chunks {
+            let center_xz = chunk_pos.xz() + IVec2::splat(CHUNK_SIDE as i32 / 2);
+            let surface = sun_beams
+                .beams
+                .get(&center_xz)
+                .filter(|beam| beam.bottom < beam.top)
+                .map(|beam| beam.bottom);
+            let priority = chunk_priority(player_tr, chunk_pos, surface);
+            requested
+                .entry(chunk_pos)
+                .and_modify(|best: &mut i32| *best = (*best).min(priority))
+                .or_insert(priority);
         }
     }
 
     requested
 }
 
+/// Lower is more urgent. It's the distance from the player, shortened for the chunks in front
+/// of the player and for the ones with the surface in them. Without a known `surface` the
+/// player is assumed to stand on it.
+fn chunk_priority(player_tr: &Transform, chunk_pos: IVec3, surface: Option<i32>) -> i32 {
+    let center = chunk_pos.as_vec3() + Vec3::splat(CHUNK_SIDE as f32 * 0.5);
+    let offset = center - player_tr.translation;
+    let mut weight = 1.0;
+    // The view direction only has the yaw of the body, the pitch of the camera is ignored
+    let is_in_view = offset.normalize_or_zero().dot(*player_tr.forward()) > VIEW_CONE_COS;
+    if is_in_view {
+        weight *= VIEW_PRIORITY_WEIGHT;
+    }
+    let surface = surface.unwrap_or(player_tr.translation.y.floor() as i32);
+    if (chunk_pos.y..chunk_pos.y + CHUNK_SIDE as i32).contains(&surface) {
+        weight *= SURFACE_PRIORITY_WEIGHT;
+    }
+    (offset.length() * weight) as i32
+}
+
+/// How many of the chunks that are needed around the players are loaded.
+#[derive(Resource, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GenerationProgress {
+    pub loaded: usize,
+    pub pending: usize,
+}
+
+impl GenerationProgress {
+    /// From 0 to 1, 1 when nothing is pending.
+    pub fn fraction(&self) -> f32 {
+        let total = self.loaded + self.pending;
+        if total == 0 {
+            1.0
+        } else {
+            self.loaded as f32 / total as f32
+        }
+    }
+}
+
+/// The generator of the loaded level, with the config and seed it was built from.
+pub struct BuiltGenerator {
+    config: GeneratorConfig,
+    seed: u32,
+    generator: Arc<dyn WorldGenerator>,
+}
+
 // Todo: split this function
 pub fn chunk_generation(
-    mut universe: ResMut<Universe>,
+    universe: Res<Universe>,
     players: Query<(&Transform, &LocalPlayer)>,
     bp: Res<Blueprints>,
     mut light_sources: ResMut<LightSources>,
     mut request: ResMut<ChunkGenerationRequest>,
-    mut generator: Local<Option<GeneratorCrazyHill>>,
+    mut generator: Local<Option<BuiltGenerator>>,
+    registry: Res<GeneratorRegistry>,
     mut sun_beams: ResMut<SunBeams>,
+    mut biomes: ResMut<Biomes>,
+    mut progress: ResMut<GenerationProgress>,
     settings: Res<McrsSettings>,
     level: Option<Res<Level>>,
     db: Option<Res<Db>>,
+    mut save_state: ResMut<SaveState>,
+    mut errors: EventWriter<SaveLoadErrorEvent>,
 ) {
     let Some(level) = level else {
         return;
@@ -452,160 +637,313 @@ pub fn chunk_generation(
         return;
     };
 
-    let base_chunks = requested_chunks(players.iter(), &settings);
+    let base_chunks = requested_chunks(players.iter(), &settings, &sun_beams);
+    let mut pending = 0;
     for (chunk_pos, priority) in base_chunks.iter() {
         if let None = universe.chunks.get(chunk_pos) {
             request.insert_priority(*chunk_pos, *priority);
+            pending += 1;
         }
     }
+    progress.set_if_neq(GenerationProgress {
+        loaded: base_chunks.len() - pending,
+        pending,
+    });
 
-    let base_set: HashSet<&IVec3> = base_chunks.iter().map(|(pos, _)| pos).collect();
-    let depended_on_set: HashSet<&IVec3> = request
+    let base_set: HashSet<&IVec3> = base_chunks.keys().collect();
+    let depended_on_set: HashSet<IVec3> = request
         .requested
         .iter()
-        .map(|(_, part)| part.depends_on.iter())
+        .map(|(_, part)| part.depends_on.iter().copied())
         .flatten()
         .collect();
 
+    // Chunks that are no longer needed stop being generated, their tasks are cancelled
+    let cancelled: Vec<IVec3> = request
+        .requested
+        .keys()
+        .filter(|chunk_pos| !base_set.contains(chunk_pos) && !depended_on_set.contains(chunk_pos))
+        .copied()
+        .collect();
+    for chunk_pos in cancelled {
+        info!(
+            target: "terrain_generation",
+            "cancelled the generation of the chunk at {}", chunk_pos
+        );
+        request.remove(&chunk_pos);
+    }
+
     // Chunks out of load distance and no longer needed are unloaded
-    db.write(|tx| {
-        let mut sun_table = tx.open_table(TABLE_SUN_BEAMS)?;
-        let mut block_table = tx.open_table(TABLE_BLOCKS)?;
-        let mut unload_chunks: Vec<IVec3> = vec![];
-        for (chunk_pos, chunk) in universe.chunks.iter() {
-            if !base_set.contains(&chunk_pos)
+    let unload_chunks: Vec<(IVec3, Arc<Chunk>)> = universe
+        .chunks
+        .iter()
+        .filter(|(chunk_pos, _)| {
+            !base_set.contains(chunk_pos)
                 && !request.requested.contains_key(chunk_pos)
                 && !depended_on_set.contains(chunk_pos)
-            {
-                info!("unloaded chunk at {}", chunk_pos);
-                write_chunk(tx, chunk_pos, chunk, Some(&mut block_table))?;
-                write_sun_beams_region(tx, chunk_pos.xz(), &sun_beams, Some(&mut sun_table))?;
-                unload_chunks.push(*chunk_pos);
+        })
+        .collect();
+    if !unload_chunks.is_empty() {
+        // only the chunks that changed since they were saved are written
+        let dirty_chunks: Vec<(IVec3, Arc<Chunk>)> = unload_chunks
+            .iter()
+            .filter(|(chunk_pos, chunk)| save_state.is_dirty(chunk_pos, chunk))
+            .cloned()
+            .collect();
+        let sent = if dirty_chunks.is_empty() {
+            Ok(())
+        } else {
+            let batch =
+                WriteBatch::with_chunks(db.palette.clone(), dirty_chunks, &sun_beams, &biomes);
+            save_state.send(&db, batch)
+        };
+        // the chunks stay loaded if they couldn't be sent to the writer
+        match sent {
+            Ok(()) => {
+                for (chunk_pos, _) in unload_chunks {
+                    info!("unloaded chunk at {}", chunk_pos);
+                    universe.chunks.remove(&chunk_pos);
+                    save_state.saved_versions.remove(&chunk_pos);
+                }
+            }
+            Err(err) => {
+                let message = format!("Failed to save the unloaded chunks: {}", err);
+                report_save_load_error(&mut errors, message);
             }
         }
-        for chunk_pos in unload_chunks {
-            universe.chunks.remove(&chunk_pos);
-        }
-        Ok(())
-    })
-    .expect("db write failed");
-
-    drop(depended_on_set);
+    }
 
     if !request.requested.is_empty() {
         info!("there are {} requested chunks", request.requested.len());
     }
 
-    // Initialize the block generator
-    if generator.is_none() {
-        *generator = Some(GeneratorCrazyHill::new(level.seed));
+    // Initialize the generator of the level, again if the blueprints were reloaded. The level
+    // changes on every tick with its world time, so only its generator and seed are compared.
+    let is_stale = match generator.as_ref() {
+        Some(built) => built.config != level.generator || built.seed != level.seed,
+        None => true,
+    };
+    if is_stale || bp.is_changed() {
+        *generator = match registry.build(&level.generator, level.seed, &bp) {
+            Ok(built) => Some(BuiltGenerator {
+                config: level.generator.clone(),
+                seed: level.seed,
+                generator: built.into(),
+            }),
+            Err(err) => {
+                error!(
+                    "the terrain can't be generated with {}: {}",
+                    level.generator, err
+                );
+                None
+            }
+        };
     }
-    let Some(generator) = generator.as_ref() else {
+    let Some(BuiltGenerator { generator, .. }) = generator.as_ref() else {
         return;
     };
 
-    // This is a rough estimate for when the world around the player is empty
-    // and is used to speed up the generation of the initial chunks
-    // Todo: use a better estimator after having implemented chunk unloading
-    //       or use a better system
-    let is_low = universe.chunks.len() < BLOCK_GENERATION_LOW_THRESHOLD;
-    let max_block_generation = if is_low {
-        MAX_BLOCK_GENERATION_PER_FRAME * BLOCK_GENERATION_LOW_MULTIPLIER
-    } else {
-        MAX_BLOCK_GENERATION_PER_FRAME
-    };
-
     // Try to load chunks before generating them
     // Todo: limit the number of loaded chunk per frame
     // Maybe not needed to limit? It's very fast.
     let mut loaded_chunks = vec![];
+    let mut corrupt_chunks = vec![];
+    let mut unreadable_chunks = vec![];
     for (chunk_pos, _) in request.requested.iter() {
         if let None = universe.chunks.get(chunk_pos) {
-            if let Some(chunk) = db.get(|tx| read_chunk(tx, chunk_pos)) {
-                universe.chunks.insert(*chunk_pos, chunk);
+            // the chunk was unloaded but the writer didn't write it yet
+            if let Some((_, chunk)) = save_state.writing.get(chunk_pos) {
+                universe.chunks.insert(*chunk_pos, chunk.clone());
                 loaded_chunks.push(*chunk_pos);
-                info!("loaded chunk at {}", chunk_pos);
+                info!("loaded chunk at {} before it was written", chunk_pos);
+                continue;
             }
+            match db.read(|tx| read_chunk(tx, &db.palette, chunk_pos)) {
+                Ok(Some(chunk)) => {
+                    match db.read(|tx| read_block_entities(tx, chunk_pos)) {
+                        Ok(entities) => {
+                            chunk.get_entities().get_mut().extend(entities);
+                            save_state
+                                .saved_versions
+                                .insert(*chunk_pos, chunk.version());
+                        }
+                        // the blocks are kept, the chunk is left unsaved so that the next
+                        // save overwrites the corrupt row
+                        Err(err) if err.is_corrupt() => {
+                            let message = format!(
+                                "The block entities of the chunk at {} are corrupt and were dropped: {}",
+                                chunk_pos, err
+                            );
+                            report_save_load_error(&mut errors, message);
+                        }
+                        // the chunk isn't loaded, saving it would lose the stored entities
+                        Err(err) => {
+                            let message =
+                                format!("Failed to load the chunk at {}: {}", chunk_pos, err);
+                            report_save_load_error(&mut errors, message);
+                            unreadable_chunks.push(*chunk_pos);
+                            continue;
+                        }
+                    }
+                    universe.chunks.insert(*chunk_pos, chunk);
+                    loaded_chunks.push(*chunk_pos);
+                    info!("loaded chunk at {}", chunk_pos);
+                }
+                Ok(None) => {}
+                // the chunk is generated again, its old bytes are kept aside
+                Err(err) if err.is_corrupt() => {
+                    let message = format!(
+                        "The chunk at {} is corrupt and will be generated again: {}",
+                        chunk_pos, err
+                    );
+                    report_save_load_error(&mut errors, message);
+                    corrupt_chunks.push(*chunk_pos);
+                }
+                // the chunk isn't generated, it would overwrite the stored one
+                Err(err) => {
+                    let message = format!("Failed to load the chunk at {}: {}", chunk_pos, err);
+                    report_save_load_error(&mut errors, message);
+                    unreadable_chunks.push(*chunk_pos);
+                }
+            }
+        }
+    }
+    if !corrupt_chunks.is_empty() {
+        let quarantined = db.write(|tx| {
+            for chunk_pos in corrupt_chunks.iter() {
+                quarantine_chunk(tx, chunk_pos)?;
+            }
+            Ok(())
+        });
+        if let Err(err) = quarantined {
+            let message = format!("Failed to quarantine the corrupt chunks: {}", err);
+            report_save_load_error(&mut errors, message);
+            unreadable_chunks.extend(corrupt_chunks);
         }
     }
+    for chunk_pos in unreadable_chunks {
+        request.remove(&chunk_pos);
+    }
     for loaded_chunk in loaded_chunks {
         info!("loading sun beams region at {}", loaded_chunk);
-        if let Some(beams) = db.get(|tx| read_sun_beams(tx, &loaded_chunk.xz())) {
-            for (pos, beam) in beams {
-                sun_beams.beams.entry(pos).or_insert(beam);
+        let region_pos = loaded_chunk.xz();
+        match db.read(|tx| read_sun_beams(tx, &region_pos)) {
+            Ok(Some(beams)) => {
+                for (pos, beam) in beams {
+                    sun_beams.beams.entry(pos).or_insert(beam);
+                }
+            }
+            Ok(None) => {}
+            Err(err) => {
+                let message = format!(
+                    "Failed to load the sun beams region at {}: {}",
+                    region_pos, err
+                );
+                report_save_load_error(&mut errors, message);
+            }
+        }
+        match db.read(|tx| read_biomes(tx, &region_pos)) {
+            Ok(Some(region)) => {
+                biomes.regions.entry(region_pos).or_insert(region);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                let message = format!(
+                    "Failed to load the biomes region at {}: {}",
+                    region_pos, err
+                );
+                report_save_load_error(&mut errors, message);
             }
         }
-        request.requested.remove(&loaded_chunk);
+        request.remove(&loaded_chunk);
     }
 
-    let mut processed_blocks = 0;
-
-    for _ in 0..10 {
-        let Some((chunk_pos, part)) = request.get_for_pass_mut(&GenerationPass::Blocks).next()
-        else {
-            break;
+    // The passes that only need the chunk run in tasks, the chunk waits in its pass meanwhile
+    let mut running_tasks = 0;
+    let mut finished_tasks = vec![];
+    for (chunk_pos, part) in request.requested.iter_mut() {
+        let Some(task) = &mut part.task else {
+            continue;
         };
-
-        if part.chunk.is_none() {
-            part.chunk = Some(Chunk::empty());
+        let Some(generated) = block_on(future::poll_once(task)) else {
+            running_tasks += 1;
+            continue;
+        };
+        part.task = None;
+        part.chunk = Some(generated.chunk);
+        for (light_type, mut leaked) in generated.leaked {
+            light_sources
+                .leaked_sources
+                .entry(light_type)
+                .or_default()
+                .append(&mut leaked);
         }
-
-        info!("generating chunk at {}", chunk_pos);
-
-        let mut generated_blocks_chunk = 0;
-
-        // Generate up to a chunk
-        if let Some(chunk) = &part.chunk {
-            let mut chunk_mut = chunk.get_mut();
-            let bound =
-                (part.current_block + (max_block_generation - processed_blocks)).min(CHUNK_VOLUME);
-            for i in part.current_block..bound {
-                chunk_mut[i] = generator.gen_block(part.pos + Chunk::idx2xyz(i), &bp);
+        let next_pass = match part.pass {
+            GenerationPass::Blocks => {
+                info!(
+                    target: "terrain_generation",
+                    "generated the blocks of the chunk at {}", chunk_pos
+                );
+                GenerationPass::Sunbeams
             }
-            let count = bound - part.current_block;
-            generated_blocks_chunk = count;
-            processed_blocks += count;
-            info!(
-                "generated {} blocks for the chunk at {}, {} left",
-                count, part.pos, part.current_block
-            );
-        }
-        if generated_blocks_chunk > 0 {
-            part.current_block += generated_blocks_chunk;
-        }
-
-        if part.current_block == CHUNK_VOLUME {
-            part.pass = GenerationPass::Sunbeams;
-        }
+            GenerationPass::Biome => GenerationPass::Decoration,
+            // the lighting task is the last one
+            _ => GenerationPass::Done,
+        };
+        finished_tasks.push((*chunk_pos, next_pass));
+    }
+    for (chunk_pos, next_pass) in finished_tasks {
+        request.set_pass(chunk_pos, next_pass);
+    }
 
-        if processed_blocks >= max_block_generation {
+    let task_pool = AsyncComputeTaskPool::get();
+    let air = Block::new(bp.air());
+    for chunk_pos in request.in_pass(GenerationPass::Blocks) {
+        if running_tasks >= MAX_GENERATION_TASKS {
             break;
         }
+        let Some(part) = request.requested.get_mut(&chunk_pos) else {
+            continue;
+        };
+        if part.task.is_some() {
+            continue;
+        }
+        info!("generating chunk at {}", chunk_pos);
+        let generator = generator.clone();
+        let chunk_pos = part.pos;
+        part.task = Some(task_pool.spawn(async move {
+            GeneratedPart {
+                chunk: generate_chunk_blocks(generator.as_ref(), chunk_pos, air),
+                leaked: HashMap::new(),
+            }
+        }));
+        running_tasks += 1;
     }
 
-    for (chunk_pos, part) in request.get_for_pass_mut(&GenerationPass::WaitingForSunbeams) {
+    for chunk_pos in request.in_pass(GenerationPass::WaitingForSunbeams) {
         let above = chunk_pos + IVec3::Y * CHUNK_SIDE as i32;
         if universe.chunks.get(&above).is_some() {
             info!(
                 target: "terrain_generation",
                 "lighting for chunk {} resumed", chunk_pos,
             );
-            part.depends_on.retain(|pos| pos != &above);
-            part.pass = GenerationPass::Sunbeams;
+            request.update(chunk_pos, |part| {
+                part.depends_on.retain(|pos| pos != &above);
+                part.pass = GenerationPass::Sunbeams;
+            });
         }
     }
 
+    // The sun beams are shared by the chunks of a column, they are extended here
     let mut request_for_sunlight = vec![];
-    for _ in 0..10 {
-        let Some((chunk_pos, part)) = request.get_for_pass_mut(&GenerationPass::Sunbeams).next()
-        else {
-            break;
+    let mut next_passes = vec![];
+    for chunk_pos in request.in_pass(GenerationPass::Sunbeams) {
+        let Some(part) = request.requested.get_mut(&chunk_pos) else {
+            continue;
         };
-
-        if processed_blocks >= max_block_generation {
-            break;
+        if part.blocks_only {
+            continue;
         }
-        processed_blocks += CHUNK_VOLUME;
 
         info!("trying to light chunk at {}", chunk_pos);
 
@@ -614,8 +952,18 @@ pub fn chunk_generation(
             continue;
         };
 
-        let is_sun =
-            |IVec3 { x, y, z }: IVec3| chunk_pos.y + y >= get_sun_heightfield(IVec2::new(x, z));
+        // The top of the world of the new columns is set by the generator
+        let sun_heights: [i32; CHUNK_AREA] = std::array::from_fn(|i| {
+            let xz = chunk_pos.xz() + IVec2::new((i % CHUNK_SIDE) as i32, (i / CHUNK_SIDE) as i32);
+            sun_beams
+                .beams
+                .entry(xz)
+                .or_insert_with(|| SunBeam::new_top(generator.sky_height(xz)))
+                .top
+        });
+        let is_sun = |IVec3 { x, y, z }: IVec3| {
+            chunk_pos.y + y >= sun_heights[(x + z * CHUNK_SIDE as i32) as usize]
+        };
 
         // Todo: this can be cached and done in a separate `GenerationPass`.
         // Calculate sunbeams by raycasting up from the lowest blocks of the chunk.
@@ -663,7 +1011,7 @@ pub fn chunk_generation(
         if any_beam_escaped && chunk_above.is_none() {
             request_for_sunlight.push(above);
             part.depends_on.push(above);
-            part.pass = GenerationPass::WaitingForSunbeams;
+            next_passes.push((chunk_pos, GenerationPass::WaitingForSunbeams));
             info!(
                 target: "terrain_generation",
                 "lighting for chunk {} is waiting for the chunk above", chunk_pos,
@@ -683,8 +1031,7 @@ pub fn chunk_generation(
         {
             let plane_index = (x + z * CHUNK_SIDE as i32) as usize;
             let beam_pos = IVec2::new(x, z) + chunk_pos.xz();
-            let sun_beam =
-                chunk_pos.y + part.blocks_beams[plane_index] == get_sun_heightfield(beam_pos);
+            let sun_beam = chunk_pos.y + part.blocks_beams[plane_index] == sun_heights[plane_index];
             let escaped_beam = part.blocks_beams[plane_index] == CHUNK_SIDE as i32 - 1;
             let sun_light = if sun_beam {
                 MAX_LIGHT
@@ -709,203 +1056,294 @@ pub fn chunk_generation(
             }
         }
 
-        part.pass = GenerationPass::Biome;
+        next_passes.push((chunk_pos, GenerationPass::Biome));
+    }
+    for (chunk_pos, pass) in next_passes {
+        request.set_pass(chunk_pos, pass);
     }
 
     for chunk_pos in request_for_sunlight {
         request.insert_priority(chunk_pos, 0);
     }
 
-    for (chunk_pos, part) in request.get_for_pass_mut(&GenerationPass::Biome) {
-        let Some(chunk) = &part.chunk else {
+    for chunk_pos in request.in_pass(GenerationPass::Biome) {
+        let Some(part) = request.requested.get_mut(&chunk_pos) else {
+            continue;
+        };
+        if part.task.is_some() {
+            continue;
+        }
+        let Some(chunk) = part.chunk.take() else {
             error!("the chunk has no blocks in it");
             continue;
         };
 
-        if processed_blocks >= max_block_generation {
-            break;
+        // The biomes of a column are chosen by its first generated chunk
+        let region = *biomes.regions.entry(chunk_pos.xz()).or_insert_with(|| {
+            std::array::from_fn(|i| {
+                let inner = IVec2::new((i % CHUNK_SIDE) as i32, (i / CHUNK_SIDE) as i32);
+                generator.biome(chunk_pos.xz() + inner)
+            })
+        });
+        let beam_bottoms: [i32; CHUNK_AREA] = std::array::from_fn(|i| {
+            let inner = IVec2::new((i % CHUNK_SIDE) as i32, (i / CHUNK_SIDE) as i32);
+            sun_beams.get_at_mut(&(chunk_pos.xz() + inner)).bottom
+        });
+
+        let generator = generator.clone();
+        part.task = Some(task_pool.spawn(async move {
+            cover_surface(
+                generator.as_ref(),
+                chunk_pos,
+                &chunk,
+                &region,
+                &beam_bottoms,
+            );
+            GeneratedPart {
+                chunk,
+                leaked: HashMap::new(),
+            }
+        }));
+    }
+
+    // Features can reach into the neighbouring chunks, so each chunk places the features of all
+    // its neighbours in it. They are always placed in the same order so that they overlap the
+    // same way in every chunk.
+    let mut request_for_decoration = vec![];
+    let mut decorations = vec![];
+    for chunk_pos in request.in_pass(GenerationPass::Decoration) {
+        let neighbours = feature_sources(chunk_pos);
+
+        let mut features = vec![];
+        let mut missing = vec![];
+        for neighbour in neighbours.iter() {
+            let neighbour_features =
+                with_generated_blocks(&universe, &request, neighbour, |chunk| {
+                    // a few blocks are read, a compact neighbour isn't expanded for them
+                    generator.features(*neighbour, &|xyz| chunk.read_block(xyz))
+                });
+            match neighbour_features {
+                Some(mut neighbour_features) => features.append(&mut neighbour_features),
+                None => missing.push(*neighbour),
+            }
         }
-        processed_blocks += CHUNK_VOLUME;
 
-        // Add dirt to the 3 stone blocks under each sunbeam
-        let mut chunk_mut = chunk.get_mut();
-        for (x, z) in (0..CHUNK_SIDE as i32)
-            .map(|x| (0..CHUNK_SIDE as i32).map(move |z| (x, z)))
-            .flatten()
-        {
-            let xz = IVec2::new(x, z) + chunk_pos.xz();
-            let beam = sun_beams.get_at_mut(&xz);
-            if (chunk_pos.y..chunk_pos.y + CHUNK_SIDE as i32).contains(&(beam.bottom - 1)) {
-                for h in 0..3 {
-                    let xyz = IVec3::new(x, (beam.bottom - chunk_pos.y) - h - 1, z);
-                    if !Chunk::contains(&xyz) {
-                        continue;
-                    }
-                    let block = &mut chunk_mut[Chunk::xyz2idx(xyz)];
-                    if !block.properties.check(BlockFlag::Opaque) {
-                        break;
-                    }
-                    if h == 0 {
-                        *block = Block::new(bp.blocks.get_named("Grass"));
-                    } else {
-                        *block = Block::new(bp.blocks.get_named("Dirt"));
-                    }
+        let Some(part) = request.requested.get_mut(&chunk_pos) else {
+            continue;
+        };
+        if !missing.is_empty() {
+            for neighbour in missing {
+                if !part.depends_on.contains(&neighbour) {
+                    part.depends_on.push(neighbour);
                 }
+                request_for_decoration.push((neighbour, part.priority));
             }
+            continue;
         }
+        part.depends_on.retain(|pos| !neighbours.contains(pos));
+        decorations.push((chunk_pos, features));
+    }
 
-        part.pass = GenerationPass::Lighting;
+    for (chunk_pos, priority) in request_for_decoration {
+        request.insert_blocks_only(chunk_pos, priority);
     }
 
-    for (chunk_pos, part) in request.get_for_pass_mut(&GenerationPass::Lighting) {
-        let Some(chunk) = &part.chunk else {
+    // The light sources of the blueprints, for the lighting tasks
+    let light_levels: Arc<HashMap<BlockId, u8>> = Arc::new(
+        bp.blocks
+            .iter()
+            .filter(|block_bp| block_bp.is_light_source())
+            .map(|block_bp| (block_bp.id, block_bp.light_level))
+            .collect(),
+    );
+    for (chunk_pos, features) in decorations {
+        let Some(part) = request.requested.get_mut(&chunk_pos) else {
+            continue;
+        };
+        let Some(chunk) = part.chunk.take() else {
             error!("the chunk has no blocks in it");
             continue;
         };
 
-        if processed_blocks >= max_block_generation {
-            break;
-        }
-        processed_blocks += CHUNK_VOLUME;
+        let beams: [SunBeam; CHUNK_AREA] = std::array::from_fn(|i| {
+            let inner = IVec2::new((i % CHUNK_SIDE) as i32, (i / CHUNK_SIDE) as i32);
+            sun_beams.get_at_mut(&(chunk_pos.xz() + inner)).clone()
+        });
+        let light_levels = light_levels.clone();
+        part.task = Some(task_pool.spawn(async move {
+            place_features(chunk_pos, &mut chunk.get_mut(), &features);
+            let leaked = light_chunk(chunk_pos, &chunk, &beams, &light_levels);
+            // done changing, the chunk is kept compact once in the universe
+            chunk.compact();
+            GeneratedPart { chunk, leaked }
+        }));
+        request.set_pass(chunk_pos, GenerationPass::Lighting);
+    }
+
+    let mut generated = vec![];
+    for chunk_pos in request.in_pass(GenerationPass::Done) {
+        let Some(state) = request.requested.get_mut(&chunk_pos) else {
+            continue;
+        };
+        let chunk = state
+            .chunk
+            .take()
+            .expect("the generator should output a chunk");
+        generated.push((chunk_pos, Arc::new(chunk)));
+    }
+    if generated.is_empty() {
+        return;
+    }
 
-        let mut chunk_mut = chunk.get_mut();
-        for pos in Chunk::iter() {
-            let xyz = pos + chunk_pos;
+    // save the chunks as they are generated, they stay loaded even if they couldn't be saved
+    let batch = WriteBatch::with_chunks(db.palette.clone(), generated.clone(), &sun_beams, &biomes);
+    if let Err(err) = save_state.send(&db, batch) {
+        let message = format!("Failed to save the generated chunks: {}", err);
+        report_save_load_error(&mut errors, message);
+    }
 
-            let mut chunk_sources = HashMap::<LightType, Vec<LightSource>>::new();
+    for (chunk_pos, chunk) in generated {
+        universe.chunks.insert(chunk_pos, chunk);
+        info!(
+            target: "terrain_generation",
+            "chunk generated at {}", chunk_pos
+        );
+        request.remove(&chunk_pos);
+    }
+}
 
-            // Collect sun sources
-            if sun_beams.get_at_mut(&xyz.xz()).contains(&xyz.y) {
-                chunk_mut[Chunk::xyz2idx(pos)].set_light(LightType::Sun, MAX_LIGHT);
-                chunk_sources
-                    .entry(LightType::Sun)
-                    .or_default()
-                    .push(LightSource {
-                        pos,
-                        brightness: MAX_LIGHT,
-                    });
-            };
+/// What a generation task hands back to the main thread.
+pub struct GeneratedPart {
+    pub chunk: Chunk,
+    /// Light leaving the chunk, in world coordinates.
+    pub leaked: HashMap<LightType, Vec<LightSource>>,
+}
 
-            // Collect torch sources
-            let block = chunk_mut[Chunk::xyz2idx(pos)];
-            let block_bp = bp.blocks.get(&block.id);
-            if block_bp.is_light_source() {
-                let brightness = block_bp.light_level;
-                chunk_mut[Chunk::xyz2idx(pos)].set_light(LightType::Sun, brightness);
-                chunk_sources
-                    .entry(LightType::Torch)
-                    .or_default()
-                    .push(LightSource { pos, brightness });
-            }
+/// Calls `f` with the chunk at `chunk_pos` if all its blocks are generated,
+/// either in the universe or still being generated.
+fn with_generated_blocks<R>(
+    universe: &Universe,
+    request: &ChunkGenerationRequest,
+    chunk_pos: &IVec3,
+    f: impl FnOnce(&Chunk) -> R,
+) -> Option<R> {
+    if let Some(chunk) = universe.chunks.get(chunk_pos) {
+        return Some(f(&chunk));
+    }
+    let part = request.requested.get(chunk_pos)?;
+    if part.pass == GenerationPass::Blocks {
+        return None;
+    }
+    part.chunk.as_ref().map(f)
+}
 
-            // Do a first pass of lighting
-            for (lt, sources) in chunk_sources {
-                let mut leaked_from_chunk = propagate_light_chunk(
-                    &mut chunk_mut,
-                    sources.iter().map(|s| s.pos).collect(),
-                    lt,
-                );
-                for source in leaked_from_chunk.iter_mut() {
-                    source.pos += chunk_pos;
+/// The blocks of the chunk at `chunk_pos` with the caves cut in them.
+/// The tunnels cross the chunk borders, every chunk cuts the part that is inside it.
+pub fn generate_chunk_blocks(
+    generator: &dyn WorldGenerator,
+    chunk_pos: IVec3,
+    air: Block,
+) -> Chunk {
+    let chunk = Chunk::empty();
+    let mut chunk_mut = chunk.get_mut();
+    for i in 0..CHUNK_VOLUME {
+        chunk_mut[i] = generator.gen_block(chunk_pos + Chunk::idx2xyz(i));
+    }
+
+    let chunk_max = chunk_pos + IVec3::splat(CHUNK_SIDE as i32 - 1);
+    for carver in generator.carvers() {
+        for carving in carver.carvings(chunk_pos) {
+            let (min, max) = carving.bounds();
+            let (min, max) = (min.max(chunk_pos), max.min(chunk_max));
+            for x in min.x..=max.x {
+                for y in min.y..=max.y {
+                    for z in min.z..=max.z {
+                        let pos = IVec3::new(x, y, z);
+                        if !carving.contains(pos) {
+                            continue;
+                        }
+                        let block = &mut chunk_mut[Chunk::xyz2idx(pos - chunk_pos)];
+                        if block.properties.check(BlockFlag::Opaque) {
+                            *block = generator.carved_fluid(y).unwrap_or(air);
+                        }
+                    }
                 }
-                light_sources
-                    .leaked_sources
-                    .entry(lt)
-                    .or_default()
-                    .append(&mut leaked_from_chunk);
             }
         }
-        part.pass = GenerationPass::Done;
     }
+    drop(chunk_mut);
+    chunk
+}
 
-    db.write(|tx| {
-        let mut sun_table = tx.open_table(TABLE_SUN_BEAMS)?;
-        let mut block_table = tx.open_table(TABLE_BLOCKS)?;
-        let mut to_remove = vec![];
-        for (chunk_pos, state) in request.requested.iter_mut() {
-            match state.pass {
-                GenerationPass::Done => {
-                    let chunk = state
-                        .chunk
-                        .take()
-                        .expect("the generator should output a chunk");
-
-                    // save the chunks as they are generated
-                    write_chunk(tx, chunk_pos, &chunk, Some(&mut block_table))?;
-                    write_sun_beams_region(tx, chunk_pos.xz(), &sun_beams, Some(&mut sun_table))?;
-
-                    universe.chunks.insert(*chunk_pos, chunk);
-                    info!(
-                        target: "terrain_generation",
-                        "chunk generated at {}", chunk_pos
-                    );
-                    to_remove.push(*chunk_pos);
+/// Covers the stone blocks under each sun beam with the surface of their biome.
+/// `region` and `beam_bottoms` hold the biome and the bottom of the beam of each column.
+fn cover_surface(
+    generator: &dyn WorldGenerator,
+    chunk_pos: IVec3,
+    chunk: &Chunk,
+    region: &[Biome; CHUNK_AREA],
+    beam_bottoms: &[i32; CHUNK_AREA],
+) {
+    let mut chunk_mut = chunk.get_mut();
+    for (x, z) in (0..CHUNK_SIDE as i32)
+        .map(|x| (0..CHUNK_SIDE as i32).map(move |z| (x, z)))
+        .flatten()
+    {
+        let column = (x + z * CHUNK_SIDE as i32) as usize;
+        let surface = generator.surface(region[column]);
+        let bottom = beam_bottoms[column];
+        if (chunk_pos.y..chunk_pos.y + CHUNK_SIDE as i32).contains(&(bottom - 1)) {
+            for (h, surface_block) in surface.iter().enumerate() {
+                let xyz = IVec3::new(x, (bottom - chunk_pos.y) - h as i32 - 1, z);
+                if !Chunk::contains(&xyz) {
+                    continue;
+                }
+                let block = &mut chunk_mut[Chunk::xyz2idx(xyz)];
+                if !block.properties.check(BlockFlag::Opaque) {
+                    break;
                 }
-                _ => {}
+                *block = *surface_block;
             }
         }
-        for chunk_pos in to_remove.iter() {
-            request.requested.remove(chunk_pos);
-        }
-        Ok(())
-    })
-    .expect("db write failed");
+    }
 }
 
-pub struct GeneratorCrazyHill {
-    terrain_noise: noise::Exponent<f64, HybridMulti<noise::Perlin>, 2>,
-    sponge_noise: HybridMulti<noise::Perlin>,
-}
+/// First lighting of a chunk that isn't in the universe yet, with the sun beams of its columns
+/// and the light sources in it. Returns the light leaving the chunk, in world coordinates.
+fn light_chunk(
+    chunk_pos: IVec3,
+    chunk: &Chunk,
+    beams: &[SunBeam; CHUNK_AREA],
+    light_levels: &HashMap<BlockId, u8>,
+) -> HashMap<LightType, Vec<LightSource>> {
+    let mut chunk_mut = chunk.get_mut();
+    let mut sources = HashMap::<LightType, Vec<IVec3>>::new();
+    for pos in Chunk::iter() {
+        let block = &mut chunk_mut[Chunk::xyz2idx(pos)];
+
+        // Everything above the top of the world is in the sun
+        let beam = &beams[(pos.x + pos.z * CHUNK_SIDE as i32) as usize];
+        let y = chunk_pos.y + pos.y;
+        if beam.contains(&y) || y > beam.top {
+            block.set_light(LightType::Sun, MAX_LIGHT);
+            sources.entry(LightType::Sun).or_default().push(pos);
+        }
 
-impl GeneratorCrazyHill {
-    fn new(seed: u32) -> Self {
-        Self {
-            terrain_noise: noise::Exponent::new(
-                HybridMulti::<noise::Perlin>::default()
-                    .set_frequency(0.001)
-                    .set_octaves(4)
-                    .set_seed(seed),
-            ),
-            sponge_noise: HybridMulti::<noise::Perlin>::default()
-                .set_frequency(0.003)
-                .set_octaves(5)
-                .set_persistence(0.5)
-                .set_seed(seed),
-        }
-    }
-
-    fn gen_block(&self, pos: IVec3, bp: &Blueprints) -> Block {
-        // create an envelope of 3d noise in -192..192
-        // squish that envelope in the y direction using a 2d perlin noise
-
-        let dpos = pos.as_dvec3();
-
-        let block;
-        let caves: f64 = -128.0;
-        let sky: f64 = 128.0;
-        let mid = (sky + caves) * 0.5;
-        let amp = (sky - caves).abs() * 0.5;
-        if dpos.y > sky {
-            block = bp.blocks.get_named("Air");
-        } else if dpos.y < caves {
-            block = bp.blocks.get_named("Stone");
-        } else {
-            let flatness = self.terrain_noise.get(dpos.xz().to_array());
-            let flatness_norm = flatness * 0.5 + 0.5;
-            if dpos.y < mid - amp * flatness_norm {
-                block = bp.blocks.get_named("Stone");
-            } else if dpos.y > mid + amp * flatness_norm {
-                block = bp.blocks.get_named("Air");
-            } else {
-                let sample = self.sponge_noise.get(dpos.to_array());
-                if sample > 1.0 - flatness_norm {
-                    block = bp.blocks.get_named("Stone");
-                } else {
-                    block = bp.blocks.get_named("Air");
-                }
-            }
+        if let Some(brightness) = light_levels.get(&block.id) {
+            block.set_light(LightType::Torch, *brightness);
+            sources.entry(LightType::Torch).or_default().push(pos);
         }
+    }
 
-        Block::new(block)
+    let mut leaked = HashMap::new();
+    for (light_type, sources) in sources {
+        // the chunk isn't in the universe yet, so its changes aren't recorded
+        let mut leaked_from_chunk =
+            propagate_light_chunk(&mut chunk_mut, sources, light_type, &mut vec![]);
+        for source in leaked_from_chunk.iter_mut() {
+            source.pos += chunk_pos;
+        }
+        leaked.insert(light_type, leaked_from_chunk);
     }
+    leaked
 }
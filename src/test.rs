@@ -0,0 +1,470 @@
+use std::{collections::HashMap, sync::Arc};
+
+use bevy::math::{IVec3, Quat, Vec3};
+use mcrs_universe::{
+    block::{Block, BlockBlueprint, BlockId, LightType},
+    chunk::Chunk,
+    palette::LevelPalette,
+    BlueprintError, BlueprintList, Blueprints, CHUNK_SIDE, CHUNK_VOLUME, PACKS_PATH,
+};
+use miniz_oxide::deflate::compress_to_vec;
+use redb::{backends::InMemoryBackend, Database};
+use serde::Serialize;
+
+use crate::{
+    generator::{
+        feature_sources, place_features, Feature, GeneratorConfig, GeneratorError,
+        GeneratorRegistry, GeneratorSuperflat, WorldGenerator, DEFAULT_SKY_HEIGHT,
+    },
+    level_writer::WriteBatch,
+    migration::{migrate, read_format_version, write_format_version},
+    saveload::{
+        quarantine_chunk, read_block_entities, read_block_names, read_chunk, read_level,
+        read_player, write_chunk, write_player, Db, SaveLoadError, SerdePlayer,
+        LEVEL_FORMAT_VERSION, TABLE_BLOCKS, TABLE_BLOCK_ENTITIES, TABLE_LEVEL,
+        TABLE_QUARANTINED_BLOCKS,
+    },
+    terrain::generate_chunk_blocks,
+};
+
+fn memory_db() -> Db {
+    let database = Database::builder()
+        .create_with_backend(InMemoryBackend::new())
+        .unwrap();
+    let mut db = Db::new(database);
+    let blueprint = |name: &str, id: u16| BlockBlueprint {
+        name: format!("base:{}", name),
+        id: id.into(),
+        ..Default::default()
+    };
+    db.palette = LevelPalette::new(
+        vec![],
+        &BlueprintList::from_list(vec![blueprint("Air", 0), blueprint("Stone", 1)]),
+    );
+    db
+}
+
+fn fixture_player() -> SerdePlayer {
+    SerdePlayer {
+        name: "steve".to_string(),
+        translation: Vec3::new(1.5, 20.0, -3.5),
+        body_rotation: Quat::IDENTITY,
+        camera_rotation: Quat::IDENTITY,
+    }
+}
+
+/// The level info of version 0.
+#[derive(Serialize)]
+struct LevelV0 {
+    name: String,
+    seed: u32,
+}
+
+/// A block of version 0: id, flags, torch light and sun light, a byte each.
+type BlockV0 = [u8; 4];
+
+/// A level written before the format version was stored, as the game then wrote it:
+/// the blocks are raw bytes, and there are no block names and no `version` row.
+/// Its ids are those of the blocks then, stone is 1 and glowstone is 5.
+fn fixture_v0(db: &Db) {
+    let mut blocks: Vec<BlockV0> = vec![[0, 0, 0, 15]; CHUNK_VOLUME];
+    blocks[Chunk::xyz2idx(IVec3::new(1, 2, 3))] = [1, 0b11, 0, 0];
+    blocks[Chunk::xyz2idx(IVec3::new(4, 5, 6))] = [5, 0b1, 15, 9];
+    let info = LevelV0 {
+        name: "old".to_string(),
+        seed: 42,
+    };
+    db.write(|tx| {
+        let mut table = tx.open_table(TABLE_LEVEL)?;
+        table.insert("info", &*bincode::serialize(&info).unwrap())?;
+        let mut table = tx.open_table(TABLE_BLOCKS)?;
+        let block_bytes: &[u8] = bytemuck::cast_slice(&blocks);
+        table.insert(IVec3::ZERO.to_array(), &*compress_to_vec(block_bytes, 6))?;
+        // cut short, it can't be migrated
+        table.insert(
+            (IVec3::X * 32).to_array(),
+            &*compress_to_vec(&block_bytes[..8], 6),
+        )?;
+        drop(table);
+        write_player(tx, &fixture_player(), None)
+    })
+    .unwrap();
+}
+
+#[test]
+fn migrate_level_v0() {
+    let db = memory_db();
+    fixture_v0(&db);
+    assert_eq!(db.read(read_format_version).unwrap(), Some(0));
+
+    assert_eq!(migrate(&db).unwrap(), Some(0));
+    assert_eq!(
+        db.read(read_format_version).unwrap(),
+        Some(LEVEL_FORMAT_VERSION)
+    );
+
+    let level = db.read(read_level).unwrap().unwrap();
+    assert_eq!(level.name, "old");
+    assert_eq!(level.seed, 42);
+    assert_eq!(level.generator, GeneratorConfig::default());
+    assert_eq!(level.spawn_point.y, DEFAULT_SKY_HEIGHT as f32);
+
+    // the blocks are read back by name, whatever their ids now
+    let blueprint = |name: &str, id: u16| BlockBlueprint {
+        name: format!("base:{}", name),
+        id: id.into(),
+        ..Default::default()
+    };
+    let blueprints = BlueprintList::from_list(vec![
+        blueprint("Air", 0),
+        blueprint("Glowstone", 1),
+        blueprint("Stone", 2),
+    ]);
+    let names = db.read(read_block_names).unwrap().unwrap();
+    let palette = LevelPalette::new(names, &blueprints);
+    let chunk = db
+        .read(|tx| read_chunk(tx, &palette, &IVec3::ZERO))
+        .unwrap()
+        .unwrap();
+    let blocks = chunk.get_ref();
+    let stone = blocks[Chunk::xyz2idx(IVec3::new(1, 2, 3))];
+    assert_eq!(stone.id, BlockId::from(2));
+    let glowstone = blocks[Chunk::xyz2idx(IVec3::new(4, 5, 6))];
+    assert_eq!(glowstone.id, BlockId::from(1));
+    assert_eq!(glowstone.get_light(LightType::Torch), 15);
+    assert_eq!(glowstone.get_light(LightType::Sun), 9);
+    let air = blocks[Chunk::xyz2idx(IVec3::ZERO)];
+    assert_eq!(air.id, BlockId::from(0));
+    assert_eq!(air.get_light(LightType::Sun), 15);
+    drop(blocks);
+
+    // the chunk that couldn't be migrated is generated again
+    let cut_short = IVec3::X * 32;
+    assert!(db
+        .read(|tx| read_chunk(tx, &palette, &cut_short))
+        .unwrap()
+        .is_none());
+    let player = db.read(|tx| read_player(tx, "steve")).unwrap().unwrap();
+    assert_eq!(player.translation, fixture_player().translation);
+
+    // a migrated level is up to date
+    assert_eq!(migrate(&db).unwrap(), None);
+}
+
+#[test]
+fn new_level_is_not_migrated() {
+    let db = memory_db();
+    assert_eq!(db.read(read_format_version).unwrap(), None);
+    assert_eq!(migrate(&db).unwrap(), None);
+}
+
+#[test]
+fn newer_level_is_refused() {
+    let db = memory_db();
+    fixture_v0(&db);
+    db.write(|tx| write_format_version(tx, LEVEL_FORMAT_VERSION + 1))
+        .unwrap();
+    assert!(matches!(
+        migrate(&db),
+        Err(SaveLoadError::NewerFormat(version)) if version == LEVEL_FORMAT_VERSION + 1
+    ));
+    // the level is left as it was
+    assert_eq!(
+        db.read(read_format_version).unwrap(),
+        Some(LEVEL_FORMAT_VERSION + 1)
+    );
+}
+
+#[test]
+fn corrupt_chunk_is_quarantined() {
+    let db = memory_db();
+    let pos = IVec3::new(32, -64, 0);
+    let garbage = [0xde, 0xad, 0xbe, 0xef];
+    db.write(|tx| {
+        let mut table = tx.open_table(TABLE_BLOCKS)?;
+        table.insert(pos.to_array(), &garbage[..])?;
+        Ok(())
+    })
+    .unwrap();
+
+    assert!(db.read(|tx| read_chunk(tx, &db.palette, &pos)).is_err());
+
+    db.write(|tx| quarantine_chunk(tx, &pos)).unwrap();
+    assert!(db
+        .read(|tx| read_chunk(tx, &db.palette, &pos))
+        .unwrap()
+        .is_none());
+    let quarantined = db
+        .read(|tx| {
+            let table = tx.open_table(TABLE_QUARANTINED_BLOCKS)?;
+            let value = table.get(pos.to_array())?;
+            Ok(value.map(|value| value.value().to_vec()))
+        })
+        .unwrap();
+    assert_eq!(quarantined, Some(garbage.to_vec()));
+}
+
+#[test]
+fn corrupt_block_entities_keep_the_blocks() {
+    let db = memory_db();
+    let pos = IVec3::new(0, 0, 32);
+    let chunk = Chunk::empty();
+    chunk.get_mut()[Chunk::xyz2idx(IVec3::new(1, 2, 3))].id = BlockId::from(1);
+    db.write(|tx| {
+        write_chunk(tx, &db.palette, &pos, &chunk, None)?;
+        let mut table = tx.open_table(TABLE_BLOCK_ENTITIES)?;
+        table.insert(pos.to_array(), &[0xde, 0xad, 0xbe, 0xef][..])?;
+        Ok(())
+    })
+    .unwrap();
+
+    let err = db.read(|tx| read_block_entities(tx, &pos)).unwrap_err();
+    assert!(err.is_corrupt());
+    let chunk = db
+        .read(|tx| read_chunk(tx, &db.palette, &pos))
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        chunk.get_ref()[Chunk::xyz2idx(IVec3::new(1, 2, 3))].id,
+        BlockId::from(1)
+    );
+}
+
+#[test]
+fn writer_thread_writes_batches() {
+    let db = memory_db();
+    let chunk = Chunk::empty();
+    chunk.get_mut()[Chunk::xyz2idx(IVec3::new(4, 5, 6))].id = BlockId::from(1);
+    let pos = IVec3::new(0, 32, 0);
+    let batch = WriteBatch {
+        palette: db.palette.clone(),
+        players: vec![fixture_player()],
+        chunks: vec![(pos, Arc::new(chunk))],
+        ends_save: true,
+        ..Default::default()
+    };
+    let id = db.writer.send(batch).unwrap();
+
+    // waits for the batch to be committed
+    db.writer.finish();
+    let reports = db.writer.reports();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].id, id);
+    assert_eq!(reports[0].chunks, vec![pos]);
+    assert!(reports[0].ends_save);
+    assert_eq!(reports[0].error, None);
+
+    let chunk = db
+        .read(|tx| read_chunk(tx, &db.palette, &pos))
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        chunk.get_ref()[Chunk::xyz2idx(IVec3::new(4, 5, 6))].id,
+        BlockId::from(1)
+    );
+    let player = db.read(|tx| read_player(tx, "steve")).unwrap().unwrap();
+    assert_eq!(player.translation, fixture_player().translation);
+
+    // nothing is written once the writer is finished
+    assert!(matches!(
+        db.writer.send(WriteBatch::default()),
+        Err(SaveLoadError::WriterStopped)
+    ));
+}
+
+fn superflat_blueprints() -> Blueprints {
+    let blueprint = |name: &str, id: u16| BlockBlueprint {
+        name: format!("base:{}", name),
+        id: id.into(),
+        ..Default::default()
+    };
+    Blueprints::new(
+        BlueprintList::from_list(vec![
+            blueprint("Air", 0),
+            blueprint("Stone", 1),
+            blueprint("Dirt", 2),
+            blueprint("Grass", 3),
+        ]),
+        BlueprintList::from_list(vec![]),
+    )
+}
+
+#[test]
+fn superflat_presets() {
+    let bp = superflat_blueprints();
+    let column = |preset: &str| {
+        let generator = GeneratorSuperflat::new(preset, &bp).unwrap();
+        (-5..=1)
+            .rev()
+            .map(|y| *generator.gen_block(IVec3::new(7, y, -3)).id)
+            .collect::<Vec<u16>>()
+    };
+    // from y = 1 down to y = -5
+    let expected = vec![0, 3, 2, 2, 2, 1, 0];
+    assert_eq!(column("Stone,3*Dirt,Grass"), expected);
+    assert_eq!(column(" stone , 3 * DIRT,grass "), expected);
+    assert_eq!(column("base:Stone,3*base:Dirt,base:Grass"), expected);
+    // the default preset
+    assert_eq!(column(""), expected);
+    assert_eq!(column("2*Stone"), vec![0, 1, 1, 0, 0, 0, 0]);
+}
+
+#[test]
+fn superflat_invalid_presets() {
+    let bp = superflat_blueprints();
+    let invalid = |preset: &str| GeneratorSuperflat::new(preset, &bp).err().unwrap();
+    for preset in ["0*Dirt", "-2*Dirt", "two*Dirt", "*Dirt", "Stone,1.5*Dirt"] {
+        assert!(
+            matches!(invalid(preset), GeneratorError::Params { message, .. }
+                if message.starts_with("bad layer thickness")),
+            "{}",
+            preset
+        );
+    }
+    for preset in ["Stone,,Grass", "3*", "Stone,"] {
+        assert!(
+            matches!(invalid(preset), GeneratorError::Params { message, .. }
+                if message.starts_with("missing block name")),
+            "{}",
+            preset
+        );
+    }
+    assert_eq!(
+        invalid("bedrock,2*dirt,grass"),
+        GeneratorError::Blueprint(BlueprintError::UnknownName("base:bedrock".to_string()))
+    );
+}
+
+/// Decorates chunks like the decoration pass, with the default generator and the base pack.
+struct Decoration {
+    generator: Box<dyn WorldGenerator>,
+    air: Block,
+    /// The blocks of the chunks before any feature is placed in them.
+    generated: HashMap<IVec3, Vec<Block>>,
+}
+
+impl Decoration {
+    fn new(seed: u32) -> Self {
+        let bp = Blueprints::load(PACKS_PATH).unwrap();
+        let generator = GeneratorRegistry::default()
+            .build(&GeneratorConfig::default(), seed, &bp)
+            .unwrap();
+        Self {
+            generator,
+            air: Block::new(bp.air()),
+            generated: HashMap::new(),
+        }
+    }
+
+    fn generated(&mut self, chunk_pos: IVec3) -> &Vec<Block> {
+        self.generated.entry(chunk_pos).or_insert_with(|| {
+            generate_chunk_blocks(self.generator.as_ref(), chunk_pos, self.air)
+                .get_ref()
+                .to_vec()
+        })
+    }
+
+    /// The features of the chunk at `chunk_pos`, read from its decorated blocks if it has some.
+    fn features(
+        &mut self,
+        chunk_pos: IVec3,
+        decorated: &HashMap<IVec3, Vec<Block>>,
+    ) -> Vec<Feature> {
+        let blocks = match decorated.get(&chunk_pos) {
+            Some(blocks) => blocks.clone(),
+            None => self.generated(chunk_pos).clone(),
+        };
+        self.generator
+            .features(chunk_pos, &|xyz| blocks[Chunk::xyz2idx(xyz)])
+    }
+
+    /// The blocks of the chunk at `chunk_pos` once decorated, the neighbours in `decorated`
+    /// were decorated before it.
+    fn decorate(&mut self, chunk_pos: IVec3, decorated: &HashMap<IVec3, Vec<Block>>) -> Vec<Block> {
+        let mut features = vec![];
+        for source in feature_sources(chunk_pos) {
+            features.append(&mut self.features(source, decorated));
+        }
+        let mut blocks = self.generated(chunk_pos).clone();
+        place_features(
+            chunk_pos,
+            blocks.as_mut_slice().try_into().unwrap(),
+            &features,
+        );
+        blocks
+    }
+
+    /// A tree that reaches out of its chunk, with its chunk and the neighbour it reaches into.
+    fn find_spilling_tree(&mut self) -> (Feature, IVec3, IVec3) {
+        let side = CHUNK_SIDE as i32;
+        for x in 0..16 {
+            for z in 0..16 {
+                for y in -4..8 {
+                    let chunk_pos = IVec3::new(x, y, z) * side;
+                    // the features are looked for in the blocks without the caves first,
+                    // only the columns of the plants are generated then
+                    let generator = self.generator.as_ref();
+                    let cheap_features =
+                        generator.features(chunk_pos, &|xyz| generator.gen_block(chunk_pos + xyz));
+                    for tree in cheap_features {
+                        // the cactuses are at most 3 blocks high and the ore veins replace stone
+                        if tree.replaces.is_some() || tree.blocks.len() <= 3 {
+                            continue;
+                        }
+                        let Some((outside, _)) = tree
+                            .blocks
+                            .iter()
+                            .find(|(pos, _)| !Chunk::contains(&(*pos - chunk_pos)))
+                        else {
+                            continue;
+                        };
+                        if !self.features(chunk_pos, &HashMap::new()).contains(&tree) {
+                            continue;
+                        }
+                        let neighbour = chunk_pos
+                            + (*outside - chunk_pos).div_euclid(IVec3::splat(side)) * side;
+                        return (tree, chunk_pos, neighbour);
+                    }
+                }
+            }
+        }
+        panic!("no tree reaches out of its chunk");
+    }
+}
+
+#[test]
+fn decoration_is_the_same_for_a_seed() {
+    let mut decoration = Decoration::new(7);
+    let (_, chunk_pos, _) = decoration.find_spilling_tree();
+    let decorated = decoration.decorate(chunk_pos, &HashMap::new());
+    assert_ne!(&decorated, decoration.generated(chunk_pos));
+
+    // a generator built again from the seed places the same features
+    let mut again = Decoration::new(7);
+    assert_eq!(again.decorate(chunk_pos, &HashMap::new()), decorated);
+}
+
+#[test]
+fn spilling_tree_is_the_same_whichever_chunk_is_decorated_first() {
+    let mut decoration = Decoration::new(7);
+    let (tree, chunk_pos, neighbour) = decoration.find_spilling_tree();
+
+    let tree_chunk_first = decoration.decorate(chunk_pos, &HashMap::new());
+    let neighbour_second = decoration.decorate(
+        neighbour,
+        &HashMap::from([(chunk_pos, tree_chunk_first.clone())]),
+    );
+    let neighbour_first = decoration.decorate(neighbour, &HashMap::new());
+    let tree_chunk_second = decoration.decorate(
+        chunk_pos,
+        &HashMap::from([(neighbour, neighbour_first.clone())]),
+    );
+    assert_eq!(tree_chunk_first, tree_chunk_second);
+    assert_eq!(neighbour_first, neighbour_second);
+
+    // some of the tree is in the neighbour
+    assert!(tree.blocks.iter().any(|(pos, block)| {
+        let xyz = *pos - neighbour;
+        Chunk::contains(&xyz) && neighbour_first[Chunk::xyz2idx(xyz)] == *block
+    }));
+}
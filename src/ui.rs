@@ -88,7 +88,9 @@ pub fn hotbar_interaction(
         }
 
         if let Ok(mut image_node) = image_query.get_mut(children[0]) {
-            let bl = bp.blocks.get(&hotbar_slot.block_id.unwrap());
+            let Ok(bl) = bp.blocks.get(&hotbar_slot.block_id.unwrap()) else {
+                continue;
+            };
             let (x, y) = match bl.block_texture_offset.as_ref().unwrap() {
                 BlockFace::Same((x, y)) => (x, y),
                 BlockFace::Cube { left: (x, y), .. } => (x, y),